@@ -0,0 +1,34 @@
+use crate::packet::ethernet::MacAddr;
+use pcap::{Activated, Capture};
+use std::net::IpAddr;
+
+/// Build the kernel-side capture filter from `-f` and the `-m`/`-p` shorthands
+///
+/// Every part has to match, so the shorthands narrow down whatever the
+/// user's own expression already selects.
+pub fn expression(filter: Option<&str>, mac: Option<&str>, ip: Option<&str>) -> Option<String> {
+    let mut parts: Vec<String> = Vec::new();
+    if let Some(filter) = filter {
+        parts.push(format!("({})", filter));
+    }
+    if let Some(mac) = mac {
+        let mac = MacAddr::from(String::from(mac));
+        parts.push(format!("ether host {}", mac));
+    }
+    if let Some(ip) = ip {
+        let ip: IpAddr = ip.parse().expect("IP Filter must be an IPv4 or IPv6 address");
+        parts.push(format!("host {}", ip));
+    }
+    match parts.len() {
+        0 => None,
+        _ => Some(parts.join(" and ")),
+    }
+}
+
+/// Compile the filter and attach it to the capture so the kernel drops
+/// everything else before it is copied to us
+pub fn apply(capture: &mut Capture<dyn Activated>, expression: &str) {
+    if let Err(e) = capture.filter(expression, true) {
+        panic!("Invalid capture filter \"{}\": {}", expression, e);
+    }
+}
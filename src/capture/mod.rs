@@ -0,0 +1,3 @@
+pub(crate) mod bpf;
+pub(crate) mod savefile;
+pub(crate) mod source;
@@ -0,0 +1,456 @@
+use byteorder::{LittleEndian, WriteBytesExt};
+use clap::ArgMatches;
+use pcap::PacketHeader;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use super::source;
+
+const SNAPLEN: u32 = 262144;
+
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+const PCAPNG_SECTION_HEADER: u32 = 0x0A0D0D0A;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 0x00000001;
+const PCAPNG_ENHANCED_PACKET: u32 = 0x00000006;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+const PCAPNG_OPT_ENDOFOPT: u16 = 0;
+const PCAPNG_OPT_COMMENT: u16 = 1;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Pcap,
+    Pcapng,
+}
+
+impl Format {
+    /// Pick the file format from the extension, pcapng only when asked for
+    fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("pcapng") => Format::Pcapng,
+            _ => Format::Pcap,
+        }
+    }
+}
+
+/// Limits after which the current file is closed and a new one started
+#[derive(Default)]
+pub struct Rotation {
+    pub max_bytes: Option<u64>,
+    pub max_seconds: Option<f64>,
+    pub max_packets: Option<u64>,
+}
+
+impl Rotation {
+    fn enabled(&self) -> bool {
+        self.max_bytes.is_some() || self.max_seconds.is_some() || self.max_packets.is_some()
+    }
+}
+
+/// Writes raw frames to a pcap or pcapng file, rotating between files when
+/// one of the [`Rotation`] limits is reached
+pub struct Savefile {
+    path: PathBuf,
+    format: Format,
+    linktype: u16,
+    rotation: Rotation,
+    out: BufWriter<File>,
+    index: u32,
+    bytes: u64,
+    packets: u64,
+    opened_at: Option<f64>,
+}
+
+impl Savefile {
+    pub fn create(path: &Path, linktype: u16, rotation: Rotation) -> io::Result<Savefile> {
+        let format = Format::from_path(path);
+        let first = if rotation.enabled() {
+            numbered_path(path, 0)
+        } else {
+            path.to_path_buf()
+        };
+        let mut savefile = Savefile {
+            path: path.to_path_buf(),
+            format,
+            linktype,
+            rotation,
+            out: BufWriter::new(File::create(&first)?),
+            index: 0,
+            bytes: 0,
+            packets: 0,
+            opened_at: None,
+        };
+        savefile.write_file_header()?;
+        Ok(savefile)
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Append one frame, with an optional comment (only kept by pcapng)
+    pub fn write(&mut self, header: &PacketHeader, data: &[u8], comment: Option<&str>) -> io::Result<()> {
+        let time = source::timestamp(header);
+        if self.should_rotate(time) {
+            self.rotate()?;
+        }
+        if self.opened_at.is_none() {
+            self.opened_at = Some(time);
+        }
+
+        let ts_sec = header.ts.tv_sec as u64;
+        let ts_usec = header.ts.tv_usec as u64;
+        let caplen = data.len() as u32;
+        let written = match self.format {
+            Format::Pcap => {
+                self.out.write_u32::<LittleEndian>(ts_sec as u32)?;
+                self.out.write_u32::<LittleEndian>(ts_usec as u32)?;
+                self.out.write_u32::<LittleEndian>(caplen)?;
+                self.out.write_u32::<LittleEndian>(header.len)?;
+                self.out.write_all(data)?;
+                16 + data.len() as u64
+            }
+            Format::Pcapng => {
+                // Option lengths are 16 bit, a longer comment is cut short
+                let comment = comment
+                    .map(|c| truncated(c, u16::MAX as usize).as_bytes())
+                    .unwrap_or(&[]);
+                let mut block_len = 32 + padded(data.len());
+                if !comment.is_empty() {
+                    // opt_comment followed by opt_endofopt
+                    block_len += 4 + padded(comment.len()) + 4;
+                }
+                let ts = ts_sec * 1_000_000 + ts_usec;
+                self.out.write_u32::<LittleEndian>(PCAPNG_ENHANCED_PACKET)?;
+                self.out.write_u32::<LittleEndian>(block_len as u32)?;
+                self.out.write_u32::<LittleEndian>(0)?;
+                self.out.write_u32::<LittleEndian>((ts >> 32) as u32)?;
+                self.out.write_u32::<LittleEndian>(ts as u32)?;
+                self.out.write_u32::<LittleEndian>(caplen)?;
+                self.out.write_u32::<LittleEndian>(header.len)?;
+                self.write_padded(data)?;
+                if !comment.is_empty() {
+                    self.out.write_u16::<LittleEndian>(PCAPNG_OPT_COMMENT)?;
+                    self.out.write_u16::<LittleEndian>(comment.len() as u16)?;
+                    self.write_padded(comment)?;
+                    self.out.write_u16::<LittleEndian>(PCAPNG_OPT_ENDOFOPT)?;
+                    self.out.write_u16::<LittleEndian>(0)?;
+                }
+                self.out.write_u32::<LittleEndian>(block_len as u32)?;
+                block_len as u64
+            }
+        };
+        // Evidence should survive the capture being interrupted
+        self.out.flush()?;
+
+        self.bytes += written;
+        self.packets += 1;
+        Ok(())
+    }
+
+    fn should_rotate(&self, time: f64) -> bool {
+        if self.packets == 0 {
+            return false;
+        }
+        if let Some(max) = self.rotation.max_bytes {
+            if self.bytes >= max {
+                return true;
+            }
+        }
+        if let Some(max) = self.rotation.max_packets {
+            if self.packets >= max {
+                return true;
+            }
+        }
+        if let (Some(max), Some(opened_at)) = (self.rotation.max_seconds, self.opened_at) {
+            if time - opened_at >= max {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.out.flush()?;
+        self.index += 1;
+        self.out = BufWriter::new(File::create(numbered_path(&self.path, self.index))?);
+        self.bytes = 0;
+        self.packets = 0;
+        self.opened_at = None;
+        self.write_file_header()
+    }
+
+    fn write_file_header(&mut self) -> io::Result<()> {
+        match self.format {
+            Format::Pcap => {
+                self.out.write_u32::<LittleEndian>(PCAP_MAGIC)?;
+                self.out.write_u16::<LittleEndian>(2)?;
+                self.out.write_u16::<LittleEndian>(4)?;
+                self.out.write_i32::<LittleEndian>(0)?;
+                self.out.write_u32::<LittleEndian>(0)?;
+                self.out.write_u32::<LittleEndian>(SNAPLEN)?;
+                self.out.write_u32::<LittleEndian>(self.linktype as u32)?;
+                self.bytes += 24;
+            }
+            Format::Pcapng => {
+                self.out.write_u32::<LittleEndian>(PCAPNG_SECTION_HEADER)?;
+                self.out.write_u32::<LittleEndian>(28)?;
+                self.out.write_u32::<LittleEndian>(PCAPNG_BYTE_ORDER_MAGIC)?;
+                self.out.write_u16::<LittleEndian>(1)?;
+                self.out.write_u16::<LittleEndian>(0)?;
+                // Section length unknown
+                self.out.write_i64::<LittleEndian>(-1)?;
+                self.out.write_u32::<LittleEndian>(28)?;
+
+                // Microsecond timestamps are the default resolution, no options needed
+                self.out.write_u32::<LittleEndian>(PCAPNG_INTERFACE_DESCRIPTION)?;
+                self.out.write_u32::<LittleEndian>(20)?;
+                self.out.write_u16::<LittleEndian>(self.linktype)?;
+                self.out.write_u16::<LittleEndian>(0)?;
+                self.out.write_u32::<LittleEndian>(SNAPLEN)?;
+                self.out.write_u32::<LittleEndian>(20)?;
+                self.bytes += 48;
+            }
+        }
+        self.out.flush()
+    }
+
+    fn write_padded(&mut self, data: &[u8]) -> io::Result<()> {
+        self.out.write_all(data)?;
+        let pad = padded(data.len()) - data.len();
+        self.out.write_all(&[0u8; 3][..pad])
+    }
+}
+
+/// Round up to the 32-bit boundary pcapng blocks and options are aligned on
+fn padded(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// The longest start of `text` within `max` bytes, cut between characters
+fn truncated(text: &str, max: usize) -> &str {
+    let mut end = text.len().min(max);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// `capture.pcap` becomes `capture_0003.pcap` for the fourth rotated file
+fn numbered_path(path: &Path, index: u32) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}_{:04}.{}", stem, index, ext.to_string_lossy()),
+        None => format!("{}_{:04}", stem, index),
+    };
+    path.with_file_name(name)
+}
+
+/// Open the `-w` output file with the rotation limits given on the command line
+pub fn open(matches: &ArgMatches, linktype: u16) -> Option<Savefile> {
+    let path = matches.value_of("write")?;
+    let rotation = Rotation {
+        max_bytes: matches
+            .value_of("rotate-size")
+            .map(|mb| mb.parse::<u64>().expect("Rotation size must be a whole number of MB") * 1_000_000),
+        max_seconds: matches
+            .value_of("rotate-seconds")
+            .map(|secs| secs.parse().expect("Rotation time must be a number of seconds")),
+        max_packets: matches
+            .value_of("rotate-count")
+            .map(|count| count.parse().expect("Rotation count must be a whole number of packets")),
+    };
+    Some(Savefile::create(Path::new(path), linktype, rotation).expect("Couldn't create capture output file"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::{ByteOrder, LittleEndian};
+
+    /// A fresh directory for one test's files
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rusty_blue_{}_{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn header(seconds: i64, micros: i64, len: usize) -> PacketHeader {
+        PacketHeader {
+            ts: libc::timeval {
+                tv_sec: seconds as libc::time_t,
+                tv_usec: micros as libc::suseconds_t,
+            },
+            caplen: len as u32,
+            len: len as u32,
+        }
+    }
+
+    /// The (seconds, microseconds, data) of every record in a pcap file
+    fn read_pcap(path: &Path) -> Vec<(u32, u32, Vec<u8>)> {
+        let file = std::fs::read(path).unwrap();
+        assert_eq!(LittleEndian::read_u32(&file[0..4]), PCAP_MAGIC);
+        assert_eq!(LittleEndian::read_u32(&file[20..24]), 1);
+        let mut records = Vec::new();
+        let mut rest = &file[24..];
+        while !rest.is_empty() {
+            let caplen = LittleEndian::read_u32(&rest[8..12]) as usize;
+            assert_eq!(LittleEndian::read_u32(&rest[12..16]) as usize, caplen);
+            records.push((
+                LittleEndian::read_u32(&rest[0..4]),
+                LittleEndian::read_u32(&rest[4..8]),
+                rest[16..16 + caplen].to_vec(),
+            ));
+            rest = &rest[16 + caplen..];
+        }
+        records
+    }
+
+    /// The (microsecond timestamp, data, comment) of every enhanced packet
+    /// block in a pcapng file, checking each block is framed by its length
+    fn read_pcapng(path: &Path) -> Vec<(u64, Vec<u8>, Option<String>)> {
+        let file = std::fs::read(path).unwrap();
+        let mut packets = Vec::new();
+        let mut rest = &file[..];
+        while !rest.is_empty() {
+            let kind = LittleEndian::read_u32(&rest[0..4]);
+            let len = LittleEndian::read_u32(&rest[4..8]) as usize;
+            assert_eq!(len % 4, 0);
+            assert_eq!(LittleEndian::read_u32(&rest[len - 4..len]) as usize, len);
+            let block = &rest[8..len - 4];
+            if kind == PCAPNG_ENHANCED_PACKET {
+                let ts = (LittleEndian::read_u32(&block[4..8]) as u64) << 32
+                    | LittleEndian::read_u32(&block[8..12]) as u64;
+                let caplen = LittleEndian::read_u32(&block[12..16]) as usize;
+                let options = &block[20 + padded(caplen)..];
+                let comment = match options.is_empty() {
+                    true => None,
+                    false => {
+                        assert_eq!(LittleEndian::read_u16(&options[0..2]), PCAPNG_OPT_COMMENT);
+                        let comment_len = LittleEndian::read_u16(&options[2..4]) as usize;
+                        assert_eq!(options.len(), 4 + padded(comment_len) + 4);
+                        Some(String::from_utf8(options[4..4 + comment_len].to_vec()).unwrap())
+                    }
+                };
+                packets.push((ts, block[20..20 + caplen].to_vec(), comment));
+            }
+            rest = &rest[len..];
+        }
+        packets
+    }
+
+    #[test]
+    fn pcap_keeps_frames_and_times() {
+        let dir = scratch("pcap");
+        let path = dir.join("out.pcap");
+        let mut savefile = Savefile::create(&path, 1, Rotation::default()).unwrap();
+        assert!(savefile.format() == Format::Pcap);
+        savefile
+            .write(&header(1_700_000_000, 250_000, 3), b"abc", Some("dropped"))
+            .unwrap();
+        savefile
+            .write(&header(1_700_000_001, 0, 5), b"defgh", None)
+            .unwrap();
+        assert_eq!(
+            read_pcap(&path),
+            vec![
+                (1_700_000_000, 250_000, b"abc".to_vec()),
+                (1_700_000_001, 0, b"defgh".to_vec())
+            ]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn pcapng_keeps_frames_and_comments() {
+        let dir = scratch("pcapng");
+        let path = dir.join("out.pcapng");
+        let mut savefile = Savefile::create(&path, 1, Rotation::default()).unwrap();
+        savefile
+            .write(
+                &header(1_700_000_000, 250_000, 3),
+                b"abc",
+                Some("PORT_SCAN"),
+            )
+            .unwrap();
+        savefile
+            .write(&header(1_700_000_001, 0, 4), b"defg", None)
+            .unwrap();
+        // Longer than the 16 bit option length, cut between two-byte characters
+        let long = "é".repeat(40_000);
+        savefile
+            .write(&header(1_700_000_002, 0, 1), b"h", Some(&long))
+            .unwrap();
+        let packets = read_pcapng(&path);
+        assert_eq!(packets.len(), 3);
+        assert_eq!(
+            packets[0],
+            (
+                1_700_000_000_250_000,
+                b"abc".to_vec(),
+                Some(String::from("PORT_SCAN"))
+            )
+        );
+        assert_eq!(packets[1], (1_700_000_001_000_000, b"defg".to_vec(), None));
+        assert_eq!(packets[2].2, Some("é".repeat(32_767)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rotates_on_each_limit() {
+        let dir = scratch("rotation");
+        let count = |name: &str, rotation: Rotation, times: &[i64]| {
+            let path = dir.join(name);
+            let mut savefile = Savefile::create(&path, 1, rotation).unwrap();
+            for &time in times {
+                savefile
+                    .write(&header(time, 0, 10), &[0; 10], None)
+                    .unwrap();
+            }
+            (0..)
+                .map(|index| numbered_path(&path, index))
+                .take_while(|path| path.exists())
+                .map(|path| read_pcap(&path).len())
+                .collect::<Vec<usize>>()
+        };
+        let packets = Rotation {
+            max_packets: Some(2),
+            ..Rotation::default()
+        };
+        assert_eq!(
+            count("packets.pcap", packets, &[0, 0, 0, 0, 0]),
+            vec![2, 2, 1]
+        );
+        // The file header and one 26 byte record reach it
+        let bytes = Rotation {
+            max_bytes: Some(50),
+            ..Rotation::default()
+        };
+        assert_eq!(count("bytes.pcap", bytes, &[0, 0, 0]), vec![1, 1, 1]);
+        let seconds = Rotation {
+            max_seconds: Some(60.0),
+            ..Rotation::default()
+        };
+        assert_eq!(
+            count("seconds.pcap", seconds, &[0, 30, 59, 60, 200]),
+            vec![3, 1, 1]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn numbers_rotated_files_before_the_extension() {
+        assert_eq!(
+            numbered_path(Path::new("/tmp/capture.pcap"), 3),
+            Path::new("/tmp/capture_0003.pcap")
+        );
+        assert_eq!(
+            numbered_path(Path::new("capture"), 12),
+            Path::new("capture_0012")
+        );
+    }
+}
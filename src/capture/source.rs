@@ -0,0 +1,40 @@
+use clap::ArgMatches;
+use pcap::{Activated, Capture, Device, PacketHeader};
+
+/// Open the packet source selected on the command line
+///
+/// `-r <file>` replays a saved pcap/pcapng capture, otherwise the interface
+/// given with `-i` (or the default device) is opened live.
+pub fn open(matches: &ArgMatches) -> Capture<dyn Activated> {
+    if let Some(path) = matches.value_of("read") {
+        return Capture::from_file(path)
+            .expect("Couldn't open capture file")
+            .into();
+    }
+
+    let dev = match matches.value_of("interface") {
+        Some(interface) => Device::list()
+            .unwrap()
+            .into_iter()
+            .find(|d| d.name == interface)
+            .expect("Couldn't find specified interface"),
+        _ => Device::lookup().unwrap(),
+    };
+
+    Capture::from_device(dev)
+        .unwrap()
+        .timeout(2500)
+        .open()
+        .unwrap()
+        .into()
+}
+
+/// Is the packet source a saved capture rather than a live interface
+pub fn is_offline(matches: &ArgMatches) -> bool {
+    matches.is_present("read")
+}
+
+/// Capture time of a record in seconds since the epoch
+pub fn timestamp(header: &PacketHeader) -> f64 {
+    header.ts.tv_sec as f64 + header.ts.tv_usec as f64 / 1_000_000.0
+}
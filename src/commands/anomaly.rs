@@ -1,16 +1,52 @@
-use crate::packet;
+use crate::capture::{bpf, savefile, source};
+use crate::detect::arp::ArpWatch;
+use crate::detect::beacon::{Beacon, Beacons};
+use crate::detect::credentials::Credentials;
+use crate::detect::dhcp::DhcpPolicy;
+use crate::detect::dns::DnsWatch;
+use crate::detect::icmp::IcmpWatch;
+use crate::detect::flood::{SynFlood, SynFloods};
+use crate::detect::ipv6;
+use crate::detect::scan::{Probe, Scan, Scans};
+use crate::detect::tls::TlsPolicy;
+use crate::detect::ConfigError;
+use crate::filter::display;
+use crate::output::record::{Record, MALFORMED_TAG};
+use crate::output;
+use crate::packet::application::Application;
+use crate::packet::error::ParseError;
+use crate::packet::ethernet::Ethernet;
+use crate::packet::icmp::Icmp;
+use crate::packet::ip::IP;
 use crate::packet::protocol::*;
-use dns_lookup::lookup_addr;
-use pcap::{Capture, Device};
+use crate::packet::transport::Transport;
+use crate::packet;
+use crate::reassembly::fragment::{Datagram, Fragments, Incomplete, FRAGMENT_TAG};
+use crate::reassembly::http::Heads;
+use crate::reassembly::stream::Streams;
+use crate::reassembly::tls::Hellos;
 use std::collections::HashSet;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::process::Command;
 use std::vec::Vec;
 
 use clap::ArgMatches;
 
+/// The detector a config builds, or the reason it cannot and an exit
+fn configured<T>(detector: Result<T, ConfigError>) -> T {
+    match detector {
+        Ok(detector) => detector,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
 pub fn anomaly(matches: &ArgMatches) {
     let killswitch: bool = matches.is_present("killswitch");
+    // Replayed captures describe another point in time, never touch local processes for them
+    let offline: bool = source::is_offline(matches);
     // Get Data file with config
     let filename = matches.value_of("config").unwrap();
     let f = std::fs::File::open(filename).unwrap();
@@ -26,86 +62,252 @@ pub fn anomaly(matches: &ArgMatches) {
         new_ports.push(p);
     }
 
-    println!("Host IP: {}", host);
-    println!("Good Ports: {:?}", new_ports);
+    let dhcp_policy = configured(DhcpPolicy::new(&data));
+    let tls_policy = configured(TlsPolicy::new(&data));
+    let mut arp_watch = configured(ArpWatch::new(&data));
+    let mut dns_watch = DnsWatch::new(&data);
+    let mut icmp_watch = IcmpWatch::new(&data);
+    let mut scans = Scans::new(&data);
+    let mut syn_floods = SynFloods::new(&data);
+    let mut beacons = Beacons::new(&data, host);
+
+    eprintln!("Host IP: {}", host);
+    eprintln!("Good Ports: {:?}", new_ports);
 
     let format: bool = !matches.is_present("no-format");
 
     let mut term = term::stdout().unwrap();
-    if format {
-        term.fg(term::color::BRIGHT_CYAN).unwrap();
-    }
-    writeln!(term, "______          _        ______ _            \n| ___ \\        | |       | ___ \\ |           \n| |_/ /   _ ___| |_ _   _| |_/ / |_   _  ___ \n|    / | | / __| __| | | | ___ \\ | | | |/ _ \\\n| |\\ \\ |_| \\__ \\ |_| |_| | |_/ / | |_| |  __/\n\\_| \\_\\__,_|___/\\__|\\__, \\____/|_|\\__,_|\\___|\n                     __/ |                   \n                    |___/                    ").unwrap();
-    if format {
+    if output::open(matches).is_human() {
+        if format {
+            term.fg(term::color::BRIGHT_CYAN).unwrap();
+        }
+        writeln!(term, "______          _        ______ _            \n| ___ \\        | |       | ___ \\ |           \n| |_/ /   _ ___| |_ _   _| |_/ / |_   _  ___ \n|    / | | / __| __| | | | ___ \\ | | | |/ _ \\\n| |\\ \\ |_| \\__ \\ |_| |_| | |_/ / | |_| |  __/\n\\_| \\_\\__,_|___/\\__|\\__, \\____/|_|\\__,_|\\___|\n                     __/ |                   \n                    |___/                    ").unwrap();
         term.reset().unwrap();
-        term.fg(term::color::RED).unwrap();
     }
-    let dev = match matches.value_of("interface") {
-        Some(interface) => Device::list()
-            .unwrap()
-            .into_iter()
-            .find(|d| d.name == interface)
-            .expect("Couldn't find specified interface"),
-        _ => Device::lookup().unwrap(),
-    };
-
-    let mut capture = Capture::from_device(dev)
-        .unwrap()
-        .timeout(2500)
-        .open()
-        .unwrap();
+    let mut capture = source::open(matches);
+    if let Some(expression) = bpf::expression(matches.value_of("filter"), None, None) {
+        eprintln!("CAPTURE FILTER: {}", expression);
+        bpf::apply(&mut capture, &expression);
+    }
+    let mut savefile = savefile::open(matches, capture.get_datalink().0 as u16);
+    let display_filter = display::open(matches);
+    let mut printer = output::Printer::new(matches, term, true);
+    printer.header();
+    if let Some(out) = &savefile {
+        if out.format() == savefile::Format::Pcap {
+            eprintln!("Saving flagged packets without reasons, use a .pcapng file to keep them");
+        }
+    }
 
+    let mut fragments = Fragments::default();
+    let mut streams = Streams::default();
+    let mut http_heads = Heads::default();
+    let mut tls_hellos = Hellos::default();
+    let mut mismatched_streams = HashSet::new();
+    let mut credentials = Credentials::default();
+    let show_passwords: bool = matches.is_present("show-passwords");
     let mut i: u64 = 1;
     let mut start_time: f64 = 0.0;
+    let mut last_time: f64 = 0.0;
 
     loop {
         let packet = match capture.next() {
             Ok(x) => x,
             Err(pcap::Error::TimeoutExpired) => continue,
+            Err(pcap::Error::NoMorePackets) => break,
             Err(_) => {
-                println!("Unknown Error in getting next packet");
+                eprintln!("Unknown Error in getting next packet");
                 continue;
             }
         };
 
-        let mut red_flag: bool = false;
-        let mut reason: String = String::new();
+        let mut reasons: Vec<String> = Vec::new();
 
-        let time: f64 = format!("{}.{}", packet.header.ts.tv_sec, packet.header.ts.tv_usec)
-            .parse()
-            .unwrap();
+        let time: f64 = source::timestamp(packet.header);
         let len = packet.header.len;
         if i == 1 {
             start_time = time;
         }
         let diff_time: f64 = time - start_time;
+        last_time = time;
+        // Summary alerts have no fields for a display filter to match, so they always show
+        let incomplete = fragments.expire(time);
+        report_incomplete(&mut printer, incomplete, i, time, diff_time);
+        let scanned = scans.expire(time);
+        report_scans(&mut printer, scanned, i, time, diff_time);
+        let beaconing = beacons.expire(time);
+        report_beacons(&mut printer, beaconing, i, time, diff_time);
         let data = packet.data.to_vec();
-        // let eth = packet::ethernet::Ethernet::new(data).unwrap();
-        let eth = packet::ethernet::Ethernet::try_from(data).unwrap();
-        let dst_mac = &eth.dst;
-        let src_mac = &eth.src;
-        let int = match packet::ip::IP::new(&eth.payload, eth.ethertype) {
-            Some(x) => x,
-            None => continue,
+        // Frames that do not decode have no fields to filter on, so filtering hides them
+        let filtering = display_filter.is_some();
+        let mut malformed = |number: u64, eth: Option<&Ethernet>, error: ParseError| {
+            if filtering || !error.is_malformed() {
+                return false;
+            }
+            let reason = String::from("MALFORMED_PACKET");
+            if let Some(out) = savefile.as_mut() {
+                out.write(packet.header, packet.data, Some(&format!("{};", reason)))
+                    .expect("Couldn't write packet to capture file");
+            }
+            let mut record = Record::malformed(number, time, diff_time, len, eth, &error);
+            record.reasons = Some(vec![reason]);
+            printer.write(record, Some(term::color::RED));
+            true
+        };
+        let eth = match Ethernet::try_from(data) {
+            Ok(x) => x,
+            Err(error) => {
+                if malformed(i, None, error) {
+                    i += 1;
+                }
+                continue;
+            }
+        };
+        let int = match IP::new(&eth.payload, eth.ethertype) {
+            Ok(x) => x,
+            Err(error) => {
+                if malformed(i, Some(&eth), error) {
+                    i += 1;
+                }
+                continue;
+            }
         };
-        let dst_ip = &int.dst;
         let src_ip = &int.src;
-        let protocol = &int.protocol;
+        let outcome = fragments.add(&int, time);
+        // Only the fragment completing a datagram has an upper layer to decode
+        let (protocol, payload) = match &outcome.datagram {
+            Datagram::Whole => (int.protocol, Some(int.payload)),
+            Datagram::Reassembled(data) => {
+                let (protocol, data) = int.upper_layer(data);
+                (protocol, Some(data))
+            }
+            Datagram::Pending => (int.protocol, None),
+        };
+        let protocol = &protocol;
+        let mut error = None;
+        let mut transport = payload.and_then(|data| {
+            Transport::new(data, protocol).unwrap_or_else(|e| {
+                error = Some(e);
+                None
+            })
+        });
+        let icmp = payload.and_then(|data| {
+            Icmp::new(data, protocol).unwrap_or_else(|e| {
+                error = Some(e);
+                None
+            })
+        });
+        // Heads and hellos split over several segments only decode once the stream has them all
+        let segment = transport.as_ref().and_then(|t| streams.add(&int, t, time));
+        if let (Some(transport), Some(segment)) = (transport.as_mut(), &segment) {
+            transport.content = segment.content.or(transport.content);
+            if let Some(http) = http_heads.add(segment) {
+                if transport.application.is_none() || http.complete {
+                    transport.application = Some(Application::Http(http));
+                }
+            }
+            if let Some(tls) = tls_hellos.add(segment) {
+                transport.application = Some(Application::Tls(tls));
+            }
+        }
+        let decoded = packet::Decoded {
+            len,
+            eth: &eth,
+            ip: &int,
+            transport: transport.as_ref(),
+            icmp: icmp.as_ref(),
+            arp: int.arp.as_ref(),
+        };
+        // Every packet feeds the detectors, the display filter only picks what is printed
+        reasons.extend(ipv6::check(&int));
+        reasons.extend(arp_watch.check(&decoded, time));
+        if let Some(reason) = dhcp_policy.check(&decoded) {
+            reasons.push(reason);
+        }
+        if let Some(reason) = tls_policy.check(&decoded) {
+            reasons.push(reason);
+        }
+        reasons.extend(dns_watch.check(&decoded, time));
+        reasons.extend(icmp_watch.check(&decoded, time));
+        if let Some(credential) = credentials.add(&decoded, segment.as_ref()) {
+            reasons.push(credential.reason(show_passwords));
+        }
+        if let Some(port) = transport.as_ref().and_then(|t| t.port_mismatch()) {
+            // Once per TCP stream, every datagram for UDP
+            let first = match &segment {
+                Some(segment) => mismatched_streams.insert(segment.stream),
+                None => true,
+            };
+            if first {
+                let content = transport.as_ref().unwrap().content.unwrap();
+                reasons.push(format!("PROTOCOL_PORT_MISMATCH ({} on {} port)", content, port));
+            }
+        }
+        if outcome.overlap {
+            reasons.push(String::from("FRAGMENT_OVERLAP"));
+        }
+        if outcome.tiny {
+            reasons.push(String::from("TINY_FRAGMENT"));
+        }
+        if outcome.oversized {
+            reasons.push(String::from("OVERSIZED_DATAGRAM"));
+        }
+        if outcome.too_many {
+            reasons.push(String::from("TOO_MANY_FRAGMENTS"));
+        }
+        // Probes and the answers to them are summed up in one alert per
+        // scanner as the scan starts and another once it is over
+        let probe = transport.as_ref().and_then(|t| Probe::new(t, segment.as_ref()));
+        let mut started = Vec::new();
+        if let Some(transport) = transport.as_ref() {
+            started.extend(scans.add(&int, transport, probe, time));
+        }
+        if let Some((scanner, target, port)) = icmp.as_ref().and_then(|i| i.port_unreachable()) {
+            started.extend(scans.refused(scanner, (target, port), time));
+        }
+        report_scans(&mut printer, started, i, time, diff_time);
+        let scanning =
+            scans.is_scanning(&int.dst) || (probe.is_some() && scans.is_scanning(&int.src));
+        // Flows are scored for beacons as they finish
+        if let Some(transport) = transport.as_ref() {
+            beacons.add(&int, transport, time);
+        }
+        // A flood is one alert when it starts, its SYNs and the answers to them are not listed
+        let flooded = match transport.as_ref() {
+            Some(transport) => {
+                if let Some(flood) = syn_floods.add(&int, transport, time) {
+                    let service = flood.target.0 == host && new_ports.contains(&flood.target.1);
+                    report_flood(&mut printer, flood, service, i, time, diff_time);
+                }
+                syn_floods.is_flooding(&(int.dst, transport.dst_port))
+                    || syn_floods.is_flooding(&(int.src, transport.src_port))
+            }
+            None => false,
+        };
+        if let Some(filter) = &display_filter {
+            if !filter.matches(&decoded) {
+                continue;
+            }
+        }
         let transport_data = match protocol {
+            _ if error.is_some() => {
+                reasons.push(String::from("MALFORMED_PACKET"));
+                (String::from(MALFORMED_TAG), error.as_ref().unwrap().to_string())
+            }
+            _ if payload.is_none() => (String::from(FRAGMENT_TAG), int.fragment().unwrap().to_string()),
             Layer4::Tcp | Layer4::Udp => {
-                let transport = packet::transport::Transport::new(int.payload, protocol).unwrap();
+                let transport = transport.as_ref().unwrap();
                 let port_of_concern: u16 = if &host == src_ip {
                     transport.src_port
                 } else {
                     transport.dst_port
                 };
-                if (!new_ports.contains(&port_of_concern)) {
-                    red_flag = true;
-                    reason.push_str("UNAUTHORIZED_PORT");
-                    if !cfg!(target_os = "windows") {
+                if !scanning && !flooded && !new_ports.contains(&port_of_concern) {
+                    let mut reason = String::from("UNAUTHORIZED_PORT");
+                    if !offline && !cfg!(target_os = "windows") {
                         let process = Command::new("lsof")
                             .arg("-i")
-                            .arg(&format!(":{}", port_of_concern))
+                            .arg(format!(":{}", port_of_concern))
                             .output()
                             .unwrap();
                         let mut lsof_out = std::str::from_utf8(&process.stdout)
@@ -114,7 +316,7 @@ pub fn anomaly(matches: &ArgMatches) {
                         lsof_out.next();
                         let mut pids = HashSet::new();
                         loop {
-                            for i in 0..9 {
+                            for _ in 0..9 {
                                 match lsof_out.next() {
                                     Some(_) => {}
                                     None => break,
@@ -125,93 +327,125 @@ pub fn anomaly(matches: &ArgMatches) {
                                 None => break,
                             };
                         }
-                        if pids.len() > 0 {
+                        if !pids.is_empty() {
                             let mut all_pids = String::from(" (PIDS: ");
                             for pid in pids {
                                 all_pids.push_str(pid);
-                                all_pids.push_str(",");
+                                all_pids.push(',');
                                 if killswitch {
-                                    Command::new("kill").arg("-9").arg(pid).output();
+                                    let _ = Command::new("kill").arg("-9").arg(pid).output();
                                 }
                             }
-                            all_pids.push_str(")");
+                            all_pids.push(')');
                             reason.push_str(&all_pids);
                         }
                     }
-                    reason.push_str(";");
+                    reasons.push(reason);
                 }
                 (transport.get_tag(), transport.to_string())
             }
             Layer4::Icmp | Layer4::ICMPv6 => {
-                let icmp = packet::icmp::Icmp::new(int.payload, protocol).unwrap();
-                (format!("{}", protocol), format!("{}", icmp))
+                (format!("{}", protocol), format!("{}", icmp.as_ref().unwrap()))
             }
             Layer4::Arp => {
                 if len > 60 {
-                    red_flag = true;
-                    reason.push_str("LARGE_ARP_PACKET;");
+                    reasons.push(String::from("LARGE_ARP_PACKET"));
                 }
-                (String::from("ARP"), int.arp.unwrap().to_string())
+                (String::from("ARP"), int.arp.as_ref().unwrap().to_string())
             }
             Layer4::Igmp => (String::from("IGMP"), String::from("IGMP")),
             Layer4::IPv6HopByHop => (String::from("IPv6HbH"), String::from("IPv6HbH")),
             Layer4::Unknown(x) => {
-                red_flag = true;
-                reason.push_str("UNKNOWN_LAYER4;");
+                reasons.push(String::from("UNKNOWN_LAYER4"));
                 (String::from("???"), format!("??? (Header ID: {})", x))
             }
         };
-        if red_flag {
-            match protocol {
-                Layer4::Arp => writeln!(
-                    term,
-                    "{} | {:.9} | {} | {} | {} | {} | {} | {}",
-                    i, diff_time, src_mac, dst_mac, transport_data.0, len, transport_data.1, reason
-                ),
-                _ => {
-                    if matches.is_present("rdns") {
-                        let src_lookup = match lookup_addr(src_ip) {
-                            Ok(x) => {
-                                format!(" ({})", x)
-                            }
-                            Err(_) => String::new(),
-                        };
-                        let dst_lookup = match lookup_addr(dst_ip) {
-                            Ok(x) => format!(" ({})", x),
-                            Err(_) => String::new(),
-                        };
-                        writeln!(
-                            term,
-                            "{} | {:.9} | {}{} | {}{} | {} | {} | {} | {}",
-                            i,
-                            diff_time,
-                            src_ip,
-                            src_lookup,
-                            dst_ip,
-                            dst_lookup,
-                            transport_data.0,
-                            len,
-                            transport_data.1,
-                            reason
-                        )
-                    } else {
-                        writeln!(
-                            term,
-                            "{} | {:.9} | {} | {} | {} | {} | {} | {}",
-                            i,
-                            diff_time,
-                            src_ip,
-                            dst_ip,
-                            transport_data.0,
-                            len,
-                            transport_data.1,
-                            reason
-                        )
-                    }
-                }
+        if !reasons.is_empty() {
+            let reason: String = reasons.iter().map(|r| format!("{};", r)).collect();
+            if let Some(out) = savefile.as_mut() {
+                out.write(packet.header, packet.data, Some(&reason))
+                    .expect("Couldn't write packet to capture file");
             }
-            .unwrap();
+            let mut record = Record::new(i, time, diff_time, &decoded, &transport_data.0, &transport_data.1);
+            record.reasons = Some(reasons);
+            printer.write(record, Some(term::color::RED));
         }
         i += 1;
     }
+    // Whatever is still waiting on fragments at the end of a capture never completed
+    let diff_time = last_time - start_time;
+    report_incomplete(&mut printer, fragments.flush(), i, last_time, diff_time);
+    report_scans(&mut printer, scans.flush(), i, last_time, diff_time);
+    report_beacons(&mut printer, beacons.flush(), i, last_time, diff_time);
+}
+
+fn report_incomplete(
+    printer: &mut output::Printer,
+    incomplete: Vec<Incomplete>,
+    number: u64,
+    time: f64,
+    diff_time: f64,
+) {
+    for datagram in incomplete {
+        let info = datagram.to_string();
+        let mut record = Record::alert(number, time, diff_time, datagram.src, datagram.dst, FRAGMENT_TAG, &info);
+        record.protocol = Layer4::from(datagram.protocol).to_string();
+        record.reasons = Some(vec![String::from("FRAGMENT_INCOMPLETE")]);
+        printer.write(record, Some(term::color::RED));
+    }
+}
+
+fn report_scans(printer: &mut output::Printer, scans: Vec<Scan>, number: u64, time: f64, diff_time: f64) {
+    for scan in scans {
+        let target = scan.target().unwrap_or(match scan.scanner {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        });
+        let info = scan.to_string();
+        let mut record = Record::alert(number, time, diff_time, scan.scanner, target, "SCAN", &info);
+        record.protocol = match scan.is_udp() {
+            true => Layer4::Udp,
+            false => Layer4::Tcp,
+        }
+        .to_string();
+        record.reasons = Some(vec![String::from("PORT_SCAN")]);
+        printer.write(record, Some(term::color::RED));
+    }
+}
+
+fn report_flood(
+    printer: &mut output::Printer,
+    flood: SynFlood,
+    service: bool,
+    number: u64,
+    time: f64,
+    diff_time: f64,
+) {
+    let source = flood.top.first().map_or(flood.target.0, |(ip, _)| *ip);
+    let mut info = flood.to_string();
+    if service {
+        info.push_str(", against a configured service");
+    }
+    let mut record = Record::alert(number, time, diff_time, source, flood.target.0, "SYN", &info);
+    record.protocol = Layer4::Tcp.to_string();
+    record.dst_port = Some(flood.target.1);
+    record.reasons = Some(vec![String::from("SYN_FLOOD")]);
+    printer.write(record, Some(term::color::RED));
+}
+
+fn report_beacons(
+    printer: &mut output::Printer,
+    beacons: Vec<Beacon>,
+    number: u64,
+    time: f64,
+    diff_time: f64,
+) {
+    for beacon in beacons {
+        let info = beacon.to_string();
+        let mut record = Record::alert(number, time, diff_time, beacon.src, beacon.dst, "BEACON", &info);
+        record.protocol = beacon.protocol.to_string();
+        record.dst_port = Some(beacon.port);
+        record.reasons = Some(vec![String::from("C2_BEACON")]);
+        printer.write(record, Some(term::color::RED));
+    }
 }
@@ -0,0 +1,238 @@
+use crate::capture::{bpf, source};
+use crate::filter::display;
+use crate::packet;
+use crate::packet::ethernet::Ethernet;
+use crate::packet::ip::IP;
+use crate::packet::transport::Transport;
+use crate::reassembly::fragment::{Datagram, Fragments};
+use crate::reassembly::stream::{Direction, Stream, Streams};
+use std::collections::HashMap;
+use term::StdoutTerminal;
+
+use clap::ArgMatches;
+
+/// Bytes kept of each conversation, and of all of them together, until one
+/// matches the display filter
+const MAX_HELD_PER_STREAM: usize = 1024 * 1024;
+const MAX_HELD: usize = 64 * 1024 * 1024;
+
+/// What a conversation sent before any of its packets matched the display filter
+#[derive(Default)]
+struct Held {
+    chunks: Vec<(Direction, Vec<u8>)>,
+    bytes: usize,
+    /// Bytes past the limits, everything after the first of them is dropped
+    dropped: usize,
+}
+
+/// How the reassembled bytes are shown
+struct View {
+    term: Box<StdoutTerminal>,
+    hex: bool,
+    color: bool,
+    /// Bytes shown so far in each direction, for the hexdump offsets
+    offsets: [usize; 2],
+}
+
+impl View {
+    fn header(&mut self, stream: &Stream) {
+        let mode = if self.hex { "hex" } else { "text" };
+        writeln!(self.term, "{}", "=".repeat(67)).unwrap();
+        writeln!(self.term, "Follow: tcp,{}", mode).unwrap();
+        writeln!(self.term, "Stream: {}", stream.id).unwrap();
+        writeln!(self.term, "Node 0: {}:{}", stream.client.0, stream.client.1).unwrap();
+        writeln!(self.term, "Node 1: {}:{}", stream.server.0, stream.server.1).unwrap();
+    }
+
+    fn footer(&mut self, stream: Option<&Stream>) {
+        if !self.hex {
+            writeln!(self.term).unwrap();
+        }
+        if let Some(stream) = stream {
+            writeln!(self.term, "State: {}", stream.state).unwrap();
+        }
+        writeln!(self.term, "{}", "=".repeat(67)).unwrap();
+    }
+
+    /// Client bytes red and server bytes blue as Wireshark does, without
+    /// colors the server side is indented instead
+    fn chunk(&mut self, direction: Direction, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        if self.color {
+            self.term
+                .fg(match direction {
+                    Direction::ToServer => term::color::RED,
+                    Direction::ToClient => term::color::BLUE,
+                })
+                .unwrap();
+        }
+        let indent = match direction {
+            Direction::ToClient if !self.color => "\t",
+            _ => "",
+        };
+        if self.hex {
+            let offset = &mut self.offsets[direction as usize];
+            for line in data.chunks(16) {
+                let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+                writeln!(
+                    self.term,
+                    "{}{:08X}  {:<48} {}",
+                    indent,
+                    offset,
+                    hex.join(" "),
+                    printable(line, false)
+                )
+                .unwrap();
+                *offset += line.len();
+            }
+        } else {
+            let text = printable(data, true);
+            let text = match indent {
+                "" => text,
+                _ => format!("{}{}", indent, text.replace('\n', &format!("\n{}", indent))),
+            };
+            write!(self.term, "{}", text).unwrap();
+        }
+        if self.color {
+            self.term.reset().unwrap();
+        }
+    }
+}
+
+/// Bytes as text, anything unprintable shown as '.'
+fn printable(data: &[u8], keep_whitespace: bool) -> String {
+    data.iter()
+        .map(|&b| match b {
+            b'\n' | b'\r' | b'\t' if keep_whitespace => b as char,
+            0x20..=0x7e => b as char,
+            _ => '.',
+        })
+        .collect()
+}
+
+pub fn follow(matches: &ArgMatches) {
+    let hex: bool = matches.is_present("hex");
+    let mut capture = source::open(matches);
+    if let Some(expression) = bpf::expression(matches.value_of("filter"), None, None) {
+        eprintln!("CAPTURE FILTER: {}", expression);
+        bpf::apply(&mut capture, &expression);
+    }
+    let display_filter = display::open(matches);
+    // Without a display filter the stream index picks the conversation, the first by default
+    let mut selected: Option<u64> = match display_filter {
+        Some(_) => None,
+        None => Some(
+            matches
+                .value_of("stream")
+                .map(|s| s.parse().expect("Stream must be a number"))
+                .unwrap_or(0),
+        ),
+    };
+    let mut view = View {
+        term: term::stdout().unwrap(),
+        hex,
+        color: !matches.is_present("no-format"),
+        offsets: [0, 0],
+    };
+
+    let mut fragments = Fragments::default();
+    let mut streams = Streams::default();
+    // Until a packet matches the display filter, every conversation might be the one
+    let mut unselected: HashMap<u64, Held> = HashMap::new();
+    let mut held_bytes = 0;
+    let mut header_shown = false;
+
+    loop {
+        let packet = match capture.next() {
+            Ok(x) => x,
+            Err(pcap::Error::TimeoutExpired) => continue,
+            Err(pcap::Error::NoMorePackets) => break,
+            Err(_) => {
+                eprintln!("Unknown Error in getting next packet");
+                continue;
+            }
+        };
+        let time: f64 = source::timestamp(packet.header);
+        let eth = match Ethernet::try_from(packet.data.to_vec()) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        let int = match IP::new(&eth.payload, eth.ethertype) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        fragments.expire(time);
+        let outcome = fragments.add(&int, time);
+        let (protocol, payload) = match &outcome.datagram {
+            Datagram::Whole => (int.protocol, int.payload),
+            Datagram::Reassembled(data) => int.upper_layer(data),
+            Datagram::Pending => continue,
+        };
+        let transport = match Transport::new(payload, &protocol) {
+            Ok(Some(x)) => x,
+            _ => continue,
+        };
+        let segment = match streams.add(&int, &transport, time) {
+            Some(x) => x,
+            None => continue,
+        };
+
+        if selected.is_none() {
+            let decoded = packet::Decoded {
+                len: packet.header.len,
+                eth: &eth,
+                ip: &int,
+                transport: Some(&transport),
+                icmp: None,
+                arp: None,
+            };
+            if display_filter.as_ref().unwrap().matches(&decoded) {
+                selected = Some(segment.stream);
+            } else {
+                let held = unselected.entry(segment.stream).or_default();
+                let len = segment.data.len();
+                if held.dropped == 0
+                    && held.bytes + len <= MAX_HELD_PER_STREAM
+                    && held_bytes + len <= MAX_HELD
+                {
+                    held.bytes += len;
+                    held_bytes += len;
+                    held.chunks.push((segment.direction, segment.data));
+                } else {
+                    held.dropped += len;
+                }
+                continue;
+            }
+        }
+        if selected != Some(segment.stream) {
+            continue;
+        }
+        if !header_shown {
+            view.header(streams.get(segment.stream).unwrap());
+            header_shown = true;
+            if let Some(held) = unselected.remove(&segment.stream) {
+                if held.dropped > 0 {
+                    eprintln!(
+                        "Only the first {} bytes before the match were kept, {} are missing",
+                        held.bytes, held.dropped
+                    );
+                }
+                for (direction, data) in held.chunks {
+                    view.chunk(direction, &data);
+                }
+            }
+            unselected.clear();
+        }
+        view.chunk(segment.direction, &segment.data);
+        if streams.get(segment.stream).unwrap().is_closed() {
+            break;
+        }
+    }
+    match selected {
+        Some(id) if header_shown => view.footer(streams.get(id)),
+        Some(id) => eprintln!("No TCP stream {} in the capture", id),
+        None => eprintln!("No TCP packet matched the display filter"),
+    }
+}
@@ -5,7 +5,6 @@ use std::io;
 use std::io::{BufRead, BufWriter, Write};
 use std::process::Command;
 use std::str;
-use std::iter::repeat;
 
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -16,37 +15,37 @@ struct Config {
     services: Vec<String>,
 }
 
-pub fn init(matches: &ArgMatches) {
+pub fn init(_matches: &ArgMatches) {
     let mut term = term::stdout().unwrap();
-    let mut stdin = io::stdin();
+    let stdin = io::stdin();
 
     term.fg(term::color::BRIGHT_CYAN).unwrap();
     writeln!(term, "______          _        ______ _            \n| ___ \\        | |       | ___ \\ |           \n| |_/ /   _ ___| |_ _   _| |_/ / |_   _  ___ \n|    / | | / __| __| | | | ___ \\ | | | |/ _ \\\n| |\\ \\ |_| \\__ \\ |_| |_| | |_/ / | |_| |  __/\n\\_| \\_\\__,_|___/\\__|\\__, \\____/|_|\\__,_|\\___|\n                     __/ |                   \n                    |___/                    ").unwrap();
-    term.reset();
+    let _ = term.reset();
 
-    write!(term, "Enter IP Address: ");
-    term.flush();
+    let _ = write!(term, "Enter IP Address: ");
+    let _ = term.flush();
 
     let mut ip = String::new();
-    stdin.read_line(&mut ip);
+    let _ = stdin.read_line(&mut ip);
     let ip = String::from(ip.trim());
 
     let mut ports: Vec<u16> = Vec::new();
     loop {
-        write!(term, "Enter Service Port: ");
-        term.flush();
+        let _ = write!(term, "Enter Service Port: ");
+        let _ = term.flush();
         let mut port_str = String::new();
-        stdin.read_line(&mut port_str);
+        let _ = stdin.read_line(&mut port_str);
         let port_str = port_str.trim();
-        if port_str.len() == 0 {
+        if port_str.is_empty() {
             break;
         }
         ports.push(port_str.parse().unwrap());
     }
 
     term.fg(term::color::YELLOW).unwrap();
-    writeln!(term, "Writing Firewall...");
-    term.reset();
+    let _ = writeln!(term, "Writing Firewall...");
+    let _ = term.reset();
 
     Command::new("iptables")
         .arg("-F")
@@ -99,8 +98,8 @@ pub fn init(matches: &ArgMatches) {
     }
 
     term.fg(term::color::BRIGHT_GREEN).unwrap();
-    writeln!(term, "Successfully Wrote iptables rules!");
-    term.reset();
+    let _ = writeln!(term, "Successfully Wrote iptables rules!");
+    let _ = term.reset();
 
     let passwd = Command::new("cat")
         .arg("/etc/passwd")
@@ -118,12 +117,12 @@ pub fn init(matches: &ArgMatches) {
         };
         let user = &line[..split];
         if !(line.contains("/false") || line.contains("/nologin")) {
-            writeln!(term, "{}", line);
+            let _ = writeln!(term, "{}", line);
             loop {
-                write!(term, "Disable Account? [y/n] ");
-                term.flush();
+                let _ = write!(term, "Disable Account? [y/n] ");
+                let _ = term.flush();
                 let mut yes_or_no = String::new();
-                stdin.read_line(&mut yes_or_no);
+                let _ = stdin.read_line(&mut yes_or_no);
                 if yes_or_no.trim().to_lowercase() == "y" {
                     Command::new("usermod")
                         .arg("-L")
@@ -145,8 +144,8 @@ pub fn init(matches: &ArgMatches) {
                         .expect("failed to execute crontab -r");
 
                     term.fg(term::color::BRIGHT_GREEN).unwrap();
-                    writeln!(term, "User Disabled!");
-                    term.reset();
+                    let _ = writeln!(term, "User Disabled!");
+                    let _ = term.reset();
                     break;
                 } else if yes_or_no.trim().to_lowercase() == "n" {
                     users.push(String::from(user));
@@ -158,12 +157,12 @@ pub fn init(matches: &ArgMatches) {
 
     let mut services: Vec<String> = Vec::new();
     loop {
-        write!(term, "Enter Service File to Keep Alive: ");
-        term.flush();
+        let _ = write!(term, "Enter Service File to Keep Alive: ");
+        let _ = term.flush();
         let mut service = String::new();
-        stdin.read_line(&mut service);
+        let _ = stdin.read_line(&mut service);
         let service = service.trim();
-        if service.len() == 0 {
+        if service.is_empty() {
             break;
         }
         services.push(String::from(service));
@@ -182,10 +181,10 @@ pub fn init(matches: &ArgMatches) {
         .unwrap();
 
     term.fg(term::color::BRIGHT_GREEN).unwrap();
-    writeln!(term, "Successfully wrote config to config.json!");
-    term.reset();
+    let _ = writeln!(term, "Successfully wrote config to config.json!");
+    let _ = term.reset();
 
-    let mut sshd_config = File::open("/etc/ssh/sshd_config").unwrap();
+    let sshd_config = File::open("/etc/ssh/sshd_config").unwrap();
     let new_file = File::create("/tmp/sshd_config").expect("Failed to create file");
     let mut buffered_out = BufWriter::new(new_file);
     let buffered = io::BufReader::new(sshd_config);
@@ -195,7 +194,7 @@ pub fn init(matches: &ArgMatches) {
 
     buffered
         .lines()
-        .map(|line_res| {
+        .try_for_each(|line_res| {
             line_res.and_then(|line| {
                 match line.trim() {
                     "PermitRootLogin yes" => {
@@ -212,19 +211,18 @@ pub fn init(matches: &ArgMatches) {
                 }
             })
         })
-        .collect::<Result<(), _>>()
         .expect("IO failed");
 
     if !permit_root {
-        buffered_out.write_all(b"PermitRootLogin no");
+        let _ = buffered_out.write_all(b"PermitRootLogin no");
     }
 
     if !use_pam {
-        buffered_out.write_all(b"UsePAM no");
+        let _ = buffered_out.write_all(b"UsePAM no");
     }
 
     if !permit_empty_pass {
-        buffered_out.write_all(b"PermitEmptyPasswords no");
+        let _ = buffered_out.write_all(b"PermitEmptyPasswords no");
     }
 
     Command::new("mv")
@@ -240,20 +238,20 @@ pub fn init(matches: &ArgMatches) {
         .expect("failed to restart sshd");
 
     term.fg(term::color::BRIGHT_GREEN).unwrap();
-    writeln!(term, "Successfully configured sshd_config!");
-    term.reset();
+    let _ = writeln!(term, "Successfully configured sshd_config!");
+    let _ = term.reset();
     
-    writeln!(term, "FILES TO CHECK NOW");
-    writeln!(term, "{}", "=".repeat(20));
-    writeln!(term, "/etc/sudoers (visudo)");
-    writeln!(term, "~/.bashrc, ~/.bash_profile, /etc/profile, /etc/bash.bashrc");
-    writeln!(term, "/etc/environment");
-    writeln!(term, "/etc/inputrc");
-    writeln!(term, "/etc/pam.d");
-    writeln!(term, "crontab -l for ROOT AND ACTIVE USERS");
-    writeln!(term, "Go Scrollin in Services for a bit (or let me do it)");
-    writeln!(term, "\n\nGood Luck Agent.");
-
-    term.flush();
-    term.reset();
+    let _ = writeln!(term, "FILES TO CHECK NOW");
+    let _ = writeln!(term, "{}", "=".repeat(20));
+    let _ = writeln!(term, "/etc/sudoers (visudo)");
+    let _ = writeln!(term, "~/.bashrc, ~/.bash_profile, /etc/profile, /etc/bash.bashrc");
+    let _ = writeln!(term, "/etc/environment");
+    let _ = writeln!(term, "/etc/inputrc");
+    let _ = writeln!(term, "/etc/pam.d");
+    let _ = writeln!(term, "crontab -l for ROOT AND ACTIVE USERS");
+    let _ = writeln!(term, "Go Scrollin in Services for a bit (or let me do it)");
+    let _ = writeln!(term, "\n\nGood Luck Agent.");
+
+    let _ = term.flush();
+    let _ = term.reset();
 }
@@ -1,3 +1,4 @@
 pub mod anomaly;
+pub mod follow;
 pub mod init;
 pub mod sniff;
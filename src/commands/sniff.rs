@@ -1,42 +1,53 @@
-use crate::packet;
+use crate::capture::{bpf, savefile, source};
+use crate::filter::{display, rules};
+use crate::output::record::{Record, MALFORMED_TAG};
+use crate::output;
+use crate::packet::application::Application;
+use crate::packet::error::ParseError;
+use crate::packet::ethernet::Ethernet;
+use crate::packet::icmp::Icmp;
+use crate::packet::ip::IP;
 use crate::packet::protocol::*;
-use dns_lookup::lookup_addr;
-use pcap::{Capture, Device};
-use std::net::IpAddr;
+use crate::packet::transport::Transport;
+use crate::packet;
+use crate::reassembly::fragment::{Datagram, Fragments, FRAGMENT_TAG};
+use crate::reassembly::http::Heads;
+use crate::reassembly::stream::Streams;
+use crate::reassembly::tls::Hellos;
 
 use clap::ArgMatches;
 
 pub fn sniff(matches: &ArgMatches) {
     let format: bool = !matches.is_present("no-format");
+    let verbose: bool = matches.is_present("verbose");
     let mut term = term::stdout().unwrap();
-    if format {
-        term.fg(term::color::BRIGHT_CYAN).unwrap();
+    if output::open(matches).is_human() {
+        if format {
+            term.fg(term::color::BRIGHT_CYAN).unwrap();
+        }
+        writeln!(term, "______          _        ______ _            \n| ___ \\        | |       | ___ \\ |           \n| |_/ /   _ ___| |_ _   _| |_/ / |_   _  ___ \n|    / | | / __| __| | | | ___ \\ | | | |/ _ \\\n| |\\ \\ |_| \\__ \\ |_| |_| | |_/ / | |_| |  __/\n\\_| \\_\\__,_|___/\\__|\\__, \\____/|_|\\__,_|\\___|\n                     __/ |                   \n                    |___/                    ").unwrap();
+        term.reset().unwrap();
     }
-    writeln!(term, "______          _        ______ _            \n| ___ \\        | |       | ___ \\ |           \n| |_/ /   _ ___| |_ _   _| |_/ / |_   _  ___ \n|    / | | / __| __| | | | ___ \\ | | | |/ _ \\\n| |\\ \\ |_| \\__ \\ |_| |_| | |_/ / | |_| |  __/\n\\_| \\_\\__,_|___/\\__|\\__, \\____/|_|\\__,_|\\___|\n                     __/ |                   \n                    |___/                    ").unwrap();
-    term.reset().unwrap();
-
-    let dev = match matches.value_of("interface") {
-        Some(interface) => Device::list()
-            .unwrap()
-            .into_iter()
-            .find(|d| d.name == interface)
-            .expect("Couldn't find specified interface"),
-        _ => Device::lookup().unwrap(),
-    };
 
-    if let Some(val) = matches.value_of("mac") {
-        println!("MAC FILTER: {}", val);
-    }
-    if let Some(val) = matches.value_of("ip") {
-        println!("IP FILTER: {}", val);
+    let mut capture = source::open(matches);
+    if let Some(expression) = bpf::expression(
+        matches.value_of("filter"),
+        matches.value_of("mac"),
+        matches.value_of("ip"),
+    ) {
+        eprintln!("CAPTURE FILTER: {}", expression);
+        bpf::apply(&mut capture, &expression);
     }
+    let mut savefile = savefile::open(matches, capture.get_datalink().0 as u16);
+    let display_filter = display::open(matches);
+    let rules = rules::open(matches);
+    let mut printer = output::Printer::new(matches, term, false);
+    printer.header();
 
-    let mut capture = Capture::from_device(dev)
-        .unwrap()
-        .timeout(2500)
-        .open()
-        .unwrap();
-
+    let mut fragments = Fragments::default();
+    let mut streams = Streams::default();
+    let mut http_heads = Heads::default();
+    let mut tls_hellos = Hellos::default();
     let mut i: u64 = 1;
     let mut start_time: f64 = 0.0;
 
@@ -44,114 +55,162 @@ pub fn sniff(matches: &ArgMatches) {
         let packet = match capture.next() {
             Ok(x) => x,
             Err(pcap::Error::TimeoutExpired) => continue,
+            Err(pcap::Error::NoMorePackets) => break,
             Err(_) => {
-                println!("Unknown Error in getting next packet");
+                eprintln!("Unknown Error in getting next packet");
                 continue;
             }
         };
-        let time: f64 = format!("{}.{}", packet.header.ts.tv_sec, packet.header.ts.tv_usec)
-            .parse()
-            .unwrap();
+        let time: f64 = source::timestamp(packet.header);
         let len = packet.header.len;
         if i == 1 {
             start_time = time;
         }
         let diff_time: f64 = time - start_time;
         let data = packet.data.to_vec();
-        // let eth = packet::ethernet::Ethernet::new(data).unwrap();
-        let eth = packet::ethernet::Ethernet::try_from(data).unwrap();
-        let dst_mac = eth.dst;
-        let src_mac = eth.src;
-        if let Some(val) = matches.value_of("mac") {
-            let filter_mac = packet::ethernet::MacAddr::from(String::from(val));
-            if src_mac != filter_mac && dst_mac != filter_mac {
-                continue;
+        // Frames that do not decode have no fields to filter on, so filtering hides them
+        let filtering = display_filter.is_some() || !rules.is_empty();
+        let mut malformed = |number: u64, eth: Option<&Ethernet>, error: ParseError| {
+            if filtering {
+                return false;
             }
-        }
-        let int = packet::ip::IP::new(&eth.payload, eth.ethertype).unwrap();
-        let dst_ip = int.dst;
-        let src_ip = int.src;
-        if let Some(val) = matches.value_of("ip") {
-            let filter_ip: IpAddr = std::net::IpAddr::V4(val.parse().unwrap());
-            if src_ip != filter_ip && dst_ip != filter_ip {
-                continue;
+            if let Some(out) = savefile.as_mut() {
+                out.write(packet.header, packet.data, None)
+                    .expect("Couldn't write packet to capture file");
             }
-        }
-        let protocol = &int.protocol;
-        let transport_data = match protocol {
-            Layer4::Tcp | Layer4::Udp => {
-                let transport = packet::transport::Transport::new(int.payload, protocol).unwrap();
-                if format {
-                    term.fg(transport.get_color()).unwrap();
+            let record = Record::malformed(number, time, diff_time, len, eth, &error);
+            printer.write(record, Some(term::color::RED));
+            true
+        };
+        let eth = match Ethernet::try_from(data) {
+            Ok(x) => x,
+            Err(error) => {
+                if malformed(i, None, error) {
+                    i += 1;
                 }
-                (transport.get_tag(), transport.to_string())
+                continue;
             }
-            Layer4::Icmp | Layer4::ICMPv6 => {
-                if format {
-                    term.fg(term::color::BRIGHT_MAGENTA).unwrap();
+        };
+        let int = match IP::new(&eth.payload, eth.ethertype) {
+            Ok(x) => x,
+            Err(error) => {
+                if malformed(i, Some(&eth), error) {
+                    i += 1;
                 }
-                let icmp = packet::icmp::Icmp::new(int.payload, protocol).unwrap();
-                (format!("{}", protocol), format!("{}", icmp))
+                continue;
             }
-            Layer4::Arp => {
-                if format {
-                    term.fg(term::color::YELLOW).unwrap();
-                }
-                (String::from("ARP"), int.arp.unwrap().to_string())
+        };
+        // Past the IP header there are addresses to show and filter on, so
+        // a broken upper layer still gets a regular row
+        fragments.expire(time);
+        let outcome = fragments.add(&int, time);
+        // Only the fragment completing a datagram has an upper layer to decode
+        let (protocol, payload) = match &outcome.datagram {
+            Datagram::Whole => (int.protocol, Some(int.payload)),
+            Datagram::Reassembled(data) => {
+                let (protocol, data) = int.upper_layer(data);
+                (protocol, Some(data))
             }
-            Layer4::Igmp => (String::from("IGMP"), String::from("IGMP")),
-            Layer4::IPv6HopByHop => (String::from("IPv6HbH"), String::from("IPv6HbH")),
-            Layer4::Unknown(_) => {
-                if format {
-                    term.fg(term::color::RED).unwrap();
+            Datagram::Pending => (int.protocol, None),
+        };
+        let protocol = &protocol;
+        let mut error = None;
+        let mut transport = payload.and_then(|data| {
+            Transport::new(data, protocol).unwrap_or_else(|e| {
+                error = Some(e);
+                None
+            })
+        });
+        let icmp = payload.and_then(|data| {
+            Icmp::new(data, protocol).unwrap_or_else(|e| {
+                error = Some(e);
+                None
+            })
+        });
+        // Heads and hellos split over several segments only decode once the stream has them all
+        let segment = transport.as_ref().and_then(|t| streams.add(&int, t, time));
+        if let (Some(transport), Some(segment)) = (transport.as_mut(), &segment) {
+            transport.content = segment.content.or(transport.content);
+            if let Some(http) = http_heads.add(segment) {
+                if transport.application.is_none() || http.complete {
+                    transport.application = Some(Application::Http(http));
                 }
-                (String::from("???"), String::from("???"))
             }
+            if let Some(tls) = tls_hellos.add(segment) {
+                transport.application = Some(Application::Tls(tls));
+            }
+        }
+        let decoded = packet::Decoded {
+            len,
+            eth: &eth,
+            ip: &int,
+            transport: transport.as_ref(),
+            icmp: icmp.as_ref(),
+            arp: int.arp.as_ref(),
         };
-
-        match protocol {
-            Layer4::Arp => writeln!(
-                term,
-                "{} | {:.9} | {} | {} | {} | {} | {}",
-                i, diff_time, src_mac, dst_mac, transport_data.0, len, transport_data.1
+        if !rules.matches(&decoded) {
+            continue;
+        }
+        if let Some(filter) = &display_filter {
+            if !filter.matches(&decoded) {
+                continue;
+            }
+        }
+        let transport_data = match protocol {
+            _ if error.is_some() => (
+                String::from(MALFORMED_TAG),
+                error.as_ref().unwrap().to_string(),
+                Some(term::color::RED),
             ),
-            _ => {
-                if matches.is_present("rdns") {
-                    let src_lookup = match lookup_addr(&src_ip) {
-                        Ok(x) => {
-                            format!(" ({})", x)
-                        }
-                        Err(_) => String::new(),
-                    };
-                    let dst_lookup = match lookup_addr(&dst_ip) {
-                        Ok(x) => format!(" ({})", x),
-                        Err(_) => String::new(),
-                    };
-                    writeln!(
-                        term,
-                        "{} | {:.9} | {}{} | {}{} | {} | {} | {}",
-                        i,
-                        diff_time,
-                        src_ip,
-                        src_lookup,
-                        dst_ip,
-                        dst_lookup,
-                        transport_data.0,
-                        len,
-                        transport_data.1
-                    )
-                } else {
-                    writeln!(
-                        term,
-                        "{} | {:.9} | {} | {} | {} | {} | {}",
-                        i, diff_time, src_ip, dst_ip, transport_data.0, len, transport_data.1
-                    )
-                }
+            _ if payload.is_none() => (
+                String::from(FRAGMENT_TAG),
+                int.fragment().unwrap().to_string(),
+                None,
+            ),
+            Layer4::Tcp | Layer4::Udp => {
+                let transport = transport.as_ref().unwrap();
+                (
+                    transport.get_tag(),
+                    transport.to_string(),
+                    Some(transport.get_color()),
+                )
             }
+            Layer4::Icmp | Layer4::ICMPv6 => (
+                format!("{}", protocol),
+                format!("{}", icmp.as_ref().unwrap()),
+                Some(term::color::BRIGHT_MAGENTA),
+            ),
+            Layer4::Arp => (
+                String::from("ARP"),
+                int.arp.as_ref().unwrap().to_string(),
+                Some(term::color::YELLOW),
+            ),
+            Layer4::Igmp => (String::from("IGMP"), String::from("IGMP"), None),
+            Layer4::IPv6HopByHop => (String::from("IPv6HbH"), String::from("IPv6HbH"), None),
+            Layer4::Unknown(_) => (
+                String::from("???"),
+                String::from("???"),
+                Some(term::color::RED),
+            ),
+        };
+
+        if let Some(out) = savefile.as_mut() {
+            out.write(packet.header, packet.data, None)
+                .expect("Couldn't write packet to capture file");
         }
-        .unwrap();
 
-        term.reset().unwrap();
+        let mut info = transport_data.1;
+        if verbose {
+            if let Some(header) = &int.ipv4 {
+                info = format!("{} {}", header, info);
+            }
+            if let Some(header) = &int.ipv6 {
+                info = format!("{} {}", header, info);
+            }
+        }
+
+        let record = Record::new(i, time, diff_time, &decoded, &transport_data.0, &info);
+        printer.write(record, transport_data.2);
         i += 1;
     }
 }
@@ -0,0 +1,188 @@
+use crate::detect::{self, ConfigError};
+use crate::packet::arp::{Arp, REPLY, REQUEST};
+use crate::packet::ethernet::MacAddr;
+use crate::packet::Decoded;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr};
+
+/// Seconds a request stays open for the reply to it
+const REQUEST_TIMEOUT: f64 = 5.0;
+
+/// IPs one MAC may claim before it is reported, unless the config says otherwise
+const MAX_IPS_PER_MAC: usize = 8;
+
+/// Bindings and requests remembered at once, all are dropped when there are more
+const MAX_ENTRIES: usize = 65536;
+
+/// Keeps the IP to MAC bindings ARP announces and flags the tricks used to
+/// poison them. The optional `gateway_ip` and `gateway_mac` config keys name
+/// the one MAC allowed to answer for the gateway, `arp_max_ips_per_mac` how
+/// many addresses one MAC may claim.
+pub struct ArpWatch {
+    gateway: Option<(IpAddr, MacAddr)>,
+    max_ips_per_mac: usize,
+    bindings: HashMap<IpAddr, MacAddr>,
+    claims: HashMap<MacAddr, HashSet<IpAddr>>,
+    /// When each address was last asked for
+    requests: HashMap<IpAddr, f64>,
+}
+
+impl ArpWatch {
+    pub fn new(config: &serde_yaml::Value) -> Result<ArpWatch, ConfigError> {
+        let gateway = match (
+            detect::value(config, "gateway_ip")?,
+            detect::value(config, "gateway_mac")?,
+        ) {
+            (Some(ip), Some(mac)) => Some((ip, mac)),
+            _ => None,
+        };
+        Ok(ArpWatch {
+            gateway,
+            max_ips_per_mac: config["arp_max_ips_per_mac"]
+                .as_u64()
+                .map_or(MAX_IPS_PER_MAC, |n| n as usize),
+            bindings: HashMap::new(),
+            claims: HashMap::new(),
+            requests: HashMap::new(),
+        })
+    }
+
+    /// Reasons this ARP packet looks like spoofing, learning its binding either way
+    pub fn check(&mut self, packet: &Decoded, time: f64) -> Vec<String> {
+        let mut reasons = Vec::new();
+        let arp: &Arp = match packet.arp {
+            Some(arp) => arp,
+            None => return reasons,
+        };
+        if self.bindings.len() >= MAX_ENTRIES || self.requests.len() >= MAX_ENTRIES {
+            self.bindings.clear();
+            self.claims.clear();
+            self.requests.clear();
+        }
+        if arp.opcode == REQUEST && !arp.is_gratuitous() {
+            self.requests.insert(arp.dst_ip, time);
+        }
+        // Probes for duplicate addresses come from 0.0.0.0 and bind nothing
+        if arp.src_ip == IpAddr::V4(Ipv4Addr::UNSPECIFIED) {
+            return reasons;
+        }
+        if arp.opcode == REPLY && !arp.is_gratuitous() {
+            // One request gets one reply, the racing answers of a poisoner stand out
+            let asked = self.requests.remove(&arp.src_ip);
+            if !asked.is_some_and(|asked| time - asked <= REQUEST_TIMEOUT) {
+                reasons.push(format!(
+                    "UNSOLICITED_ARP_REPLY ({} is at {})",
+                    arp.src_ip, arp.src_mac
+                ));
+            }
+        }
+        if let Some((ip, mac)) = self.gateway {
+            if arp.src_ip == ip && arp.src_mac != mac {
+                reasons.push(format!(
+                    "ARP_GATEWAY_SPOOF ({} claimed by {}, expected {})",
+                    ip, arp.src_mac, mac
+                ));
+            }
+        }
+        if let Some(old) = self.bindings.insert(arp.src_ip, arp.src_mac) {
+            if old != arp.src_mac {
+                let label = match arp.is_gratuitous() {
+                    true => "GRATUITOUS_ARP_BINDING_CHANGE",
+                    false => "ARP_BINDING_CHANGE",
+                };
+                reasons.push(format!(
+                    "{} ({} {} -> {})",
+                    label, arp.src_ip, old, arp.src_mac
+                ));
+                if let Some(ips) = self.claims.get_mut(&old) {
+                    ips.remove(&arp.src_ip);
+                }
+            }
+        }
+        let ips = self.claims.entry(arp.src_mac).or_default();
+        // Reported once, when the MAC first goes over the limit
+        if ips.insert(arp.src_ip) && ips.len() == self.max_ips_per_mac + 1 {
+            reasons.push(format!(
+                "ARP_MAC_CLAIMS_MANY_IPS ({} claims {} addresses)",
+                arp.src_mac,
+                ips.len()
+            ));
+        }
+        reasons
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::ethernet::Ethernet;
+    use crate::packet::ip::IP;
+
+    /// An ARP packet from 00:11:22:33:44:`mac` at 10.0.0.`src` to 10.0.0.`dst`
+    fn check(
+        watch: &mut ArpWatch,
+        opcode: u16,
+        mac: u8,
+        src: u8,
+        dst: u8,
+        time: f64,
+    ) -> Vec<String> {
+        let mut frame = hex::decode("ffffffffffff0011223344000806000108000604").unwrap();
+        frame[11] = mac;
+        frame.extend(opcode.to_be_bytes());
+        frame.extend([0x00, 0x11, 0x22, 0x33, 0x44, mac, 10, 0, 0, src]);
+        frame.extend([0, 0, 0, 0, 0, 0, 10, 0, 0, dst]);
+        let eth = Ethernet::try_from(frame).unwrap();
+        let ip = IP::new(&eth.payload, eth.ethertype).unwrap();
+        let decoded = Decoded {
+            len: 42,
+            eth: &eth,
+            ip: &ip,
+            transport: None,
+            icmp: None,
+            arp: ip.arp.as_ref(),
+        };
+        watch.check(&decoded, time)
+    }
+
+    #[test]
+    fn flags_a_second_reply_to_one_request() {
+        let mut watch = ArpWatch::new(&serde_yaml::Value::Null).unwrap();
+        assert!(check(&mut watch, REQUEST, 1, 1, 2, 0.0).is_empty());
+        assert!(check(&mut watch, REPLY, 2, 2, 1, 0.1).is_empty());
+        let reasons = check(&mut watch, REPLY, 9, 2, 1, 0.1);
+        assert!(reasons[0].starts_with("UNSOLICITED_ARP_REPLY (10.0.0.2 is at"));
+        assert!(reasons[1].starts_with("ARP_BINDING_CHANGE (10.0.0.2"));
+
+        // Asked again too late
+        check(&mut watch, REQUEST, 1, 1, 2, 1.0);
+        let reasons = check(&mut watch, REPLY, 2, 2, 1, 1.0 + REQUEST_TIMEOUT + 1.0);
+        assert!(reasons[0].starts_with("UNSOLICITED_ARP_REPLY"));
+    }
+
+    #[test]
+    fn flags_a_mac_claiming_many_addresses() {
+        let config = serde_yaml::from_str("arp_max_ips_per_mac: 2").unwrap();
+        let mut watch = ArpWatch::new(&config).unwrap();
+        // Gratuitous announcements, so only the claims count
+        check(&mut watch, REQUEST, 7, 1, 1, 0.0);
+        check(&mut watch, REQUEST, 7, 2, 2, 0.0);
+        let reasons = check(&mut watch, REQUEST, 7, 3, 3, 0.0);
+        assert_eq!(
+            reasons,
+            vec!["ARP_MAC_CLAIMS_MANY_IPS (00:11:22:33:44:07 claims 3 addresses)"]
+        );
+        assert!(check(&mut watch, REQUEST, 7, 4, 4, 0.0).is_empty());
+    }
+
+    #[test]
+    fn reports_a_typo_in_the_gateway() {
+        let config =
+            serde_yaml::from_str("{gateway_ip: 10.0.0.1, gateway_mac: '00:11:22:33:44:zz'}");
+        let error = ArpWatch::new(&config.unwrap()).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Invalid gateway_mac in config: '00:11:22:33:44:zz' Invalid character 'z' at position 10"
+        );
+    }
+}
@@ -0,0 +1,368 @@
+use crate::packet::ip::IP;
+use crate::packet::protocol::Layer4;
+use crate::packet::transport::{TcpFlags, Transport};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::IpAddr;
+
+/// Defaults for the optional `beacon_min_connections`, `beacon_min_period`
+/// (seconds) and `beacon_confidence` (0 to 1) config keys
+const MIN_CONNECTIONS: usize = 6;
+const MIN_PERIOD: f64 = 2.0;
+const CONFIDENCE: f64 = 0.8;
+
+/// Seconds a flow may go quiet before it counts as finished
+const FLOW_TIMEOUT: f64 = 10.0;
+
+/// Flows kept per destination, enough to judge a period by
+const HISTORY: usize = 64;
+
+/// Flows and destinations followed at once, all are dropped when there are more
+const MAX_FLOWS: usize = 65536;
+const MAX_CHANNELS: usize = 65536;
+
+/// How far off the median an interval may be and still count as on time
+const ON_TIME: f64 = 0.2;
+
+/// The host's port, then the peer and its port, and whether it is TCP
+type Key = (u16, IpAddr, u16, bool);
+
+/// One outbound connection, or one run of UDP datagrams
+struct Flow {
+    start: f64,
+    last_seen: f64,
+    bytes: usize,
+    closed: bool,
+}
+
+/// Every flow from the host to one service
+#[derive(Default)]
+struct Channel {
+    /// Start time and payload bytes of each finished flow, oldest first
+    flows: VecDeque<(f64, usize)>,
+    /// Flows finished since the channel was last scored
+    changed: bool,
+    reported: bool,
+}
+
+/// Connections the host makes to one destination at a steady rhythm
+pub struct Beacon {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub port: u16,
+    pub protocol: Layer4,
+    pub connections: usize,
+    /// Mean seconds between connections and their standard deviation
+    pub period: f64,
+    pub jitter: f64,
+    /// Mean payload bytes per connection
+    pub bytes: f64,
+    pub confidence: f64,
+}
+
+impl fmt::Display for Beacon {
+    /// `Beacon to 1.2.3.4:443 every 60.0s ±1.2s, 12 connections of ~420 bytes, confidence 0.93`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Beacon to {}:{} every {:.1}s ±{:.1}s, {} connections of ~{:.0} bytes, confidence {:.2}",
+            self.dst,
+            self.port,
+            self.period,
+            self.jitter,
+            self.connections,
+            self.bytes,
+            self.confidence
+        )
+    }
+}
+
+/// Builds flow records of the host's outbound traffic and looks for
+/// destinations it calls back to on a timer, the way implants check in
+pub struct Beacons {
+    host: IpAddr,
+    min_connections: usize,
+    min_period: f64,
+    confidence: f64,
+    flows: HashMap<Key, Flow>,
+    channels: HashMap<(IpAddr, u16, bool), Channel>,
+    last_expired: f64,
+}
+
+impl Beacons {
+    pub fn new(config: &serde_yaml::Value, host: IpAddr) -> Beacons {
+        Beacons {
+            host,
+            min_connections: config["beacon_min_connections"]
+                .as_u64()
+                .map_or(MIN_CONNECTIONS, |n| (n as usize).max(2)),
+            min_period: config["beacon_min_period"].as_f64().unwrap_or(MIN_PERIOD),
+            confidence: config["beacon_confidence"].as_f64().unwrap_or(CONFIDENCE),
+            flows: HashMap::new(),
+            channels: HashMap::new(),
+            last_expired: 0.0,
+        }
+    }
+
+    /// Count a TCP segment or UDP datagram into the flow it belongs to
+    pub fn add(&mut self, ip: &IP, transport: &Transport, time: f64) {
+        let tcp = transport.tcp.is_some();
+        let key = match (ip.src == self.host, ip.dst == self.host) {
+            (true, false) => (transport.src_port, ip.dst, transport.dst_port, tcp),
+            (false, true) => (transport.dst_port, ip.src, transport.src_port, tcp),
+            _ => return,
+        };
+        if let Some(flow) = self.flows.get(&key) {
+            // A new connection reusing the ports of a finished one
+            if flow.closed
+                && transport
+                    .tcp
+                    .as_ref()
+                    .is_some_and(|t| t.flags.has(TcpFlags::SYN))
+            {
+                let flow = self.flows.remove(&key).unwrap();
+                self.finish(key, flow);
+            }
+        }
+        if !self.flows.contains_key(&key) {
+            // Only flows the host started are outbound
+            if ip.src != self.host {
+                return;
+            }
+            if self.flows.len() >= MAX_FLOWS {
+                self.flows.clear();
+            }
+            self.flows.insert(
+                key,
+                Flow {
+                    start: time,
+                    last_seen: time,
+                    bytes: 0,
+                    closed: false,
+                },
+            );
+        }
+        let flow = self.flows.get_mut(&key).unwrap();
+        flow.last_seen = time;
+        flow.bytes += transport.payload.len();
+        if let Some(tcp) = &transport.tcp {
+            if tcp.flags.has(TcpFlags::FIN) || tcp.flags.has(TcpFlags::RST) {
+                flow.closed = true;
+            }
+        }
+    }
+
+    /// Beacons found among the flows that finished since the last call
+    pub fn expire(&mut self, now: f64) -> Vec<Beacon> {
+        // Looking through every flow once a second is plenty
+        if now - self.last_expired < 1.0 {
+            return Vec::new();
+        }
+        self.last_expired = now;
+        let done: Vec<Key> = self
+            .flows
+            .iter()
+            .filter(|(_, flow)| flow.closed || now - flow.last_seen > FLOW_TIMEOUT)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in done {
+            let flow = self.flows.remove(&key).unwrap();
+            self.finish(key, flow);
+        }
+        self.score()
+    }
+
+    /// Beacons among every flow seen, for the end of a capture
+    pub fn flush(&mut self) -> Vec<Beacon> {
+        let flows: Vec<(Key, Flow)> = self.flows.drain().collect();
+        for (key, flow) in flows {
+            self.finish(key, flow);
+        }
+        self.score()
+    }
+
+    fn finish(&mut self, (_, dst, port, tcp): Key, flow: Flow) {
+        if !self.channels.contains_key(&(dst, port, tcp)) && self.channels.len() >= MAX_CHANNELS {
+            self.channels.clear();
+        }
+        let channel = self.channels.entry((dst, port, tcp)).or_default();
+        // Flows finish out of order, keep them sorted by start
+        let at = channel
+            .flows
+            .partition_point(|(start, _)| *start <= flow.start);
+        channel.flows.insert(at, (flow.start, flow.bytes));
+        if channel.flows.len() > HISTORY {
+            channel.flows.pop_front();
+        }
+        channel.changed = true;
+    }
+
+    fn score(&mut self) -> Vec<Beacon> {
+        let mut found = Vec::new();
+        for ((dst, port, tcp), channel) in self.channels.iter_mut() {
+            if !channel.changed || channel.reported || channel.flows.len() < self.min_connections {
+                continue;
+            }
+            channel.changed = false;
+            let starts: Vec<f64> = channel.flows.iter().map(|(start, _)| *start).collect();
+            let intervals: Vec<f64> = starts.windows(2).map(|w| w[1] - w[0]).collect();
+            let (period, jitter) = spread(&intervals);
+            if period < self.min_period {
+                continue;
+            }
+            let sizes: Vec<f64> = channel
+                .flows
+                .iter()
+                .map(|(_, bytes)| *bytes as f64)
+                .collect();
+            let (bytes, size_spread) = spread(&sizes);
+
+            // Steady timing, most intervals near the usual one, and the same
+            // amount said each time
+            let jitter_score = (1.0 - jitter / period).max(0.0);
+            let mut sorted = intervals.clone();
+            sorted.sort_by(f64::total_cmp);
+            let median = sorted[sorted.len() / 2];
+            let on_time = intervals
+                .iter()
+                .filter(|i| (*i - median).abs() <= median * ON_TIME)
+                .count();
+            let interval_score = on_time as f64 / intervals.len() as f64;
+            let size_score = match bytes > 0.0 {
+                true => (1.0 - size_spread / bytes).max(0.0),
+                false => 1.0,
+            };
+            let confidence = 0.4 * jitter_score + 0.3 * interval_score + 0.3 * size_score;
+            if confidence < self.confidence {
+                continue;
+            }
+            channel.reported = true;
+            found.push(Beacon {
+                src: self.host,
+                dst: *dst,
+                port: *port,
+                protocol: match tcp {
+                    true => Layer4::Tcp,
+                    false => Layer4::Udp,
+                },
+                connections: channel.flows.len(),
+                period,
+                jitter,
+                bytes,
+                confidence,
+            });
+        }
+        found
+    }
+}
+
+/// Mean and standard deviation
+fn spread(values: &[f64]) -> (f64, f64) {
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64;
+    (mean, variance.sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::protocol::Layer3;
+
+    const HOST: [u8; 4] = [10, 0, 0, 5];
+
+    /// A TCP segment between the host's port `port` and 198.51.100.7:443
+    fn packet(outbound: bool, port: u16, flags: u8, payload: usize) -> Vec<u8> {
+        let mut data = hex::decode("450000000000400040060000").unwrap();
+        let (src, dst) = (HOST, [198, 51, 100, 7]);
+        let mut ports = [port.to_be_bytes(), 443u16.to_be_bytes()];
+        match outbound {
+            true => data.extend(src.iter().chain(&dst)),
+            false => {
+                data.extend(dst.iter().chain(&src));
+                ports.reverse();
+            }
+        }
+        data.extend(ports.concat());
+        data.extend([0, 0, 0, 1, 0, 0, 0, 0, 0x50, flags, 4, 0, 0, 0, 0, 0]);
+        data.extend(vec![0x17; payload]);
+        let len = data.len() as u16;
+        data[2..4].copy_from_slice(&len.to_be_bytes());
+        data
+    }
+
+    fn add(beacons: &mut Beacons, data: &[u8], time: f64) {
+        let ip = IP::new(data, Layer3::IPv4).unwrap();
+        let transport = Transport::new(ip.payload, &ip.protocol).unwrap().unwrap();
+        beacons.add(&ip, &transport, time);
+    }
+
+    /// One short connection from the host's `port`, opened at `time`
+    fn connect(beacons: &mut Beacons, port: u16, time: f64) {
+        add(beacons, &packet(true, port, TcpFlags::SYN, 0), time);
+        add(
+            beacons,
+            &packet(false, port, TcpFlags::SYN | TcpFlags::ACK, 0),
+            time,
+        );
+        add(
+            beacons,
+            &packet(true, port, TcpFlags::ACK | TcpFlags::PSH, 200),
+            time + 0.1,
+        );
+        add(
+            beacons,
+            &packet(false, port, TcpFlags::ACK | TcpFlags::PSH, 40),
+            time + 0.2,
+        );
+        add(
+            beacons,
+            &packet(true, port, TcpFlags::FIN | TcpFlags::ACK, 0),
+            time + 0.3,
+        );
+    }
+
+    #[test]
+    fn finds_a_steady_check_in() {
+        let mut beacons = Beacons::new(&serde_yaml::Value::Null, IpAddr::from(HOST));
+        let mut found = Vec::new();
+        for (n, jitter) in [0.0, 0.4, -0.3, 0.2, -0.1, 0.3].iter().enumerate() {
+            let time = n as f64 * 30.0 + jitter;
+            connect(&mut beacons, 50000 + n as u16, time);
+            found.extend(beacons.expire(time + 1.0));
+            assert!(beacons.flows.is_empty());
+        }
+        assert_eq!(found.len(), 1);
+        let beacon = &found[0];
+        assert_eq!((beacon.port, beacon.connections), (443, MIN_CONNECTIONS));
+        assert!((beacon.period - 30.0).abs() < 0.5 && beacon.bytes == 240.0);
+        assert!(beacon.confidence >= CONFIDENCE);
+        // Reported once
+        connect(&mut beacons, 50010, 180.0);
+        assert!(beacons.flush().is_empty());
+    }
+
+    #[test]
+    fn irregular_connections_are_no_beacon() {
+        let mut beacons = Beacons::new(&serde_yaml::Value::Null, IpAddr::from(HOST));
+        for (n, time) in [0.0, 3.0, 40.0, 47.0, 200.0, 230.0, 800.0]
+            .iter()
+            .enumerate()
+        {
+            connect(&mut beacons, 50000 + n as u16, *time);
+        }
+        assert!(beacons.flush().is_empty());
+    }
+
+    #[test]
+    fn ignores_inbound_connections() {
+        let mut beacons = Beacons::new(&serde_yaml::Value::Null, IpAddr::from(HOST));
+        for n in 0..10 {
+            add(
+                &mut beacons,
+                &packet(false, 50000 + n, TcpFlags::SYN, 0),
+                n as f64 * 30.0,
+            );
+        }
+        assert!(beacons.flows.is_empty() && beacons.flush().is_empty());
+    }
+}
@@ -0,0 +1,373 @@
+use crate::packet::application::Application;
+use crate::packet::Decoded;
+use crate::reassembly::stream::{Direction, Segment};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// Longer command lines are dropped unread, logins are short
+const MAX_LINE: usize = 1024;
+
+/// Sessions followed at once, all are dropped when there are more
+const MAX_SESSIONS: usize = 4096;
+
+/// A username and password seen crossing the wire in the clear
+pub struct Credential {
+    pub protocol: String,
+    pub client: IpAddr,
+    pub server: IpAddr,
+    pub username: String,
+    pub password: String,
+}
+
+impl Credential {
+    /// The alert reason, the password is masked unless `show_password`
+    pub fn reason(&self, show_password: bool) -> String {
+        let password = match show_password {
+            true => self.password.as_str(),
+            false => "********",
+        };
+        format!(
+            "CLEARTEXT_CREDENTIALS ({} {} -> {} user {} password {})",
+            self.protocol, self.client, self.server, self.username, password
+        )
+    }
+}
+
+/// What the next client line or keystrokes are expected to hold
+#[derive(Clone, Copy, Default, PartialEq)]
+enum Expect {
+    #[default]
+    Command,
+    /// The base64 response to a bare `AUTH PLAIN`
+    PlainResponse,
+    /// The base64 username, then password, of `AUTH LOGIN`
+    LoginUser,
+    LoginPassword,
+    /// Telnet keystrokes after a `login:` or `Password:` prompt
+    TelnetUser,
+    TelnetPassword,
+}
+
+#[derive(Default)]
+struct Session {
+    line: Vec<u8>,
+    user: Option<String>,
+    expect: Expect,
+}
+
+/// Follows the login exchanges of FTP, SMTP, POP3, IMAP and Telnet streams,
+/// and HTTP Basic authorization headers
+#[derive(Default)]
+pub struct Credentials {
+    sessions: HashMap<u64, Session>,
+}
+
+impl Credentials {
+    /// Feed a decoded packet and the bytes it put in order, returning a
+    /// credential once both halves of it were seen
+    pub fn add(&mut self, packet: &Decoded, segment: Option<&Segment>) -> Option<Credential> {
+        let transport = packet.transport?;
+        let protocol = transport.get_tag();
+        let (client, server) = match segment.map(|s| s.direction) {
+            Some(Direction::ToClient) => (packet.ip.dst, packet.ip.src),
+            _ => (packet.ip.src, packet.ip.dst),
+        };
+        let found = match &transport.application {
+            Some(Application::Http(http)) if http.complete => {
+                ["Authorization", "Proxy-Authorization"]
+                    .iter()
+                    .find_map(|name| basic(http.header(name)?))
+            }
+            _ => None,
+        };
+        let found = found.or_else(|| {
+            let segment = segment?;
+            if !matches!(&protocol[..], "FTP" | "SMTP" | "POP3" | "IMAP" | "TELNET") {
+                return None;
+            }
+            if !self.sessions.contains_key(&segment.stream) && self.sessions.len() >= MAX_SESSIONS {
+                self.sessions.clear();
+            }
+            let session = self.sessions.entry(segment.stream).or_default();
+            match (&protocol[..], segment.direction) {
+                ("TELNET", Direction::ToClient) => {
+                    session.prompt(&segment.data);
+                    None
+                }
+                ("TELNET", Direction::ToServer) => session.keystrokes(&segment.data),
+                (_, Direction::ToServer) => session.lines(&segment.data),
+                _ => None,
+            }
+        });
+        found.map(|(username, password)| Credential {
+            protocol,
+            client,
+            server,
+            username,
+            password,
+        })
+    }
+}
+
+impl Session {
+    fn lines(&mut self, data: &[u8]) -> Option<(String, String)> {
+        self.line.extend_from_slice(data);
+        let mut found = None;
+        while let Some(end) = self.line.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.line.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            found = found.or(self.command(line.trim_end()));
+        }
+        if self.line.len() > MAX_LINE {
+            self.line.clear();
+        }
+        found
+    }
+
+    fn command(&mut self, line: &str) -> Option<(String, String)> {
+        match self.expect {
+            Expect::PlainResponse => {
+                self.expect = Expect::Command;
+                return plain(line);
+            }
+            Expect::LoginUser => {
+                self.user = decode(line);
+                self.expect = Expect::LoginPassword;
+                return None;
+            }
+            Expect::LoginPassword => {
+                self.expect = Expect::Command;
+                let user = self.user.take().unwrap_or_else(|| String::from("?"));
+                return decode(line).map(|password| (user, password));
+            }
+            _ => {}
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        // IMAP commands start with a tag of the client's choosing
+        let tagged = match words.get(1).map(|w| w.to_ascii_uppercase()) {
+            Some(w) => {
+                (w == "LOGIN" || w == "AUTHENTICATE") && !words[0].eq_ignore_ascii_case("AUTH")
+            }
+            None => false,
+        };
+        let words = match tagged {
+            true => &words[1..],
+            false => &words[..],
+        };
+        let verb = words.first()?.to_ascii_uppercase();
+        let mechanism = words.get(1).map(|w| w.to_ascii_uppercase());
+        match (&verb[..], mechanism.as_deref()) {
+            ("USER", _) => {
+                self.user = words.get(1).map(|w| String::from(*w));
+                None
+            }
+            ("PASS", _) => {
+                let user = self.user.take().unwrap_or_else(|| String::from("?"));
+                Some((user, words[1..].join(" ")))
+            }
+            ("LOGIN", _) if words.len() >= 3 => {
+                Some((unquote(words[1]), unquote(&words[2..].join(" "))))
+            }
+            ("AUTH" | "AUTHENTICATE", Some("PLAIN")) => match words.get(2) {
+                Some(response) => plain(response),
+                None => {
+                    self.expect = Expect::PlainResponse;
+                    None
+                }
+            },
+            ("AUTH" | "AUTHENTICATE", Some("LOGIN")) => {
+                match words.get(2) {
+                    Some(user) => {
+                        self.user = decode(user);
+                        self.expect = Expect::LoginPassword;
+                    }
+                    None => self.expect = Expect::LoginUser,
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Server output, watching for the login and password prompts
+    fn prompt(&mut self, data: &[u8]) {
+        let text = String::from_utf8_lossy(data).to_ascii_lowercase();
+        if text.contains("password:") {
+            self.expect = Expect::TelnetPassword;
+        } else if text.contains("login:") || text.contains("username:") {
+            self.expect = Expect::TelnetUser;
+        } else {
+            return;
+        }
+        self.line.clear();
+    }
+
+    /// Client keystrokes, which Telnet sends a few at a time
+    fn keystrokes(&mut self, data: &[u8]) -> Option<(String, String)> {
+        let mut bytes = data.iter();
+        while let Some(&byte) = bytes.next() {
+            match byte {
+                // IAC, skip the option negotiation
+                0xff => match bytes.next() {
+                    Some(0xfa) => while !matches!(bytes.next(), Some(0xf0) | None) {},
+                    Some(251..=254) => {
+                        bytes.next();
+                    }
+                    _ => {}
+                },
+                0x08 | 0x7f => {
+                    self.line.pop();
+                }
+                b'\r' | b'\n' => {
+                    let typed = String::from_utf8_lossy(&self.line).into_owned();
+                    self.line.clear();
+                    match self.expect {
+                        Expect::TelnetUser => self.user = Some(typed),
+                        Expect::TelnetPassword => {
+                            self.expect = Expect::Command;
+                            let user = self.user.take().unwrap_or_else(|| String::from("?"));
+                            return Some((user, typed));
+                        }
+                        _ => {}
+                    }
+                    self.expect = Expect::Command;
+                }
+                0x20..=0x7e if self.line.len() < MAX_LINE => self.line.push(byte),
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+fn decode(text: &str) -> Option<String> {
+    let bytes = base64::decode(text.trim()).ok()?;
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// `AUTH PLAIN` carries `authzid \0 authcid \0 password` in base64
+fn plain(response: &str) -> Option<(String, String)> {
+    let decoded = decode(response)?;
+    let mut parts = decoded.split('\0').skip(1);
+    Some((String::from(parts.next()?), String::from(parts.next()?)))
+}
+
+/// `Basic` authorization is base64 of `user:password`
+fn basic(value: &str) -> Option<(String, String)> {
+    let (scheme, token) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return None;
+    }
+    let decoded = decode(token)?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((String::from(user), String::from(password)))
+}
+
+fn unquote(word: &str) -> String {
+    String::from(word.trim_matches('"'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn found(user: &str, password: &str) -> Option<(String, String)> {
+        Some((String::from(user), String::from(password)))
+    }
+
+    #[test]
+    fn pairs_user_and_pass_across_segments() {
+        let mut session = Session::default();
+        assert_eq!(session.lines(b"USER alice\r\nPA"), None);
+        assert_eq!(
+            session.lines(b"SS my secret\r\n"),
+            found("alice", "my secret")
+        );
+        // A PASS without a USER before it
+        assert_eq!(session.lines(b"PASS secret\r\n"), found("?", "secret"));
+    }
+
+    #[test]
+    fn reads_a_tagged_imap_login() {
+        let mut session = Session::default();
+        assert_eq!(
+            session.lines(b"a001 LOGIN alice \"secret\"\r\n"),
+            found("alice", "secret")
+        );
+        // AUTH as the first word is SMTP, not an IMAP tag
+        assert_eq!(session.lines(b"AUTH LOGIN\r\n"), None);
+        assert!(session.expect == Expect::LoginUser);
+    }
+
+    #[test]
+    fn decodes_auth_plain_inline_and_in_two_steps() {
+        let mut session = Session::default();
+        assert_eq!(
+            session.lines(b"AUTH PLAIN AGFsaWNlAHNlY3JldA==\r\n"),
+            found("alice", "secret")
+        );
+        assert_eq!(session.lines(b"a002 AUTHENTICATE PLAIN\r\n"), None);
+        assert_eq!(
+            session.lines(b"AGFsaWNlAHNlY3JldA==\r\n"),
+            found("alice", "secret")
+        );
+        assert!(session.expect == Expect::Command);
+    }
+
+    #[test]
+    fn decodes_auth_login() {
+        let mut session = Session::default();
+        assert_eq!(session.lines(b"AUTH LOGIN\r\nYWxpY2U=\r\n"), None);
+        assert_eq!(session.lines(b"c2VjcmV0\r\n"), found("alice", "secret"));
+        // With the username on the command line
+        assert_eq!(session.lines(b"AUTH LOGIN YWxpY2U=\r\n"), None);
+        assert_eq!(session.lines(b"c2VjcmV0\r\n"), found("alice", "secret"));
+    }
+
+    #[test]
+    fn follows_telnet_keystrokes_after_the_prompts() {
+        let mut session = Session::default();
+        // Typed before any prompt, with the client's option negotiation
+        assert_eq!(
+            session.keystrokes(b"\xff\xfd\x01\xff\xfa\x18\x00xterm\xff\xf0ls\r"),
+            None
+        );
+        session.prompt(b"\r\nlogin: ");
+        // One keystroke at a time, with a typo taken back
+        for key in b"alx\x7fice\r" {
+            assert_eq!(session.keystrokes(&[*key]), None);
+        }
+        session.prompt(b"Password:");
+        assert_eq!(
+            session.keystrokes(b"sex\x08cret\r\n"),
+            found("alice", "secret")
+        );
+        assert!(session.expect == Expect::Command);
+    }
+
+    #[test]
+    fn reads_basic_authorization() {
+        assert_eq!(basic("Basic YWxpY2U6c2VjcmV0"), found("alice", "secret"));
+        assert_eq!(basic("Bearer YWxpY2U6c2VjcmV0"), None);
+        assert_eq!(basic("Basic YWxpY2U="), None);
+    }
+
+    #[test]
+    fn masks_the_password_unless_asked() {
+        let credential = Credential {
+            protocol: String::from("FTP"),
+            client: "10.0.0.5".parse().unwrap(),
+            server: "10.0.0.21".parse().unwrap(),
+            username: String::from("alice"),
+            password: String::from("secret"),
+        };
+        assert_eq!(
+            credential.reason(false),
+            "CLEARTEXT_CREDENTIALS (FTP 10.0.0.5 -> 10.0.0.21 user alice password ********)"
+        );
+        assert_eq!(
+            credential.reason(true),
+            "CLEARTEXT_CREDENTIALS (FTP 10.0.0.5 -> 10.0.0.21 user alice password secret)"
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use crate::detect::{self, ConfigError};
+use crate::packet::application::Application;
+use crate::packet::dhcp::{Dhcp, MessageType};
+use crate::packet::ethernet::MacAddr;
+use crate::packet::Decoded;
+use std::net::{IpAddr, Ipv4Addr};
+
+/// The DHCP servers allowed on the segment, from the optional `dhcp_servers`
+/// (server identifiers) and `dhcp_server_macs` lists of the host config
+pub struct DhcpPolicy {
+    servers: Option<Vec<Ipv4Addr>>,
+    macs: Option<Vec<[u8; 6]>>,
+}
+
+impl DhcpPolicy {
+    pub fn new(config: &serde_yaml::Value) -> Result<DhcpPolicy, ConfigError> {
+        let macs: Option<Vec<MacAddr>> = detect::list(config, "dhcp_server_macs")?;
+        Ok(DhcpPolicy {
+            servers: detect::list(config, "dhcp_servers")?,
+            macs: macs.map(|macs| macs.iter().map(MacAddr::octets).collect()),
+        })
+    }
+
+    /// A reason when an OFFER or ACK comes from a server that is not allowed
+    pub fn check(&self, packet: &Decoded) -> Option<String> {
+        let dhcp: &Dhcp = match packet.transport?.application.as_ref()? {
+            Application::Dhcp(dhcp) => dhcp,
+            _ => return None,
+        };
+        if !matches!(
+            dhcp.message_type,
+            Some(MessageType::Offer | MessageType::Ack)
+        ) {
+            return None;
+        }
+        // Without a server identifier the server can only be known by its address
+        let server = match (dhcp.server_id, packet.ip.src) {
+            (Some(id), _) => Some(id),
+            (None, IpAddr::V4(src)) => Some(src),
+            _ => None,
+        };
+        let unknown_server = match (&self.servers, server) {
+            (Some(servers), Some(server)) => !servers.contains(&server),
+            _ => false,
+        };
+        let mac = packet.eth.src.octets();
+        let unknown_mac = match &self.macs {
+            Some(macs) => !macs.contains(&mac),
+            None => false,
+        };
+        if !unknown_server && !unknown_mac {
+            return None;
+        }
+        Some(format!(
+            "ROGUE_DHCP_SERVER ({} {})",
+            server.map_or(String::from("?"), |s| s.to_string()),
+            packet.eth.src
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::ethernet::Ethernet;
+    use crate::packet::ip::IP;
+    use crate::packet::transport::Transport;
+
+    /// A DHCP message of `message_type` from 00:11:22:33:44:`mac` at
+    /// 10.0.0.`src`, naming 10.0.0.`server` as its server identifier if any
+    fn message(message_type: u8, mac: u8, src: u8, server: Option<u8>) -> Vec<u8> {
+        let mut dhcp = vec![2, 1, 6, 0, 0, 0, 0, 1];
+        dhcp.extend([0; 8]);
+        dhcp.extend([10, 0, 0, 20]);
+        dhcp.extend([0; 8]);
+        dhcp.extend([0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb]);
+        dhcp.extend([0; 10 + 64 + 128]);
+        dhcp.extend([0x63, 0x82, 0x53, 0x63, 53, 1, message_type]);
+        if let Some(server) = server {
+            dhcp.extend([54, 4, 10, 0, 0, server]);
+        }
+        dhcp.push(255);
+        let mut frame = hex::decode("ffffffffffff0011223344000800").unwrap();
+        frame[11] = mac;
+        frame.extend([0x45, 0, 0, 0, 0, 0, 0, 0, 64, 17, 0, 0, 10, 0, 0, src]);
+        frame[16..18].copy_from_slice(&(28 + dhcp.len() as u16).to_be_bytes());
+        frame.extend([255, 255, 255, 255, 0, 67, 0, 68]);
+        frame.extend((8 + dhcp.len() as u16).to_be_bytes());
+        frame.extend([0, 0]);
+        frame.extend(dhcp);
+        frame
+    }
+
+    fn check(policy: &DhcpPolicy, frame: Vec<u8>) -> Option<String> {
+        let eth = Ethernet::try_from(frame).unwrap();
+        let ip = IP::new(&eth.payload, eth.ethertype).unwrap();
+        let transport = Transport::new(ip.payload, &ip.protocol).unwrap();
+        let decoded = Decoded {
+            len: 0,
+            eth: &eth,
+            ip: &ip,
+            transport: transport.as_ref(),
+            icmp: None,
+            arp: None,
+        };
+        policy.check(&decoded)
+    }
+
+    fn policy(config: &str) -> DhcpPolicy {
+        DhcpPolicy::new(&serde_yaml::from_str(config).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn flags_offers_and_acks_from_unlisted_servers() {
+        let policy = policy("dhcp_servers: [10.0.0.1]");
+        assert_eq!(check(&policy, message(2, 1, 1, Some(1))), None);
+        assert_eq!(
+            check(&policy, message(2, 9, 9, Some(9))),
+            Some(String::from(
+                "ROGUE_DHCP_SERVER (10.0.0.9 00:11:22:33:44:09)"
+            ))
+        );
+        // Without a server identifier the source address stands in for it
+        assert!(check(&policy, message(5, 9, 9, None)).is_some());
+        assert_eq!(check(&policy, message(5, 9, 1, None)), None);
+        // Clients asking are never servers
+        assert_eq!(check(&policy, message(1, 9, 9, Some(9))), None);
+        assert_eq!(check(&policy, message(3, 9, 9, Some(9))), None);
+    }
+
+    #[test]
+    fn flags_servers_behind_unlisted_macs() {
+        let policy = policy("dhcp_server_macs: ['00:11:22:33:44:01']");
+        assert_eq!(check(&policy, message(2, 1, 9, Some(9))), None);
+        assert_eq!(
+            check(&policy, message(5, 2, 1, Some(1))),
+            Some(String::from(
+                "ROGUE_DHCP_SERVER (10.0.0.1 00:11:22:33:44:02)"
+            ))
+        );
+    }
+
+    #[test]
+    fn trusts_every_server_without_a_list() {
+        let policy = DhcpPolicy::new(&serde_yaml::Value::Null).unwrap();
+        assert_eq!(check(&policy, message(2, 9, 9, Some(9))), None);
+    }
+
+    #[test]
+    fn reports_a_typo_in_the_lists() {
+        let error = |config: &str| {
+            DhcpPolicy::new(&serde_yaml::from_str(config).unwrap())
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            error("dhcp_server_macs: ['00:11:22:33:44']"),
+            "Invalid dhcp_server_macs in config: '00:11:22:33:44' Invalid string length"
+        );
+        assert_eq!(
+            error("dhcp_servers: [10.0.0.256]"),
+            "Invalid dhcp_servers in config: '10.0.0.256' invalid IPv4 address syntax"
+        );
+        assert!(error("dhcp_servers: 10.0.0.1")
+            .starts_with("Invalid dhcp_servers in config: expected a list"));
+        assert!(error("dhcp_server_macs: [1]")
+            .starts_with("Invalid dhcp_server_macs in config: expected a string"));
+    }
+}
@@ -0,0 +1,397 @@
+use crate::packet::application::Application;
+use crate::packet::dns::Dns;
+use crate::packet::Decoded;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+/// Defaults for the optional per-rule config keys, `dns_window` is the
+/// seconds the volume rules count over
+const WINDOW: f64 = 60.0;
+const MAX_LABEL_LENGTH: usize = 50;
+const LABEL_ENTROPY: f64 = 3.8;
+const TXT_THRESHOLD: usize = 20;
+const SUBDOMAIN_THRESHOLD: usize = 50;
+const NXDOMAIN_THRESHOLD: usize = 20;
+
+/// Shorter labels are too short for their entropy to mean much
+const ENTROPY_MIN_LENGTH: usize = 16;
+
+/// Parent domains and clients followed at once, all are dropped when there are more
+const MAX_ENTRIES: usize = 65536;
+
+/// Public suffixes of two labels, under which registered domains take three
+const SECOND_LEVEL_SUFFIXES: &[&str] = &[
+    "ac.jp", "ac.uk", "co.id", "co.il", "co.in", "co.jp", "co.kr", "co.nz", "co.th", "co.uk",
+    "co.za", "com.ar", "com.au", "com.br", "com.cn", "com.eg", "com.hk", "com.mx", "com.my",
+    "com.ng", "com.ph", "com.pk", "com.sa", "com.sg", "com.tr", "com.tw", "com.ua", "com.vn",
+    "edu.au", "edu.cn", "gov.au", "gov.cn", "gov.uk", "ltd.uk", "me.uk", "ne.jp", "net.au",
+    "net.br", "net.cn", "net.in", "net.nz", "or.jp", "or.kr", "org.au", "org.br", "org.cn",
+    "org.in", "org.nz", "org.uk", "org.za", "plc.uk",
+];
+
+const TYPE_NULL: u16 = 10;
+const TYPE_TXT: u16 = 16;
+const NXDOMAIN: u8 = 3;
+
+/// What one parent domain was asked for in the current window
+struct Parent {
+    since: f64,
+    subdomains: HashSet<String>,
+    txt: usize,
+    /// Rules already reported this window
+    reported: HashSet<&'static str>,
+}
+
+/// The NXDOMAIN answers one client got in the current window
+struct Client {
+    since: f64,
+    nxdomains: usize,
+    parents: HashSet<String>,
+}
+
+/// Watches queries for the shapes DNS tunnels and domain generation
+/// algorithms leave, each rule reported once per parent domain and window
+pub struct DnsWatch {
+    window: f64,
+    max_label_length: usize,
+    label_entropy: f64,
+    txt_threshold: usize,
+    subdomain_threshold: usize,
+    nxdomain_threshold: usize,
+    parents: HashMap<String, Parent>,
+    clients: HashMap<IpAddr, Client>,
+}
+
+impl DnsWatch {
+    pub fn new(config: &serde_yaml::Value) -> DnsWatch {
+        let threshold =
+            |key: &str, default: usize| config[key].as_u64().map_or(default, |n| n as usize);
+        DnsWatch {
+            window: config["dns_window"].as_f64().unwrap_or(WINDOW),
+            max_label_length: threshold("dns_max_label_length", MAX_LABEL_LENGTH),
+            label_entropy: config["dns_label_entropy"]
+                .as_f64()
+                .unwrap_or(LABEL_ENTROPY),
+            txt_threshold: threshold("dns_txt_threshold", TXT_THRESHOLD),
+            subdomain_threshold: threshold("dns_subdomain_threshold", SUBDOMAIN_THRESHOLD),
+            nxdomain_threshold: threshold("dns_nxdomain_threshold", NXDOMAIN_THRESHOLD),
+            parents: HashMap::new(),
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Reasons this DNS message looks like tunnelling or DGA lookups
+    pub fn check(&mut self, packet: &Decoded, time: f64) -> Vec<String> {
+        let dns: &Dns = match packet.transport.and_then(|t| t.application.as_ref()) {
+            Some(Application::Dns(dns)) => dns,
+            _ => return Vec::new(),
+        };
+        match dns.response {
+            true => self.answer(dns, packet.ip.dst, time),
+            false => self.query(dns, time),
+        }
+    }
+
+    fn query(&mut self, dns: &Dns, time: f64) -> Vec<String> {
+        let mut reasons = Vec::new();
+        for question in &dns.questions {
+            let (subdomain, parent) = match split(&question.name) {
+                Some(split) => split,
+                None => continue,
+            };
+            if !self.parents.contains_key(&parent) && self.parents.len() >= MAX_ENTRIES {
+                self.parents.clear();
+            }
+            let window = self.window;
+            let fresh = || Parent {
+                since: time,
+                subdomains: HashSet::new(),
+                txt: 0,
+                reported: HashSet::new(),
+            };
+            let state = self.parents.entry(parent.clone()).or_insert_with(fresh);
+            if time - state.since > window {
+                *state = fresh();
+            }
+            let mut found: Vec<(&str, String)> = Vec::new();
+            let labels: Vec<&str> = subdomain.split('.').filter(|l| !l.is_empty()).collect();
+            if let Some(long) = labels.iter().find(|l| l.len() > self.max_label_length) {
+                let details = format!("{} character label under {}", long.len(), parent);
+                found.push(("DNS_LONG_LABEL", details));
+            }
+            let high = labels
+                .iter()
+                .filter(|l| l.len() >= ENTROPY_MIN_LENGTH)
+                .map(|l| entropy(l))
+                .fold(0.0, f64::max);
+            if high >= self.label_entropy {
+                let details = format!("label entropy {:.2} under {}", high, parent);
+                found.push(("DNS_HIGH_ENTROPY_LABEL", details));
+            }
+            if question.qtype == TYPE_TXT || question.qtype == TYPE_NULL {
+                state.txt += 1;
+                if state.txt >= self.txt_threshold {
+                    let details = format!(
+                        "{} TXT/NULL queries for {} in {:.0}s",
+                        state.txt, parent, window
+                    );
+                    found.push(("DNS_TXT_VOLUME", details));
+                }
+            }
+            // Counting further than the threshold tells nothing more
+            if !subdomain.is_empty() && state.subdomains.len() < self.subdomain_threshold {
+                state.subdomains.insert(subdomain);
+                if state.subdomains.len() >= self.subdomain_threshold {
+                    let details = format!(
+                        "{} unique subdomains of {} in {:.0}s",
+                        state.subdomains.len(),
+                        parent,
+                        window
+                    );
+                    found.push(("DNS_MANY_SUBDOMAINS", details));
+                }
+            }
+            for (rule, details) in found {
+                if state.reported.insert(rule) {
+                    reasons.push(format!("{} ({})", rule, details));
+                }
+            }
+        }
+        reasons
+    }
+
+    fn answer(&mut self, dns: &Dns, client: IpAddr, time: f64) -> Vec<String> {
+        if dns.rcode != NXDOMAIN {
+            return Vec::new();
+        }
+        let parent = match dns.questions.first().and_then(|q| parent(&q.name)) {
+            Some(parent) => parent,
+            None => return Vec::new(),
+        };
+        if !self.clients.contains_key(&client) && self.clients.len() >= MAX_ENTRIES {
+            self.clients.clear();
+        }
+        let state = self.clients.entry(client).or_insert_with(|| Client {
+            since: time,
+            nxdomains: 0,
+            parents: HashSet::new(),
+        });
+        if time - state.since > self.window {
+            state.since = time;
+            state.nxdomains = 0;
+            state.parents.clear();
+        }
+        state.nxdomains += 1;
+        // Reported once, when the client first reaches the threshold this window
+        if state.nxdomains > self.nxdomain_threshold {
+            return Vec::new();
+        }
+        state.parents.insert(parent.clone());
+        if state.nxdomains < self.nxdomain_threshold {
+            return Vec::new();
+        }
+        vec![format!(
+            "DNS_NXDOMAIN_STORM ({} NXDOMAIN answers to {} in {:.0}s across {} parent domains, last {})",
+            state.nxdomains,
+            client,
+            self.window,
+            state.parents.len(),
+            parent
+        )]
+    }
+}
+
+/// The registered domain a name sits under, counting the `co.uk` style
+/// suffixes listed above as one label
+fn parent(name: &str) -> Option<String> {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let labels: Vec<&str> = name.split('.').collect();
+    if labels.len() < 2 {
+        return None;
+    }
+    // Local and reverse names are neither tunnels nor generated
+    if matches!(labels[labels.len() - 1], "local" | "arpa") {
+        return None;
+    }
+    let suffix = labels[labels.len() - 2..].join(".");
+    let keep = match SECOND_LEVEL_SUFFIXES.contains(&suffix.as_str()) && labels.len() >= 3 {
+        true => 3,
+        false => 2,
+    };
+    Some(labels[labels.len() - keep..].join("."))
+}
+
+/// The labels in front of the parent domain, empty for the parent itself,
+/// and the parent
+fn split(name: &str) -> Option<(String, String)> {
+    let parent = parent(name)?;
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let subdomain = name[..name.len() - parent.len()].trim_end_matches('.');
+    Some((String::from(subdomain), parent))
+}
+
+/// Shannon entropy in bits per character
+fn entropy(label: &str) -> f64 {
+    let mut counts: HashMap<u8, usize> = HashMap::new();
+    for byte in label.to_ascii_lowercase().bytes() {
+        *counts.entry(byte).or_default() += 1;
+    }
+    let len = label.len() as f64;
+    counts
+        .values()
+        .map(|n| {
+            let p = *n as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::ethernet::Ethernet;
+    use crate::packet::ip::IP;
+    use crate::packet::transport::Transport;
+
+    /// A query from 10.0.0.5 to 10.0.0.53 for `name`, or its answer with `rcode`
+    fn message(name: &str, qtype: u16, rcode: Option<u8>) -> Vec<u8> {
+        let mut dns = match rcode {
+            Some(rcode) => vec![0, 1, 0x81, 0x80 | rcode, 0, 1, 0, 0, 0, 0, 0, 0],
+            None => vec![0, 1, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0],
+        };
+        for label in name.split('.') {
+            dns.push(label.len() as u8);
+            dns.extend(label.as_bytes());
+        }
+        dns.push(0);
+        dns.extend(qtype.to_be_bytes());
+        dns.extend([0, 1]);
+        let mut ports = vec![0x9c, 0x40, 0, 53];
+        let mut hosts = vec![10, 0, 0, 5, 10, 0, 0, 53];
+        if rcode.is_some() {
+            ports.rotate_left(2);
+            hosts.rotate_left(4);
+        }
+        let mut frame = hex::decode("ffffffffffff0011223344550800").unwrap();
+        frame.extend([0x45, 0, 0, 0, 0, 0, 0x40, 0, 0x40, 17, 0, 0]);
+        frame[16..18].copy_from_slice(&(28 + dns.len() as u16).to_be_bytes());
+        frame.extend(hosts);
+        frame.extend(ports);
+        frame.extend((8 + dns.len() as u16).to_be_bytes());
+        frame.extend([0, 0]);
+        frame.extend(dns);
+        frame
+    }
+
+    fn check(watch: &mut DnsWatch, frame: Vec<u8>, time: f64) -> Vec<String> {
+        let eth = Ethernet::try_from(frame).unwrap();
+        let ip = IP::new(&eth.payload, eth.ethertype).unwrap();
+        let transport = Transport::new(ip.payload, &ip.protocol).unwrap();
+        let decoded = Decoded {
+            len: 0,
+            eth: &eth,
+            ip: &ip,
+            transport: transport.as_ref(),
+            icmp: None,
+            arp: None,
+        };
+        watch.check(&decoded, time)
+    }
+
+    fn rules(reasons: &[String]) -> Vec<&str> {
+        reasons
+            .iter()
+            .map(|r| r.split(' ').next().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn parent_keeps_listed_suffixes_only() {
+        assert_eq!(parent("www.example.com").as_deref(), Some("example.com"));
+        assert_eq!(
+            parent("a.b.example.co.uk.").as_deref(),
+            Some("example.co.uk")
+        );
+        assert_eq!(
+            parent("WWW.Example.COM.AU").as_deref(),
+            Some("example.com.au")
+        );
+        // Short registered domains under country codes are not suffixes
+        assert_eq!(parent("mzxw6ytb.abc.io").as_deref(), Some("abc.io"));
+        assert_eq!(parent("mzxw6ytb.c2.me").as_deref(), Some("c2.me"));
+        assert_eq!(parent("co.uk").as_deref(), Some("co.uk"));
+        assert_eq!(parent("com"), None);
+        assert_eq!(parent("printer.local"), None);
+        assert_eq!(parent("5.0.0.10.in-addr.arpa"), None);
+    }
+
+    #[test]
+    fn split_takes_the_labels_in_front() {
+        assert_eq!(
+            split("a.b.example.co.uk."),
+            Some((String::from("a.b"), String::from("example.co.uk")))
+        );
+        assert_eq!(
+            split("example.com"),
+            Some((String::new(), String::from("example.com")))
+        );
+    }
+
+    #[test]
+    fn entropy_in_bits_per_character() {
+        assert_eq!(entropy("aaaaaaaa"), 0.0);
+        assert_eq!(entropy("abcdABCD"), 2.0);
+        assert_eq!(entropy("0123456789abcdef"), 4.0);
+    }
+
+    #[test]
+    fn flags_tunnel_shaped_names() {
+        let mut watch = DnsWatch::new(&serde_yaml::Value::Null);
+        let long = "a".repeat(MAX_LABEL_LENGTH + 1);
+        let reasons = check(
+            &mut watch,
+            message(&format!("{}.t.example.com", long), 1, None),
+            0.0,
+        );
+        assert_eq!(rules(&reasons), vec!["DNS_LONG_LABEL"]);
+        let random = "q3v9zk2lmx8w7rtp4hjn";
+        let reasons = check(
+            &mut watch,
+            message(&format!("{}.t.c2.me", random), 1, None),
+            0.0,
+        );
+        assert_eq!(rules(&reasons), vec!["DNS_HIGH_ENTROPY_LABEL"]);
+        assert!(check(&mut watch, message("www.example.com", 1, None), 0.0).is_empty());
+    }
+
+    #[test]
+    fn volume_rules_cross_their_thresholds_once() {
+        let config =
+            "{dns_txt_threshold: 3, dns_subdomain_threshold: 4, dns_nxdomain_threshold: 2}";
+        let mut watch = DnsWatch::new(&serde_yaml::from_str(config).unwrap());
+        let mut found = Vec::new();
+        for n in 0..6 {
+            let name = format!("s{}.abc.io", n);
+            for reason in check(&mut watch, message(&name, TYPE_TXT, None), n as f64) {
+                found.push((n, reason));
+            }
+        }
+        let found: Vec<(i32, &str)> = found
+            .iter()
+            .map(|(n, r)| (*n, r.split(' ').next().unwrap()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![(2, "DNS_TXT_VOLUME"), (3, "DNS_MANY_SUBDOMAINS")]
+        );
+
+        let nxdomain = |name: &str| message(name, 1, Some(NXDOMAIN));
+        assert!(check(&mut watch, nxdomain("kq3b.com"), 10.0).is_empty());
+        let storm = check(&mut watch, nxdomain("zx8v.net"), 11.0);
+        assert!(storm[0].starts_with("DNS_NXDOMAIN_STORM (2 NXDOMAIN answers to 10.0.0.5"));
+        assert!(check(&mut watch, nxdomain("p0wq.org"), 12.0).is_empty());
+        // A new window starts the count over
+        let later = 11.0 + WINDOW + 1.0;
+        assert!(check(&mut watch, nxdomain("kq3b.com"), later).is_empty());
+    }
+}
@@ -0,0 +1,304 @@
+use crate::packet::ip::IP;
+use crate::packet::transport::{TcpFlags, Transport};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::IpAddr;
+
+/// Defaults for the optional `syn_flood_rate` (half-open connections a second)
+/// and `syn_flood_window` (seconds averaged over) config keys
+const RATE: f64 = 100.0;
+const WINDOW: f64 = 5.0;
+
+/// Seconds a handshake may take before it is forgotten, about what a server
+/// keeps a SYN queue entry for
+const HALF_OPEN_TIMEOUT: f64 = 75.0;
+
+/// Half-open connections remembered at once, all are dropped when there are more
+const MAX_HALF_OPEN: usize = 1 << 20;
+
+/// Sources listed in an alert
+const TOP_SOURCES: usize = 5;
+
+type Key = ((IpAddr, u16), (IpAddr, u16));
+
+/// A handshake waiting on the client's ACK
+struct HalfOpen {
+    first_seen: f64,
+    /// The latest SYN for it and how often one was sent
+    last_syn: f64,
+    syns: usize,
+    /// Whether its target counts it, as it does while the latest SYN is in the window
+    counted: bool,
+}
+
+struct Target {
+    /// Every SYN in the window and the connection it opens
+    syns: VecDeque<(f64, Key)>,
+    /// Connections opened by those SYNs still half-open
+    half_open: usize,
+    flooding: bool,
+}
+
+impl Target {
+    /// Let go of the SYNs that left the window
+    fn slide(&mut self, half_open: &mut HashMap<Key, HalfOpen>, now: f64, window: f64) {
+        while let Some(&(seen, key)) = self.syns.front() {
+            if now - seen <= window {
+                break;
+            }
+            self.syns.pop_front();
+            if let Some(entry) = half_open.get_mut(&key) {
+                // A retransmission later in the window still counts it
+                if entry.counted && entry.last_syn == seen {
+                    entry.counted = false;
+                    self.half_open -= 1;
+                }
+            }
+        }
+    }
+}
+
+/// A flood of handshakes that were never completed against one service
+pub struct SynFlood {
+    pub target: (IpAddr, u16),
+    pub window: f64,
+    pub half_open: usize,
+    pub syns: usize,
+    /// The sources with the most half-open connections, most first
+    pub top: Vec<(IpAddr, usize)>,
+    pub sources: usize,
+    /// Many sources that never retry their SYN are unlikely to be real stacks
+    pub spoofed: bool,
+}
+
+impl fmt::Display for SynFlood {
+    /// `SYN flood on 10.0.0.2:80, 900 half-open in 5.0s from 880 sources ...`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "SYN flood on {}:{}, {} half-open in {:.1}s ({} SYNs) from {} sources",
+            self.target.0, self.target.1, self.half_open, self.window, self.syns, self.sources
+        )?;
+        let top: Vec<String> = self
+            .top
+            .iter()
+            .map(|(ip, n)| format!("{} x{}", ip, n))
+            .collect();
+        write!(f, ", top {}", top.join(", "))?;
+        if self.spoofed {
+            write!(f, ", sources look spoofed")?;
+        }
+        Ok(())
+    }
+}
+
+/// Tracks TCP handshakes per service and flags SYNs that outpace the ACKs
+pub struct SynFloods {
+    rate: f64,
+    window: f64,
+    half_open: HashMap<Key, HalfOpen>,
+    targets: HashMap<(IpAddr, u16), Target>,
+    last_expired: f64,
+}
+
+impl SynFloods {
+    pub fn new(config: &serde_yaml::Value) -> SynFloods {
+        SynFloods {
+            rate: config["syn_flood_rate"].as_f64().unwrap_or(RATE),
+            window: config["syn_flood_window"].as_f64().unwrap_or(WINDOW),
+            half_open: HashMap::new(),
+            targets: HashMap::new(),
+            last_expired: 0.0,
+        }
+    }
+
+    /// Feed a TCP segment, returning a flood as soon as one starts
+    pub fn add(&mut self, ip: &IP, transport: &Transport, time: f64) -> Option<SynFlood> {
+        self.expire(time);
+        let flags = transport.tcp.as_ref()?.flags;
+        let src = (ip.src, transport.src_port);
+        let dst = (ip.dst, transport.dst_port);
+        if flags.has(TcpFlags::RST) {
+            // A closed port turns the handshake down, that is no flood
+            self.close(&(src, dst));
+            self.close(&(dst, src));
+            return None;
+        }
+        if !flags.has(TcpFlags::SYN) {
+            if flags.has(TcpFlags::ACK) {
+                self.close(&(src, dst));
+            }
+            return None;
+        }
+        if flags.has(TcpFlags::ACK) {
+            return None;
+        }
+        if self.half_open.len() >= MAX_HALF_OPEN {
+            self.half_open.clear();
+            self.targets.clear();
+        }
+        let key = (src, dst);
+        let entry = self.half_open.entry(key).or_insert(HalfOpen {
+            first_seen: time,
+            last_syn: time,
+            syns: 0,
+            counted: false,
+        });
+        entry.last_syn = time;
+        entry.syns += 1;
+        let window = self.window;
+        let target = self.targets.entry(dst).or_insert_with(|| Target {
+            syns: VecDeque::new(),
+            half_open: 0,
+            flooding: false,
+        });
+        target.syns.push_back((time, key));
+        if !entry.counted {
+            entry.counted = true;
+            target.half_open += 1;
+        }
+        target.slide(&mut self.half_open, time, window);
+        let rate = target.half_open as f64 / window;
+        if target.flooding {
+            // Over once the rate is well below the threshold again
+            target.flooding = rate >= self.rate / 2.0;
+            return None;
+        }
+        if rate < self.rate {
+            return None;
+        }
+        target.flooding = true;
+        // The breakdown by source is only worked out for the alert
+        let mut counts: HashMap<IpAddr, usize> = HashMap::new();
+        let mut retries = 0;
+        for (seen, key) in &target.syns {
+            match self.half_open.get(key) {
+                Some(entry) if entry.counted && entry.last_syn == *seen => {
+                    *counts.entry(key.0 .0).or_default() += 1;
+                    retries += entry.syns - 1;
+                }
+                _ => {}
+            }
+        }
+        let half_open = target.half_open;
+        let mut top: Vec<(IpAddr, usize)> = counts.iter().map(|(ip, n)| (*ip, *n)).collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        top.truncate(TOP_SOURCES);
+        Some(SynFlood {
+            target: dst,
+            window,
+            half_open,
+            syns: target.syns.len(),
+            top,
+            sources: counts.len(),
+            spoofed: counts.len() * 2 > half_open && retries * 10 < half_open,
+        })
+    }
+
+    /// Is `target` under a flood right now
+    pub fn is_flooding(&self, target: &(IpAddr, u16)) -> bool {
+        self.targets.get(target).is_some_and(|t| t.flooding)
+    }
+
+    /// The handshake is over one way or another
+    fn close(&mut self, key: &Key) {
+        if let Some(entry) = self.half_open.remove(key) {
+            if let Some(target) = self.targets.get_mut(&key.1).filter(|_| entry.counted) {
+                target.half_open -= 1;
+            }
+        }
+    }
+
+    fn expire(&mut self, now: f64) {
+        // Looking through every handshake once a second is plenty
+        if now - self.last_expired < 1.0 {
+            return;
+        }
+        self.last_expired = now;
+        let window = self.window;
+        for target in self.targets.values_mut() {
+            target.slide(&mut self.half_open, now, window);
+        }
+        let targets = &mut self.targets;
+        self.half_open.retain(|key, entry| {
+            let keep = now - entry.first_seen <= HALF_OPEN_TIMEOUT;
+            if !keep && entry.counted {
+                if let Some(target) = targets.get_mut(&key.1) {
+                    target.half_open -= 1;
+                }
+            }
+            keep
+        });
+        self.targets.retain(|_, target| !target.syns.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::protocol::Layer3;
+
+    const SYN: u8 = TcpFlags::SYN;
+    const ACK: u8 = TcpFlags::ACK;
+
+    /// A TCP segment from 10.0.1.`client`:40000 to 10.0.0.2:80
+    fn packet(client: u8, flags: u8) -> Vec<u8> {
+        let mut data = hex::decode("450000280000400040060000").unwrap();
+        data.extend([10, 0, 1, client, 10, 0, 0, 2, 0x9c, 0x40, 0, 80]);
+        data.extend([0, 0, 0, 1, 0, 0, 0, 0, 0x50, flags, 4, 0, 0, 0, 0, 0]);
+        data
+    }
+
+    fn add(floods: &mut SynFloods, client: u8, flags: u8, time: f64) -> Option<SynFlood> {
+        let data = packet(client, flags);
+        let ip = IP::new(&data, Layer3::IPv4).unwrap();
+        let transport = Transport::new(ip.payload, &ip.protocol).unwrap().unwrap();
+        floods.add(&ip, &transport, time)
+    }
+
+    fn floods() -> SynFloods {
+        SynFloods::new(&serde_yaml::from_str("{syn_flood_rate: 10, syn_flood_window: 1}").unwrap())
+    }
+
+    #[test]
+    fn alerts_once_as_half_open_connections_pile_up() {
+        let mut floods = floods();
+        let target = (IpAddr::from([10, 0, 0, 2]), 80);
+        for client in 1..10 {
+            assert!(add(&mut floods, client, SYN, client as f64 / 100.0).is_none());
+        }
+        let flood = add(&mut floods, 10, SYN, 0.1).unwrap();
+        assert_eq!((flood.half_open, flood.syns, flood.sources), (10, 10, 10));
+        assert!(flood.spoofed && floods.is_flooding(&target));
+        assert!(add(&mut floods, 11, SYN, 0.2).is_none());
+    }
+
+    #[test]
+    fn completed_and_refused_handshakes_are_no_flood() {
+        let mut floods = floods();
+        for client in 1..100 {
+            add(&mut floods, client, SYN, 0.0);
+            let answer = match client % 2 {
+                0 => ACK,
+                _ => TcpFlags::RST,
+            };
+            assert!(add(&mut floods, client, answer, 0.0).is_none());
+        }
+    }
+
+    #[test]
+    fn syns_leave_the_window() {
+        let mut floods = floods();
+        for client in 1..10 {
+            add(&mut floods, client, SYN, 0.0);
+        }
+        // The first nine are still half-open, but too long ago to count
+        for client in 10..19 {
+            assert!(add(&mut floods, client, SYN, 2.0).is_none());
+        }
+        // A retransmission brings one back into the window
+        let flood = add(&mut floods, 1, SYN, 2.5).unwrap();
+        assert_eq!((flood.half_open, flood.syns), (10, 10));
+        assert!(!flood.spoofed);
+    }
+}
@@ -0,0 +1,258 @@
+use crate::packet::Decoded;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+
+/// Defaults for the optional `icmp_max_payload` (bytes), `icmp_window`
+/// (seconds) and `icmp_rate_threshold` (echo requests a second) config keys
+const MAX_PAYLOAD: usize = 128;
+const WINDOW: f64 = 10.0;
+const RATE_THRESHOLD: f64 = 10.0;
+
+/// Seconds a request waits for its reply, and a pair may stay quiet before
+/// its rules can be reported again
+const REPLY_TIMEOUT: f64 = 10.0;
+const IDLE: f64 = 60.0;
+
+/// Pairs and outstanding requests followed at once, all are dropped when there are more
+const MAX_ENTRIES: usize = 65536;
+
+/// Where ping tools put a timestamp before their fill pattern
+const TIMESTAMP_LEN: usize = 16;
+
+/// Echo traffic one host sends another
+struct Pair {
+    last_seen: f64,
+    since: f64,
+    requests: usize,
+    /// Rules already reported while the pair keeps talking
+    reported: HashSet<&'static str>,
+}
+
+/// Looks for data hidden in ICMP echo, the way ptunnel and icmpsh carry it
+pub struct IcmpWatch {
+    max_payload: usize,
+    window: f64,
+    rate_threshold: f64,
+    pairs: HashMap<(IpAddr, IpAddr), Pair>,
+    /// Hash of the data each request carried and when it was sent, by
+    /// requester, responder, identifier and sequence
+    requests: HashMap<(IpAddr, IpAddr, u16, u16), (u64, f64)>,
+}
+
+impl IcmpWatch {
+    pub fn new(config: &serde_yaml::Value) -> IcmpWatch {
+        IcmpWatch {
+            max_payload: config["icmp_max_payload"]
+                .as_u64()
+                .map_or(MAX_PAYLOAD, |n| n as usize),
+            window: config["icmp_window"].as_f64().unwrap_or(WINDOW),
+            rate_threshold: config["icmp_rate_threshold"]
+                .as_f64()
+                .unwrap_or(RATE_THRESHOLD),
+            pairs: HashMap::new(),
+            requests: HashMap::new(),
+        }
+    }
+
+    /// Reasons this echo request or reply looks like a covert channel
+    pub fn check(&mut self, packet: &Decoded, time: f64) -> Vec<String> {
+        let icmp = match packet.icmp {
+            Some(icmp) => icmp,
+            None => return Vec::new(),
+        };
+        let (identifier, sequence) = match (icmp.identifier, icmp.sequence) {
+            (Some(identifier), Some(sequence)) => (identifier, sequence),
+            _ => return Vec::new(),
+        };
+        let (src, dst) = (packet.ip.src, packet.ip.dst);
+        let request = icmp.is_echo_request();
+        let pair = match request {
+            true => (src, dst),
+            false => (dst, src),
+        };
+        if self.pairs.len() >= MAX_ENTRIES || self.requests.len() >= MAX_ENTRIES {
+            self.pairs.clear();
+            self.requests.clear();
+        }
+        let fresh = || Pair {
+            last_seen: time,
+            since: time,
+            requests: 0,
+            reported: HashSet::new(),
+        };
+        let state = self.pairs.entry(pair).or_insert_with(fresh);
+        if time - state.last_seen > IDLE {
+            *state = fresh();
+        }
+        state.last_seen = time;
+
+        let mut found: Vec<(&str, String)> = Vec::new();
+        let payload = icmp.payload;
+        if payload.len() > self.max_payload {
+            let details = format!("{} byte payload {} -> {}", payload.len(), src, dst);
+            found.push(("ICMP_OVERSIZED_ECHO", details));
+        }
+        if !is_ping_pattern(payload) {
+            let details = format!(
+                "{} byte payload {} -> {} matches no ping fill",
+                payload.len(),
+                src,
+                dst
+            );
+            found.push(("ICMP_UNUSUAL_PAYLOAD", details));
+        }
+        let key = (pair.0, pair.1, identifier, sequence);
+        let mut hasher = DefaultHasher::new();
+        payload.hash(&mut hasher);
+        let hash = hasher.finish();
+        if request {
+            if time - state.since > self.window {
+                state.since = time;
+                state.requests = 0;
+            }
+            state.requests += 1;
+            if state.requests as f64 >= self.rate_threshold * self.window {
+                let details = format!(
+                    "{} echo requests {} -> {} in {:.0}s",
+                    state.requests, src, dst, self.window
+                );
+                found.push(("ICMP_ECHO_RATE", details));
+            }
+            self.requests.insert(key, (hash, time));
+        } else if let Some((sent, at)) = self.requests.remove(&key) {
+            // A reply echoes the request's data unchanged
+            if sent != hash && time - at <= REPLY_TIMEOUT {
+                let details = format!(
+                    "reply {} -> {} id 0x{:04x} seq {} differs from its request",
+                    src, dst, identifier, sequence
+                );
+                found.push(("ICMP_ECHO_MISMATCH", details));
+            }
+        }
+        let mut reasons = Vec::new();
+        for (rule, details) in found {
+            if state.reported.insert(rule) {
+                reasons.push(format!("{} ({})", rule, details));
+            }
+        }
+        reasons
+    }
+}
+
+/// Whether echo data looks like what ping tools send: nothing, one byte
+/// repeated, Windows' `abcdefghijklmnopqrstuvw` or the Unix counting fill
+/// after a timestamp
+fn is_ping_pattern(payload: &[u8]) -> bool {
+    if payload.len() <= TIMESTAMP_LEN || payload.iter().all(|b| *b == payload[0]) {
+        return true;
+    }
+    let windows = payload
+        .iter()
+        .enumerate()
+        .all(|(i, b)| *b == b'a' + (i % 23) as u8);
+    let unix = payload
+        .iter()
+        .enumerate()
+        .skip(TIMESTAMP_LEN)
+        .all(|(i, b)| *b == i as u8);
+    windows || unix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::ethernet::Ethernet;
+    use crate::packet::icmp::Icmp;
+    use crate::packet::ip::IP;
+
+    /// An echo request from 10.0.0.5 to 10.0.0.9, or the reply back
+    fn check(
+        watch: &mut IcmpWatch,
+        request: bool,
+        seq: u16,
+        data: &[u8],
+        time: f64,
+    ) -> Vec<String> {
+        let mut frame = hex::decode("ffffffffffff0011223344550800").unwrap();
+        frame.extend([0x45, 0, 0, 0, 0, 0, 0x40, 0, 0x40, 1, 0, 0]);
+        frame[16..18].copy_from_slice(&(28 + data.len() as u16).to_be_bytes());
+        let hosts = [[10, 0, 0, 5], [10, 0, 0, 9]];
+        match request {
+            true => frame.extend(hosts.concat()),
+            false => frame.extend([hosts[1], hosts[0]].concat()),
+        }
+        let icmp_type = match request {
+            true => 8,
+            false => 0,
+        };
+        frame.extend([icmp_type, 0, 0, 0, 0x12, 0x34]);
+        frame.extend(seq.to_be_bytes());
+        frame.extend(data);
+        let eth = Ethernet::try_from(frame).unwrap();
+        let ip = IP::new(&eth.payload, eth.ethertype).unwrap();
+        let icmp = Icmp::new(ip.payload, &ip.protocol).unwrap();
+        let decoded = Decoded {
+            len: 0,
+            eth: &eth,
+            ip: &ip,
+            transport: None,
+            icmp: icmp.as_ref(),
+            arp: None,
+        };
+        watch.check(&decoded, time)
+    }
+
+    fn rules(reasons: Vec<String>) -> Vec<String> {
+        reasons
+            .iter()
+            .map(|r| String::from(r.split(' ').next().unwrap()))
+            .collect()
+    }
+
+    /// What `ping` on Linux sends, a timestamp then counting bytes
+    fn unix_ping() -> Vec<u8> {
+        (0..56)
+            .map(|i| if i < 16 { 0xa5 } else { i as u8 })
+            .collect()
+    }
+
+    #[test]
+    fn ping_is_quiet() {
+        let mut watch = IcmpWatch::new(&serde_yaml::Value::Null);
+        for seq in 0..10 {
+            assert!(check(&mut watch, true, seq, &unix_ping(), seq as f64).is_empty());
+            assert!(check(&mut watch, false, seq, &unix_ping(), seq as f64).is_empty());
+        }
+    }
+
+    #[test]
+    fn flags_data_that_does_not_look_like_ping() {
+        let mut watch = IcmpWatch::new(&serde_yaml::Value::Null);
+        let command = b"uid=0(root) gid=0(root) groups=0(root)";
+        let reasons = check(&mut watch, true, 1, command, 0.0);
+        assert_eq!(rules(reasons), vec!["ICMP_UNUSUAL_PAYLOAD"]);
+        // Once while the pair keeps talking
+        assert!(check(&mut watch, true, 2, command, 1.0).is_empty());
+
+        let windows: Vec<u8> = (0..32).map(|i| b'a' + (i % 23) as u8).collect();
+        check(&mut watch, true, 3, &windows, 2.0);
+        let reasons = check(&mut watch, false, 3, &[0; 32], 2.1);
+        assert_eq!(rules(reasons), vec!["ICMP_ECHO_MISMATCH"]);
+
+        let reasons = check(&mut watch, true, 4, &[0; MAX_PAYLOAD + 1], 3.0);
+        assert_eq!(rules(reasons), vec!["ICMP_OVERSIZED_ECHO"]);
+    }
+
+    #[test]
+    fn flags_a_fast_stream_of_requests() {
+        let config = serde_yaml::from_str("{icmp_window: 5, icmp_rate_threshold: 2}").unwrap();
+        let mut watch = IcmpWatch::new(&config);
+        for seq in 1..10 {
+            assert!(check(&mut watch, true, seq, &unix_ping(), seq as f64 / 10.0).is_empty());
+        }
+        let reasons = check(&mut watch, true, 10, &unix_ping(), 1.0);
+        assert_eq!(rules(reasons), vec!["ICMP_ECHO_RATE"]);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::packet::ip::{Ipv6Extension, IP};
+
+/// Longer chains than this are not seen from well behaved stacks
+const MAX_EXTENSIONS: usize = 6;
+
+/// Reasons this packet's extension headers look suspicious, if any
+///
+/// Overlapping IPv6 fragments are caught by the fragment reassembly like IPv4 ones.
+pub fn check(ip: &IP) -> Vec<String> {
+    let mut reasons = Vec::new();
+    let header = match &ip.ipv6 {
+        Some(header) => header,
+        None => return reasons,
+    };
+    let extensions = &header.extensions;
+    if extensions.iter().any(|e| {
+        matches!(
+            e,
+            Ipv6Extension::Routing {
+                routing_type: 0,
+                ..
+            }
+        )
+    }) {
+        reasons.push(String::from("IPV6_TYPE0_ROUTING_HEADER"));
+    }
+    if extensions
+        .iter()
+        .skip(1)
+        .any(|e| matches!(e, Ipv6Extension::HopByHop))
+    {
+        reasons.push(String::from("IPV6_HOP_BY_HOP_NOT_FIRST"));
+    }
+    // Destination options may legitimately appear twice, around a routing header
+    let same = |a: &Ipv6Extension, b: &Ipv6Extension| {
+        std::mem::discriminant(a) == std::mem::discriminant(b)
+    };
+    let repeated = extensions.iter().enumerate().any(|(i, e)| {
+        let limit = if matches!(e, Ipv6Extension::DestinationOptions) {
+            2
+        } else {
+            1
+        };
+        extensions[..i].iter().filter(|o| same(o, e)).count() >= limit
+    });
+    if repeated {
+        reasons.push(String::from("IPV6_REPEATED_EXTENSION_HEADER"));
+    }
+    if extensions.len() > MAX_EXTENSIONS {
+        reasons.push(String::from("IPV6_EXTENSION_CHAIN_TOO_LONG"));
+    }
+    reasons
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::protocol::Layer3;
+
+    /// Reasons for an IPv6 packet whose extension headers are `chain`, each
+    /// an empty 8 bytes, ahead of UDP
+    fn check_chain(chain: &[u8]) -> Vec<String> {
+        let mut data = hex::decode("6000000000001140").unwrap();
+        data.extend([0x20, 0x01, 0x0d, 0xb8].repeat(8));
+        let mut next = 17;
+        let mut headers = Vec::new();
+        for kind in chain.iter().rev() {
+            headers.splice(0..0, [next, 0, 0, 0, 0, 0, 0, 0]);
+            next = *kind;
+        }
+        data[6] = next;
+        data.extend(headers);
+        data.extend([0, 53, 0, 53, 0, 8, 0, 0]);
+        let len = data.len() as u16 - 40;
+        data[4..6].copy_from_slice(&len.to_be_bytes());
+        check(&IP::new(&data, Layer3::IPv6).unwrap())
+    }
+
+    #[test]
+    fn usual_chains_pass() {
+        assert!(check_chain(&[]).is_empty());
+        assert!(check_chain(&[0, 60, 44, 60]).is_empty());
+    }
+
+    #[test]
+    fn flags_odd_chains() {
+        assert_eq!(check_chain(&[43]), vec!["IPV6_TYPE0_ROUTING_HEADER"]);
+        assert_eq!(check_chain(&[60, 0]), vec!["IPV6_HOP_BY_HOP_NOT_FIRST"]);
+        assert_eq!(check_chain(&[60, 60, 60]), vec!["IPV6_REPEATED_EXTENSION_HEADER"]);
+    }
+
+    #[test]
+    fn flags_a_chain_over_the_limit() {
+        let too_long = String::from("IPV6_EXTENSION_CHAIN_TOO_LONG");
+        assert!(!check_chain(&[0, 60, 43, 44, 51, 60]).contains(&too_long));
+        assert!(check_chain(&[0, 60, 43, 44, 51, 60, 60]).contains(&too_long));
+    }
+}
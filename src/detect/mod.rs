@@ -0,0 +1,71 @@
+pub(crate) mod arp;
+pub(crate) mod beacon;
+pub(crate) mod credentials;
+pub(crate) mod dhcp;
+pub(crate) mod dns;
+pub(crate) mod flood;
+pub(crate) mod icmp;
+pub(crate) mod ipv6;
+pub(crate) mod scan;
+pub(crate) mod tls;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A host config value a detector cannot make sense of
+#[derive(Debug)]
+pub struct ConfigError {
+    key: &'static str,
+    message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid {} in config: {}", self.key, self.message)
+    }
+}
+
+fn parse<T>(key: &'static str, value: &serde_yaml::Value) -> Result<T, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let text = value.as_str().ok_or_else(|| ConfigError {
+        key,
+        message: format!("expected a string, found {:?}", value),
+    })?;
+    text.parse().map_err(|e| ConfigError {
+        key,
+        message: format!("'{}' {}", text, e),
+    })
+}
+
+/// The optional `key` of the host config, `None` when it is not set
+pub fn value<T>(config: &serde_yaml::Value, key: &'static str) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    match &config[key] {
+        serde_yaml::Value::Null => Ok(None),
+        value => parse(key, value).map(Some),
+    }
+}
+
+/// The optional `key` list of the host config, `None` when it is not set
+pub fn list<T>(config: &serde_yaml::Value, key: &'static str) -> Result<Option<Vec<T>>, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    match &config[key] {
+        serde_yaml::Value::Null => Ok(None),
+        serde_yaml::Value::Sequence(values) => {
+            values.iter().map(|v| parse(key, v)).collect::<Result<_, _>>().map(Some)
+        }
+        value => Err(ConfigError {
+            key,
+            message: format!("expected a list, found {:?}", value),
+        }),
+    }
+}
@@ -0,0 +1,613 @@
+use crate::packet::ip::IP;
+use crate::packet::transport::{TcpFlags, Transport};
+use crate::reassembly::stream::{Direction, Segment};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::net::IpAddr;
+
+/// Defaults for the optional `scan_window`, `scan_port_threshold` and
+/// `scan_host_threshold` config keys
+const WINDOW: f64 = 60.0;
+const PORT_THRESHOLD: usize = 20;
+const HOST_THRESHOLD: usize = 50;
+
+/// Seconds a probe waits for its answer before it counts as unanswered
+const REPLY_TIMEOUT: f64 = 5.0;
+
+/// Sources followed at once, those not scanning are all dropped to make room
+/// and scanners too once they fill half of it
+const MAX_SOURCES: usize = 65536;
+
+/// Targets and unanswered probes remembered per source, a scan bigger than
+/// this is counted this far
+const MAX_TARGETS: usize = 1 << 20;
+
+/// The kinds of probe a scanner sends, named like the nmap scans using them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Probe {
+    Syn,
+    Fin,
+    Null,
+    Xmas,
+    Ack,
+    Udp,
+}
+
+impl Probe {
+    /// The probe a segment or datagram is, if any; an ACK only counts when it
+    /// is the first packet of a connection and goes towards the guessed server
+    /// side, any UDP datagram that is not an answer may be a probe
+    pub fn new(transport: &Transport, segment: Option<&Segment>) -> Option<Probe> {
+        let flags = match &transport.tcp {
+            Some(tcp) => tcp.flags,
+            None => return Some(Probe::Udp),
+        };
+        let segment = segment?;
+        match flags.0 & !(TcpFlags::ECE | TcpFlags::CWR) {
+            TcpFlags::SYN => Some(Probe::Syn),
+            TcpFlags::FIN => Some(Probe::Fin),
+            0 => Some(Probe::Null),
+            f if f == TcpFlags::FIN | TcpFlags::PSH | TcpFlags::URG => Some(Probe::Xmas),
+            TcpFlags::ACK
+                if transport.payload.is_empty()
+                    && segment.opened
+                    && segment.direction == Direction::ToServer =>
+            {
+                Some(Probe::Ack)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Probe::Syn => "SYN",
+            Probe::Fin => "FIN",
+            Probe::Null => "NULL",
+            Probe::Xmas => "Xmas",
+            Probe::Ack => "ACK",
+            Probe::Udp => "UDP",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A scan as it crosses the threshold, or summed up once it is over
+pub struct Scan {
+    pub scanner: IpAddr,
+    pub probes: BTreeSet<Probe>,
+    pub hosts: BTreeSet<IpAddr>,
+    pub ports: BTreeSet<u16>,
+    pub first_seen: f64,
+    pub last_seen: f64,
+    /// The scanner went quiet, or the capture ended
+    pub finished: bool,
+}
+
+impl Scan {
+    /// The one host scanned, for vertical scans
+    pub fn target(&self) -> Option<IpAddr> {
+        match self.hosts.len() {
+            1 => self.hosts.iter().next().copied(),
+            _ => None,
+        }
+    }
+
+    /// Whether only UDP was probed
+    pub fn is_udp(&self) -> bool {
+        self.probes.iter().all(|p| *p == Probe::Udp)
+    }
+}
+
+impl fmt::Display for Scan {
+    /// `SYN scan of 1000 ports on 10.0.0.2 in 12.3s`, `, ongoing` while it lasts
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let probes: Vec<String> = self.probes.iter().map(|p| p.to_string()).collect();
+        write!(f, "{} scan of ", probes.join("/"))?;
+        match (self.target(), self.ports.len()) {
+            (Some(host), ports) => write!(f, "{} ports on {}", ports, host),
+            (None, 1) => write!(
+                f,
+                "port {} on {} hosts",
+                self.ports.iter().next().unwrap(),
+                self.hosts.len()
+            ),
+            (None, ports) => write!(f, "{} ports on {} hosts", ports, self.hosts.len()),
+        }?;
+        write!(f, " in {:.1}s", self.last_seen - self.first_seen)?;
+        if !self.finished {
+            write!(f, ", ongoing")?;
+        }
+        Ok(())
+    }
+}
+
+/// How far back probes count and how many make a scan
+struct Limits {
+    window: f64,
+    port_threshold: usize,
+    host_threshold: usize,
+}
+
+struct Source {
+    first_seen: f64,
+    last_seen: f64,
+    probes: BTreeSet<Probe>,
+    /// Probes still waiting on an answer, with when they were sent
+    pending: HashMap<(IpAddr, u16), (f64, Probe)>,
+    /// The same in the order they were sent, for timing them out
+    sent: VecDeque<(f64, (IpAddr, u16))>,
+    /// When each (host, port) last let a probe go unanswered or refused it
+    targets: HashMap<(IpAddr, u16), f64>,
+    /// The same in the order they were counted, for sliding the window
+    counted: VecDeque<(f64, (IpAddr, u16))>,
+    /// Ports counted per host and hosts per port within the window
+    ports: HashMap<IpAddr, usize>,
+    hosts: HashMap<u16, usize>,
+    /// Over a threshold, from here on every probe belongs to the scan
+    scanning: bool,
+}
+
+impl Source {
+    fn new(time: f64) -> Source {
+        Source {
+            first_seen: time,
+            last_seen: time,
+            probes: BTreeSet::new(),
+            pending: HashMap::new(),
+            sent: VecDeque::new(),
+            targets: HashMap::new(),
+            counted: VecDeque::new(),
+            ports: HashMap::new(),
+            hosts: HashMap::new(),
+            scanning: false,
+        }
+    }
+
+    /// Count a probe that went unanswered or was refused, returning whether
+    /// that made the source a scanner
+    fn count(&mut self, limits: &Limits, target: (IpAddr, u16), probe: Probe, time: f64) -> bool {
+        self.probes.insert(probe);
+        if self.scanning {
+            if self.targets.len() < MAX_TARGETS {
+                self.targets.insert(target, time);
+            }
+            return false;
+        }
+        while let Some(&(seen, key)) = self.counted.front() {
+            if time - seen <= limits.window {
+                break;
+            }
+            self.counted.pop_front();
+            if self.targets.get(&key) == Some(&seen) {
+                self.targets.remove(&key);
+                decrement(&mut self.ports, key.0);
+                decrement(&mut self.hosts, key.1);
+            }
+        }
+        if self.targets.insert(target, time).is_none() {
+            *self.ports.entry(target.0).or_default() += 1;
+            *self.hosts.entry(target.1).or_default() += 1;
+        }
+        self.counted.push_back((time, target));
+        if self.ports[&target.0] < limits.port_threshold
+            && self.hosts[&target.1] < limits.host_threshold
+        {
+            return false;
+        }
+        self.scanning = true;
+        self.first_seen = self.counted.front().map_or(time, |(seen, _)| *seen);
+        self.counted.clear();
+        self.ports.clear();
+        self.hosts.clear();
+        true
+    }
+
+    /// Count the probes that waited too long for an answer by `now`,
+    /// returning whether that made the source a scanner
+    fn settle(&mut self, limits: &Limits, now: f64) -> bool {
+        let mut started = false;
+        while let Some(&(sent, key)) = self.sent.front() {
+            if now - sent <= REPLY_TIMEOUT {
+                break;
+            }
+            self.sent.pop_front();
+            // Answered, or probed again since
+            match self.pending.get(&key) {
+                Some((at, _)) if *at == sent => {}
+                _ => continue,
+            }
+            let (_, probe) = self.pending.remove(&key).unwrap();
+            started |= self.count(limits, key, probe, sent);
+        }
+        started
+    }
+
+    fn scan(&self, scanner: IpAddr, finished: bool) -> Scan {
+        Scan {
+            scanner,
+            probes: self.probes.clone(),
+            hosts: self.targets.keys().map(|(host, _)| *host).collect(),
+            ports: self.targets.keys().map(|(_, port)| *port).collect(),
+            first_seen: self.first_seen,
+            last_seen: self.last_seen,
+            finished,
+        }
+    }
+}
+
+fn decrement<K: std::hash::Hash + Eq>(counts: &mut HashMap<K, usize>, key: K) {
+    if let Some(n) = counts.get_mut(&key) {
+        *n -= 1;
+        if *n == 0 {
+            counts.remove(&key);
+        }
+    }
+}
+
+/// Groups the probes each source sends, so a scan gives one alert as it
+/// starts and one when it is over rather than one per port. Only probes
+/// refused or never answered count, a port that answers is a service in use.
+pub struct Scans {
+    limits: Limits,
+    sources: HashMap<IpAddr, Source>,
+    /// Scans summed up early to make room, until the next expire
+    evicted: Vec<Scan>,
+    last_expired: f64,
+}
+
+impl Scans {
+    pub fn new(config: &serde_yaml::Value) -> Scans {
+        let threshold =
+            |key: &str, default: usize| config[key].as_u64().map_or(default, |n| n as usize);
+        Scans {
+            limits: Limits {
+                window: config["scan_window"].as_f64().unwrap_or(WINDOW),
+                port_threshold: threshold("scan_port_threshold", PORT_THRESHOLD),
+                host_threshold: threshold("scan_host_threshold", HOST_THRESHOLD),
+            },
+            sources: HashMap::new(),
+            evicted: Vec::new(),
+            last_expired: 0.0,
+        }
+    }
+
+    /// Feed a TCP segment or UDP datagram, `probe` being what [`Probe::new`]
+    /// made of it, returning a scan as soon as one starts
+    pub fn add(
+        &mut self,
+        ip: &IP,
+        transport: &Transport,
+        probe: Option<Probe>,
+        time: f64,
+    ) -> Option<Scan> {
+        let target = (ip.src, transport.src_port);
+        let answers = self
+            .sources
+            .get(&ip.dst)
+            .is_some_and(|s| s.pending.contains_key(&target));
+        if answers {
+            let refused = transport
+                .tcp
+                .as_ref()
+                .is_some_and(|t| t.flags.has(TcpFlags::RST));
+            return self.answer(ip.dst, target, refused, time);
+        }
+        let probe = probe?;
+        // Nobody is expected to answer these
+        let group = match ip.dst {
+            IpAddr::V4(v4) => v4.is_broadcast() || v4.is_multicast(),
+            IpAddr::V6(v6) => v6.is_multicast(),
+        };
+        if probe == Probe::Udp && group {
+            return None;
+        }
+        if !self.sources.contains_key(&ip.src) && self.sources.len() >= MAX_SOURCES {
+            self.make_room();
+        }
+        let source = self
+            .sources
+            .entry(ip.src)
+            .or_insert_with(|| Source::new(time));
+        source.last_seen = time;
+        let started = source.settle(&self.limits, time);
+        let key = (ip.dst, transport.dst_port);
+        if source.pending.len() < MAX_TARGETS || source.pending.contains_key(&key) {
+            source.pending.insert(key, (time, probe));
+            source.sent.push_back((time, key));
+        }
+        match started {
+            true => Some(source.scan(ip.src, false)),
+            false => None,
+        }
+    }
+
+    /// An ICMP port unreachable turning down a UDP probe
+    pub fn refused(&mut self, scanner: IpAddr, target: (IpAddr, u16), time: f64) -> Option<Scan> {
+        self.answer(scanner, target, true, time)
+    }
+
+    fn answer(
+        &mut self,
+        scanner: IpAddr,
+        target: (IpAddr, u16),
+        refused: bool,
+        time: f64,
+    ) -> Option<Scan> {
+        let source = self.sources.get_mut(&scanner)?;
+        let (_, probe) = source.pending.remove(&target)?;
+        match refused && source.count(&self.limits, target, probe, time) {
+            true => Some(source.scan(scanner, false)),
+            false => None,
+        }
+    }
+
+    /// Has `ip` been seen scanning, so replies to it are part of the scan too
+    pub fn is_scanning(&self, ip: &IpAddr) -> bool {
+        self.sources.get(ip).is_some_and(|s| s.scanning)
+    }
+
+    /// Scans that started as their probes went unanswered, then those whose
+    /// source went quiet for a whole window
+    pub fn expire(&mut self, now: f64) -> Vec<Scan> {
+        let mut found = std::mem::take(&mut self.evicted);
+        // Looking through every source once a second is plenty
+        if now - self.last_expired < 1.0 {
+            return found;
+        }
+        self.last_expired = now;
+        for (ip, source) in self.sources.iter_mut() {
+            if source.settle(&self.limits, now) {
+                found.push(source.scan(*ip, false));
+            }
+        }
+        let window = self.limits.window;
+        let idle: Vec<IpAddr> = self
+            .sources
+            .iter()
+            .filter(|(_, s)| now - s.last_seen > window.max(REPLY_TIMEOUT))
+            .map(|(ip, _)| *ip)
+            .collect();
+        found.extend(idle.into_iter().filter_map(|ip| self.finish(ip)));
+        found
+    }
+
+    /// Every scan still going, for the end of a capture, counting the probes
+    /// still waiting as unanswered
+    pub fn flush(&mut self) -> Vec<Scan> {
+        for source in self.sources.values_mut() {
+            source.settle(&self.limits, f64::INFINITY);
+        }
+        let sources: Vec<IpAddr> = self.sources.keys().copied().collect();
+        let mut found = std::mem::take(&mut self.evicted);
+        found.extend(sources.into_iter().filter_map(|ip| self.finish(ip)));
+        found
+    }
+
+    fn finish(&mut self, ip: IpAddr) -> Option<Scan> {
+        let source = self.sources.remove(&ip)?;
+        match source.scanning {
+            true => Some(source.scan(ip, true)),
+            false => None,
+        }
+    }
+
+    /// Drop every source that is not scanning at once, as the other detectors
+    /// clear their tables, so spoofed sources cost one pass per table full.
+    /// Scanners filling half the table are summed up as finished.
+    fn make_room(&mut self) {
+        self.sources.retain(|_, s| s.scanning);
+        if self.sources.len() >= MAX_SOURCES / 2 {
+            let scanners: Vec<IpAddr> = self.sources.keys().copied().collect();
+            let finished: Vec<Scan> = scanners
+                .into_iter()
+                .filter_map(|ip| self.finish(ip))
+                .collect();
+            self.evicted.extend(finished);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::protocol::Layer3;
+    use std::net::Ipv4Addr;
+
+    const SCANNER: u8 = 9;
+    const SYN: Option<u8> = Some(TcpFlags::SYN);
+
+    /// From 10.0.0.`src`:`src_port` to 10.0.0.`dst`:`dst_port`, a TCP segment
+    /// with `flags` or else an empty UDP datagram
+    fn packet(src: (u8, u16), dst: (u8, u16), flags: Option<u8>) -> Vec<u8> {
+        let mut data = hex::decode("450000000000400040000000").unwrap();
+        data.extend([10, 0, 0, src.0, 10, 0, 0, dst.0]);
+        data.extend(src.1.to_be_bytes());
+        data.extend(dst.1.to_be_bytes());
+        match flags {
+            Some(flags) => {
+                data[9] = 6;
+                data.extend([0, 0, 0, 1, 0, 0, 0, 0, 0x50, flags, 4, 0, 0, 0, 0, 0]);
+            }
+            None => {
+                data[9] = 17;
+                data.extend([0, 8, 0, 0]);
+            }
+        }
+        let len = data.len() as u16;
+        data[2..4].copy_from_slice(&len.to_be_bytes());
+        data
+    }
+
+    fn add(scans: &mut Scans, data: &[u8], probe: Option<Probe>, time: f64) -> Option<Scan> {
+        let ip = IP::new(data, Layer3::IPv4).unwrap();
+        let transport = Transport::new(ip.payload, &ip.protocol).unwrap().unwrap();
+        scans.add(&ip, &transport, probe, time)
+    }
+
+    #[test]
+    fn refused_probes_start_a_scan() {
+        let mut scans = Scans::new(&serde_yaml::Value::Null);
+        let rst = Some(TcpFlags::RST | TcpFlags::ACK);
+        for port in 1..PORT_THRESHOLD as u16 {
+            let time = port as f64 / 100.0;
+            add(
+                &mut scans,
+                &packet((SCANNER, 40000), (2, port), SYN),
+                Some(Probe::Syn),
+                time,
+            );
+            assert!(add(
+                &mut scans,
+                &packet((2, port), (SCANNER, 40000), rst),
+                None,
+                time
+            )
+            .is_none());
+        }
+        let last = PORT_THRESHOLD as u16;
+        add(
+            &mut scans,
+            &packet((SCANNER, 40000), (2, last), SYN),
+            Some(Probe::Syn),
+            1.0,
+        );
+        let scan = add(
+            &mut scans,
+            &packet((2, last), (SCANNER, 40000), rst),
+            None,
+            1.0,
+        )
+        .unwrap();
+        assert!(!scan.finished && scans.is_scanning(&scan.scanner));
+        assert_eq!(
+            scan.to_string(),
+            "SYN scan of 20 ports on 10.0.0.2 in 1.0s, ongoing"
+        );
+
+        let over = scans.expire(1.0 + WINDOW + 1.0);
+        assert_eq!(over.len(), 1);
+        assert!(over[0].finished && !scans.is_scanning(&over[0].scanner));
+    }
+
+    #[test]
+    fn answered_connections_are_no_scan() {
+        let mut scans = Scans::new(&serde_yaml::Value::Null);
+        let syn_ack = Some(TcpFlags::SYN | TcpFlags::ACK);
+        for host in 10..10 + HOST_THRESHOLD as u8 * 2 {
+            let time = host as f64;
+            add(
+                &mut scans,
+                &packet((SCANNER, 40000), (host, 443), SYN),
+                Some(Probe::Syn),
+                time,
+            );
+            add(
+                &mut scans,
+                &packet((host, 443), (SCANNER, 40000), syn_ack),
+                None,
+                time,
+            );
+        }
+        assert!(scans.flush().is_empty());
+    }
+
+    #[test]
+    fn unanswered_probes_time_out_into_a_scan() {
+        let mut scans = Scans::new(&serde_yaml::Value::Null);
+        for host in 10..10 + HOST_THRESHOLD as u8 {
+            let probe = packet((SCANNER, 40000), (host, 22), SYN);
+            assert!(add(&mut scans, &probe, Some(Probe::Syn), 0.0).is_none());
+        }
+        assert!(scans.expire(REPLY_TIMEOUT).is_empty());
+        let started = scans.expire(REPLY_TIMEOUT + 1.0);
+        assert_eq!(started.len(), 1);
+        assert_eq!(
+            started[0].to_string(),
+            "SYN scan of port 22 on 50 hosts in 0.0s, ongoing"
+        );
+        let summary = scans.flush();
+        assert!(summary[0].finished && summary[0].hosts.len() == HOST_THRESHOLD);
+    }
+
+    #[test]
+    fn a_full_table_keeps_scanners_until_they_fill_half() {
+        let mut scans = Scans::new(&serde_yaml::Value::Null);
+        let fill = |scans: &mut Scans, scanning: bool| {
+            for n in 0..MAX_SOURCES as u32 {
+                let mut source = Source::new(0.0);
+                source.scanning = scanning;
+                source
+                    .targets
+                    .insert((IpAddr::from([10, 0, 0, 2]), 22), 0.0);
+                scans
+                    .sources
+                    .insert(IpAddr::V4(Ipv4Addr::from((11 << 24) + n)), source);
+            }
+        };
+        fill(&mut scans, false);
+        scans
+            .sources
+            .get_mut(&IpAddr::from([11, 0, 0, 7]))
+            .unwrap()
+            .scanning = true;
+        let probe = packet((SCANNER, 40000), (2, 22), SYN);
+        add(&mut scans, &probe, Some(Probe::Syn), 0.5);
+        assert_eq!(scans.sources.len(), 2);
+        assert!(scans.is_scanning(&IpAddr::from([11, 0, 0, 7])));
+        assert!(scans.expire(0.5).is_empty());
+
+        fill(&mut scans, true);
+        add(
+            &mut scans,
+            &packet((8, 40000), (2, 22), SYN),
+            Some(Probe::Syn),
+            0.6,
+        );
+        assert_eq!(scans.sources.len(), 1);
+        let evicted = scans.expire(0.6);
+        assert_eq!(evicted.len(), MAX_SOURCES);
+        assert!(evicted.iter().all(|scan| scan.finished));
+    }
+
+    #[test]
+    fn udp_probes_count_unless_answered() {
+        let config = serde_yaml::from_str("scan_port_threshold: 3").unwrap();
+        let mut scans = Scans::new(&config);
+        let scanner = IpAddr::from([10, 0, 0, SCANNER]);
+        let target = IpAddr::from([10, 0, 0, 2]);
+        // A DNS query and its answer
+        add(
+            &mut scans,
+            &packet((SCANNER, 5353), (2, 53), None),
+            Some(Probe::Udp),
+            0.0,
+        );
+        add(
+            &mut scans,
+            &packet((2, 53), (SCANNER, 5353), None),
+            Some(Probe::Udp),
+            0.0,
+        );
+        for port in [161, 162] {
+            add(
+                &mut scans,
+                &packet((SCANNER, 5353), (2, port), None),
+                Some(Probe::Udp),
+                0.0,
+            );
+            assert!(scans.refused(scanner, (target, port), 0.1).is_none());
+        }
+        add(
+            &mut scans,
+            &packet((SCANNER, 5353), (2, 500), None),
+            Some(Probe::Udp),
+            0.2,
+        );
+        let scan = scans.refused(scanner, (target, 500), 0.3).unwrap();
+        assert!(scan.is_udp());
+        assert_eq!(scan.ports, BTreeSet::from([161, 162, 500]));
+    }
+}
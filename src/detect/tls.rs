@@ -0,0 +1,70 @@
+use crate::detect::{self, ConfigError};
+use crate::packet::application::Application;
+use crate::packet::Decoded;
+
+/// TLS client fingerprints from the optional `tls_bad_fingerprints` and
+/// `tls_allowed_fingerprints` lists of the host config, each entry either a
+/// JA3 hash or a JA4 string
+pub struct TlsPolicy {
+    bad: Vec<String>,
+    allowed: Option<Vec<String>>,
+}
+
+impl TlsPolicy {
+    pub fn new(config: &serde_yaml::Value) -> Result<TlsPolicy, ConfigError> {
+        let list = |key| {
+            detect::list(config, key).map(|fingerprints: Option<Vec<String>>| {
+                fingerprints.map(|f| f.iter().map(|f| f.to_ascii_lowercase()).collect())
+            })
+        };
+        Ok(TlsPolicy {
+            bad: list("tls_bad_fingerprints")?.unwrap_or_default(),
+            allowed: list("tls_allowed_fingerprints")?,
+        })
+    }
+
+    /// A reason when a ClientHello is known bad, or missing from the allowed list
+    pub fn check(&self, packet: &Decoded) -> Option<String> {
+        let hello = match packet.transport?.application.as_ref()? {
+            Application::Tls(tls) => tls.client_hello.as_ref()?,
+            _ => return None,
+        };
+        let (ja3, ja4) = (hello.ja3(), hello.ja4());
+        let listed = |list: &Vec<String>| list.iter().any(|f| *f == ja3 || *f == ja4);
+        let reason = match (listed(&self.bad), &self.allowed) {
+            (true, _) => "KNOWN_BAD_TLS_FINGERPRINT",
+            (false, Some(allowed)) if !listed(allowed) => "UNKNOWN_TLS_FINGERPRINT",
+            _ => return None,
+        };
+        Some(format!(
+            "{} ({} ja3 {} ja4 {})",
+            reason,
+            hello.server_name.as_deref().unwrap_or("no SNI"),
+            ja3,
+            ja4
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_fingerprints_in_any_case() {
+        let config =
+            serde_yaml::from_str("tls_bad_fingerprints: [E7D705A3286E19EA42F587B344EE6865]");
+        let policy = TlsPolicy::new(&config.unwrap()).unwrap();
+        assert_eq!(policy.bad, vec!["e7d705a3286e19ea42f587b344ee6865"]);
+        assert!(policy.allowed.is_none());
+    }
+
+    #[test]
+    fn reports_a_fingerprint_that_is_not_a_string() {
+        let config = serde_yaml::from_str("tls_allowed_fingerprints: [12345]");
+        let error = TlsPolicy::new(&config.unwrap()).err().unwrap();
+        assert!(error
+            .to_string()
+            .starts_with("Invalid tls_allowed_fingerprints in config: expected a string"));
+    }
+}
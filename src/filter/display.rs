@@ -0,0 +1,1567 @@
+use crate::packet::application::Application;
+use crate::packet::dhcp::Dhcp;
+use crate::packet::dns::{Dns, RData};
+use crate::packet::http::{Http, StartLine};
+use crate::packet::icmp::Icmp;
+use crate::packet::ip::{Cidr, Ipv6Extension};
+use crate::packet::protocol::*;
+use crate::packet::tls::{ClientHello, Tls};
+use crate::packet::transport::{TcpFlags, TcpHeader, Transport};
+use crate::packet::Decoded;
+use std::fmt;
+use std::net::IpAddr;
+
+/// A post-decode filter such as `tcp.dport == 22 && ip.src in 10.0.0.0/8 && !arp`
+///
+/// ```text
+/// expr    := and ( ("||" | "or") and )*
+/// and     := unary ( ("&&" | "and") unary )*
+/// unary   := ("!" | "not") unary | "(" expr ")" | test
+/// test    := name [ cmp value | "in" value | "in" "{" value ( [","] value )* "}" ]
+/// cmp     := "==" | "!=" | "<" | "<=" | ">" | ">="
+/// ```
+///
+/// A bare protocol name (`tcp`, `arp`, `vlan`, ...) or field name tests
+/// whether it is present in the packet.
+pub struct DisplayFilter {
+    root: Node,
+}
+
+impl DisplayFilter {
+    pub fn parse(expression: &str) -> Result<DisplayFilter, FilterError> {
+        let tokens = lex(expression)?;
+        let mut parser = Parser {
+            expression,
+            tokens,
+            pos: 0,
+        };
+        let root = parser.expr()?;
+        if let Some(extra) = parser.tokens.get(parser.pos) {
+            return Err(parser.error_at(extra.position, "expected '&&', '||' or end of filter"));
+        }
+        Ok(DisplayFilter { root })
+    }
+
+    pub fn matches(&self, packet: &Decoded) -> bool {
+        self.root.eval(packet)
+    }
+}
+
+#[derive(Debug)]
+pub struct FilterError {
+    expression: String,
+    position: usize,
+    message: String,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Invalid display filter: {}", self.message)?;
+        writeln!(f, "    {}", self.expression)?;
+        // The position is a byte offset, the caret goes under its character
+        let column = self.expression[..self.position].chars().count();
+        write!(f, "    {}^", " ".repeat(column))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+enum Token {
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    Comma,
+    And,
+    Or,
+    Not,
+    In,
+    Cmp(Cmp),
+    Word(String),
+    Str(String),
+}
+
+struct Lexed {
+    token: Token,
+    position: usize,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | ':' | '/' | '_' | '-')
+}
+
+fn lex(expression: &str) -> Result<Vec<Lexed>, FilterError> {
+    let error = |position: usize, message: &str| FilterError {
+        expression: String::from(expression),
+        position,
+        message: String::from(message),
+    };
+    let chars: Vec<(usize, char)> = expression.char_indices().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let (position, c) = chars[i];
+        let next = chars.get(i + 1).map(|(_, c)| *c);
+        let (token, width) = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('(', _) => (Token::LParen, 1),
+            (')', _) => (Token::RParen, 1),
+            ('{', _) => (Token::LBrace, 1),
+            ('}', _) => (Token::RBrace, 1),
+            (',', _) => (Token::Comma, 1),
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('=', Some('=')) => (Token::Cmp(Cmp::Eq), 2),
+            ('=', _) => (Token::Cmp(Cmp::Eq), 1),
+            ('!', Some('=')) => (Token::Cmp(Cmp::Ne), 2),
+            ('!', _) => (Token::Not, 1),
+            ('<', Some('=')) => (Token::Cmp(Cmp::Le), 2),
+            ('<', _) => (Token::Cmp(Cmp::Lt), 1),
+            ('>', Some('=')) => (Token::Cmp(Cmp::Ge), 2),
+            ('>', _) => (Token::Cmp(Cmp::Gt), 1),
+            ('"', _) => {
+                let mut end = i + 1;
+                while end < chars.len() && chars[end].1 != '"' {
+                    end += 1;
+                }
+                if end == chars.len() {
+                    return Err(error(position, "unterminated string"));
+                }
+                let value: String = chars[i + 1..end].iter().map(|(_, c)| c).collect();
+                (Token::Str(value), end + 1 - i)
+            }
+            (c, _) if is_word_char(c) => {
+                let mut end = i;
+                while end < chars.len() && is_word_char(chars[end].1) {
+                    end += 1;
+                }
+                let word: String = chars[i..end].iter().map(|(_, c)| c).collect();
+                let token = match &word[..] {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    "in" => Token::In,
+                    _ => Token::Word(word),
+                };
+                (token, end - i)
+            }
+            ('&', _) | ('|', _) => return Err(error(position, "use '&&' and '||' for logic")),
+            _ => return Err(error(position, &format!("unexpected character '{}'", c))),
+        };
+        tokens.push(Lexed { token, position });
+        i += width;
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Int,
+    Ip,
+    Mac,
+    Str,
+    Proto,
+}
+
+impl Kind {
+    fn describe(&self) -> &'static str {
+        match self {
+            Kind::Int => "a number",
+            Kind::Ip => "an IP address or CIDR network",
+            Kind::Mac => "a MAC address",
+            Kind::Str => "a word or quoted string",
+            Kind::Proto => "a protocol name or number",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    Int(u64),
+    Ip(IpAddr),
+    Net(Cidr),
+    Mac([u8; 6]),
+    Str(String),
+}
+
+impl Value {
+    fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Ip(a), Value::Ip(b)) => a == b,
+            (Value::Ip(a), Value::Net(net)) => net.contains(a),
+            (Value::Mac(a), Value::Mac(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a.eq_ignore_ascii_case(b),
+            _ => false,
+        }
+    }
+
+    fn compare(&self, cmp: Cmp, other: &Value) -> bool {
+        match (cmp, self, other) {
+            (Cmp::Eq, _, _) => self.equals(other),
+            (Cmp::Ne, _, _) => !self.equals(other),
+            (Cmp::Lt, Value::Int(a), Value::Int(b)) => a < b,
+            (Cmp::Le, Value::Int(a), Value::Int(b)) => a <= b,
+            (Cmp::Gt, Value::Int(a), Value::Int(b)) => a > b,
+            (Cmp::Ge, Value::Int(a), Value::Int(b)) => a >= b,
+            _ => false,
+        }
+    }
+}
+
+struct Field {
+    names: &'static [&'static str],
+    kind: Kind,
+    extract: fn(&Decoded) -> Vec<Value>,
+}
+
+fn tcp<'a>(p: &'a Decoded) -> Option<&'a Transport<'a>> {
+    p.transport.filter(|t| matches!(t.protocol, Layer4::Tcp))
+}
+
+fn tcp_header<'a>(p: &'a Decoded) -> Option<&'a TcpHeader> {
+    p.transport.and_then(|t| t.tcp.as_ref())
+}
+
+fn ipv6_extension(p: &Decoded, pick: fn(&Ipv6Extension) -> Option<u64>) -> Option<u64> {
+    p.ip.ipv6.as_ref()?.extensions.iter().find_map(pick)
+}
+
+fn dns<'a>(p: &'a Decoded) -> Option<&'a Dns> {
+    match p.transport?.application.as_ref()? {
+        Application::Dns(dns) => Some(dns),
+        _ => None,
+    }
+}
+
+fn dhcp<'a>(p: &'a Decoded) -> Option<&'a Dhcp> {
+    match p.transport?.application.as_ref()? {
+        Application::Dhcp(dhcp) => Some(dhcp),
+        _ => None,
+    }
+}
+
+fn http<'a>(p: &'a Decoded) -> Option<&'a Http> {
+    match p.transport?.application.as_ref()? {
+        Application::Http(http) => Some(http),
+        _ => None,
+    }
+}
+
+fn http_header(p: &Decoded, name: &str) -> Vec<Value> {
+    http(p)
+        .and_then(|h| h.header(name))
+        .map(|v| Value::Str(String::from(v)))
+        .into_iter()
+        .collect()
+}
+
+fn tls<'a>(p: &'a Decoded) -> Option<&'a Tls> {
+    match p.transport?.application.as_ref()? {
+        Application::Tls(tls) => Some(tls),
+        _ => None,
+    }
+}
+
+fn client_hello<'a>(p: &'a Decoded) -> Option<&'a ClientHello> {
+    tls(p)?.client_hello.as_ref()
+}
+
+fn udp<'a>(p: &'a Decoded) -> Option<&'a Transport<'a>> {
+    p.transport.filter(|t| matches!(t.protocol, Layer4::Udp))
+}
+
+fn icmp_v4<'a>(p: &'a Decoded) -> Option<&'a Icmp<'a>> {
+    p.icmp.filter(|i| matches!(i.protocol, Layer4::Icmp))
+}
+
+fn icmp_v6<'a>(p: &'a Decoded) -> Option<&'a Icmp<'a>> {
+    p.icmp.filter(|i| matches!(i.protocol, Layer4::ICMPv6))
+}
+
+fn int<T: Into<u64>>(value: Option<T>) -> Vec<Value> {
+    value.map(|v| Value::Int(v.into())).into_iter().collect()
+}
+
+const FIELDS: &[Field] = &[
+    Field {
+        names: &["frame.len"],
+        kind: Kind::Int,
+        extract: |p| int(Some(p.len)),
+    },
+    Field {
+        names: &["eth.src"],
+        kind: Kind::Mac,
+        extract: |p| vec![Value::Mac(p.eth.src.octets())],
+    },
+    Field {
+        names: &["eth.dst"],
+        kind: Kind::Mac,
+        extract: |p| vec![Value::Mac(p.eth.dst.octets())],
+    },
+    Field {
+        names: &["eth.addr"],
+        kind: Kind::Mac,
+        extract: |p| vec![Value::Mac(p.eth.src.octets()), Value::Mac(p.eth.dst.octets())],
+    },
+    Field {
+        names: &["vlan.id", "vlan.vid"],
+        kind: Kind::Int,
+        extract: |p| int(p.eth.dot1q.as_ref().map(|v| v.vid)),
+    },
+    Field {
+        names: &["vlan.pcp"],
+        kind: Kind::Int,
+        extract: |p| int(p.eth.dot1q.as_ref().map(|v| v.pcp)),
+    },
+    Field {
+        names: &["vlan.dei"],
+        kind: Kind::Int,
+        extract: |p| int(p.eth.dot1q.as_ref().map(|v| v.dei as u8)),
+    },
+    Field {
+        names: &["ip.src"],
+        kind: Kind::Ip,
+        extract: |p| match p.ip.protocol {
+            Layer4::Arp => vec![],
+            _ => vec![Value::Ip(p.ip.src)],
+        },
+    },
+    Field {
+        names: &["ip.dst"],
+        kind: Kind::Ip,
+        extract: |p| match p.ip.protocol {
+            Layer4::Arp => vec![],
+            _ => vec![Value::Ip(p.ip.dst)],
+        },
+    },
+    Field {
+        names: &["ip.addr", "ip.host"],
+        kind: Kind::Ip,
+        extract: |p| match p.ip.protocol {
+            Layer4::Arp => vec![],
+            _ => vec![Value::Ip(p.ip.src), Value::Ip(p.ip.dst)],
+        },
+    },
+    Field {
+        names: &["ip.proto"],
+        kind: Kind::Proto,
+        extract: |p| int(p.ip.protocol.number()),
+    },
+    Field {
+        names: &["ip.ttl"],
+        kind: Kind::Int,
+        extract: |p| int(p.ip.ipv4.as_ref().map(|h| h.ttl)),
+    },
+    Field {
+        names: &["ip.id", "ip.identification"],
+        kind: Kind::Int,
+        extract: |p| int(p.ip.ipv4.as_ref().map(|h| h.identification)),
+    },
+    Field {
+        names: &["ip.len"],
+        kind: Kind::Int,
+        extract: |p| int(p.ip.ipv4.as_ref().map(|h| h.total_length)),
+    },
+    Field {
+        names: &["ip.hdr_len"],
+        kind: Kind::Int,
+        extract: |p| int(p.ip.ipv4.as_ref().map(|h| h.header_len() as u64)),
+    },
+    Field {
+        names: &["ip.dsfield.dscp", "ip.dscp"],
+        kind: Kind::Int,
+        extract: |p| int(p.ip.ipv4.as_ref().map(|h| h.dscp)),
+    },
+    Field {
+        names: &["ip.dsfield.ecn", "ip.ecn"],
+        kind: Kind::Int,
+        extract: |p| int(p.ip.ipv4.as_ref().map(|h| h.ecn)),
+    },
+    Field {
+        names: &["ip.flags.df"],
+        kind: Kind::Int,
+        extract: |p| int(p.ip.ipv4.as_ref().map(|h| h.dont_fragment as u8)),
+    },
+    Field {
+        names: &["ip.flags.mf"],
+        kind: Kind::Int,
+        extract: |p| int(p.ip.ipv4.as_ref().map(|h| h.more_fragments as u8)),
+    },
+    Field {
+        names: &["ip.frag_offset"],
+        kind: Kind::Int,
+        extract: |p| int(p.ip.ipv4.as_ref().map(|h| h.fragment_offset as u64 * 8)),
+    },
+    Field {
+        names: &["ip.checksum"],
+        kind: Kind::Int,
+        extract: |p| int(p.ip.ipv4.as_ref().map(|h| h.checksum)),
+    },
+    Field {
+        names: &["ipv6.hlim"],
+        kind: Kind::Int,
+        extract: |p| int(p.ip.ipv6.as_ref().map(|h| h.hop_limit)),
+    },
+    Field {
+        names: &["ipv6.flow"],
+        kind: Kind::Int,
+        extract: |p| int(p.ip.ipv6.as_ref().map(|h| h.flow_label)),
+    },
+    Field {
+        names: &["ipv6.tclass"],
+        kind: Kind::Int,
+        extract: |p| int(p.ip.ipv6.as_ref().map(|h| h.traffic_class)),
+    },
+    Field {
+        names: &["ipv6.plen"],
+        kind: Kind::Int,
+        extract: |p| int(p.ip.ipv6.as_ref().map(|h| h.payload_length)),
+    },
+    Field {
+        names: &["ipv6.ext_count"],
+        kind: Kind::Int,
+        extract: |p| int(p.ip.ipv6.as_ref().map(|h| h.extensions.len() as u64)),
+    },
+    Field {
+        names: &["ipv6.routing.type"],
+        kind: Kind::Int,
+        extract: |p| {
+            int(ipv6_extension(p, |e| match e {
+                Ipv6Extension::Routing { routing_type, .. } => Some(*routing_type as u64),
+                _ => None,
+            }))
+        },
+    },
+    Field {
+        names: &["ipv6.fragment.id"],
+        kind: Kind::Int,
+        extract: |p| {
+            int(ipv6_extension(p, |e| match e {
+                Ipv6Extension::Fragment { identification, .. } => Some(*identification as u64),
+                _ => None,
+            }))
+        },
+    },
+    Field {
+        names: &["ipv6.fragment.offset"],
+        kind: Kind::Int,
+        extract: |p| {
+            int(ipv6_extension(p, |e| match e {
+                Ipv6Extension::Fragment { offset, .. } => Some(*offset as u64 * 8),
+                _ => None,
+            }))
+        },
+    },
+    Field {
+        names: &["ipv6.fragment.more"],
+        kind: Kind::Int,
+        extract: |p| {
+            int(ipv6_extension(p, |e| match e {
+                Ipv6Extension::Fragment { more_fragments, .. } => Some(*more_fragments as u64),
+                _ => None,
+            }))
+        },
+    },
+    Field {
+        names: &["tcp.sport", "tcp.src_port", "tcp.srcport"],
+        kind: Kind::Int,
+        extract: |p| int(tcp(p).map(|t| t.src_port)),
+    },
+    Field {
+        names: &["tcp.dport", "tcp.dst_port", "tcp.dstport"],
+        kind: Kind::Int,
+        extract: |p| int(tcp(p).map(|t| t.dst_port)),
+    },
+    Field {
+        names: &["tcp.port"],
+        kind: Kind::Int,
+        extract: |p| match tcp(p) {
+            Some(t) => vec![Value::Int(t.src_port.into()), Value::Int(t.dst_port.into())],
+            None => vec![],
+        },
+    },
+    Field {
+        names: &["tcp.len"],
+        kind: Kind::Int,
+        extract: |p| int(tcp(p).map(|t| t.payload.len() as u64)),
+    },
+    Field {
+        names: &["tcp.seq"],
+        kind: Kind::Int,
+        extract: |p| int(tcp_header(p).map(|h| h.seq)),
+    },
+    Field {
+        names: &["tcp.ack"],
+        kind: Kind::Int,
+        extract: |p| int(tcp_header(p).map(|h| h.ack)),
+    },
+    Field {
+        names: &["tcp.window", "tcp.window_size"],
+        kind: Kind::Int,
+        extract: |p| int(tcp_header(p).map(|h| h.window)),
+    },
+    Field {
+        names: &["tcp.checksum"],
+        kind: Kind::Int,
+        extract: |p| int(tcp_header(p).map(|h| h.checksum)),
+    },
+    Field {
+        names: &["tcp.urgent_pointer", "tcp.urg"],
+        kind: Kind::Int,
+        extract: |p| int(tcp_header(p).map(|h| h.urgent)),
+    },
+    Field {
+        names: &["tcp.flags"],
+        kind: Kind::Int,
+        extract: |p| int(tcp_header(p).map(|h| h.flags.0)),
+    },
+    Field {
+        names: &["tcp.flags.syn"],
+        kind: Kind::Int,
+        extract: |p| int(tcp_header(p).map(|h| h.flags.has(TcpFlags::SYN) as u8)),
+    },
+    Field {
+        names: &["tcp.flags.ack"],
+        kind: Kind::Int,
+        extract: |p| int(tcp_header(p).map(|h| h.flags.has(TcpFlags::ACK) as u8)),
+    },
+    Field {
+        names: &["tcp.flags.fin"],
+        kind: Kind::Int,
+        extract: |p| int(tcp_header(p).map(|h| h.flags.has(TcpFlags::FIN) as u8)),
+    },
+    Field {
+        names: &["tcp.flags.rst"],
+        kind: Kind::Int,
+        extract: |p| int(tcp_header(p).map(|h| h.flags.has(TcpFlags::RST) as u8)),
+    },
+    Field {
+        names: &["tcp.flags.psh"],
+        kind: Kind::Int,
+        extract: |p| int(tcp_header(p).map(|h| h.flags.has(TcpFlags::PSH) as u8)),
+    },
+    Field {
+        names: &["tcp.flags.urg"],
+        kind: Kind::Int,
+        extract: |p| int(tcp_header(p).map(|h| h.flags.has(TcpFlags::URG) as u8)),
+    },
+    Field {
+        names: &["tcp.flags.ece"],
+        kind: Kind::Int,
+        extract: |p| int(tcp_header(p).map(|h| h.flags.has(TcpFlags::ECE) as u8)),
+    },
+    Field {
+        names: &["tcp.flags.cwr"],
+        kind: Kind::Int,
+        extract: |p| int(tcp_header(p).map(|h| h.flags.has(TcpFlags::CWR) as u8)),
+    },
+    Field {
+        names: &["udp.sport", "udp.src_port", "udp.srcport"],
+        kind: Kind::Int,
+        extract: |p| int(udp(p).map(|t| t.src_port)),
+    },
+    Field {
+        names: &["udp.dport", "udp.dst_port", "udp.dstport"],
+        kind: Kind::Int,
+        extract: |p| int(udp(p).map(|t| t.dst_port)),
+    },
+    Field {
+        names: &["udp.port"],
+        kind: Kind::Int,
+        extract: |p| match udp(p) {
+            Some(t) => vec![Value::Int(t.src_port.into()), Value::Int(t.dst_port.into())],
+            None => vec![],
+        },
+    },
+    Field {
+        names: &["udp.len"],
+        kind: Kind::Int,
+        extract: |p| int(udp(p).map(|t| t.payload.len() as u64)),
+    },
+    Field {
+        names: &["transport.tag", "tag"],
+        kind: Kind::Str,
+        extract: |p| p.transport.map(|t| Value::Str(t.get_tag())).into_iter().collect(),
+    },
+    Field {
+        names: &["transport.port_tag"],
+        kind: Kind::Str,
+        extract: |p| p.transport.map(|t| Value::Str(t.port_tag())).into_iter().collect(),
+    },
+    Field {
+        names: &["transport.content"],
+        kind: Kind::Str,
+        extract: |p| {
+            p.transport
+                .and_then(|t| t.content)
+                .map(|c| Value::Str(c.to_string()))
+                .into_iter()
+                .collect()
+        },
+    },
+    Field {
+        names: &["icmp.type"],
+        kind: Kind::Int,
+        extract: |p| int(icmp_v4(p).map(|i| i.icmp_type)),
+    },
+    Field {
+        names: &["icmp.code"],
+        kind: Kind::Int,
+        extract: |p| int(icmp_v4(p).map(|i| i.icmp_code)),
+    },
+    Field {
+        names: &["icmpv6.type"],
+        kind: Kind::Int,
+        extract: |p| int(icmp_v6(p).map(|i| i.icmp_type)),
+    },
+    Field {
+        names: &["icmpv6.code"],
+        kind: Kind::Int,
+        extract: |p| int(icmp_v6(p).map(|i| i.icmp_code)),
+    },
+    Field {
+        names: &["icmp.ident"],
+        kind: Kind::Int,
+        extract: |p| int(icmp_v4(p).and_then(|i| i.identifier)),
+    },
+    Field {
+        names: &["icmp.seq"],
+        kind: Kind::Int,
+        extract: |p| int(icmp_v4(p).and_then(|i| i.sequence)),
+    },
+    Field {
+        names: &["icmpv6.echo.identifier"],
+        kind: Kind::Int,
+        extract: |p| int(icmp_v6(p).and_then(|i| i.identifier)),
+    },
+    Field {
+        names: &["icmpv6.echo.sequence_number"],
+        kind: Kind::Int,
+        extract: |p| int(icmp_v6(p).and_then(|i| i.sequence)),
+    },
+    Field {
+        names: &["dns.id"],
+        kind: Kind::Int,
+        extract: |p| int(dns(p).map(|d| d.id)),
+    },
+    Field {
+        names: &["dns.flags.response"],
+        kind: Kind::Int,
+        extract: |p| int(dns(p).map(|d| d.response)),
+    },
+    Field {
+        names: &["dns.flags.opcode"],
+        kind: Kind::Int,
+        extract: |p| int(dns(p).map(|d| d.opcode)),
+    },
+    Field {
+        names: &["dns.flags.rcode"],
+        kind: Kind::Int,
+        extract: |p| int(dns(p).map(|d| d.rcode)),
+    },
+    Field {
+        names: &["dns.qry.name"],
+        kind: Kind::Str,
+        extract: |p| {
+            dns(p)
+                .into_iter()
+                .flat_map(|d| &d.questions)
+                .map(|q| Value::Str(q.name.clone()))
+                .collect()
+        },
+    },
+    Field {
+        names: &["dns.qry.type"],
+        kind: Kind::Int,
+        extract: |p| {
+            dns(p)
+                .into_iter()
+                .flat_map(|d| &d.questions)
+                .map(|q| Value::Int(q.qtype.into()))
+                .collect()
+        },
+    },
+    Field {
+        names: &["dns.qry.class"],
+        kind: Kind::Int,
+        extract: |p| {
+            dns(p)
+                .into_iter()
+                .flat_map(|d| &d.questions)
+                .map(|q| Value::Int(q.class.into()))
+                .collect()
+        },
+    },
+    Field {
+        names: &["dns.resp.name"],
+        kind: Kind::Str,
+        extract: |p| {
+            dns(p)
+                .into_iter()
+                .flat_map(|d| &d.answers)
+                .map(|r| Value::Str(r.name.clone()))
+                .collect()
+        },
+    },
+    Field {
+        names: &["dns.resp.type"],
+        kind: Kind::Int,
+        extract: |p| {
+            dns(p)
+                .into_iter()
+                .flat_map(|d| &d.answers)
+                .map(|r| Value::Int(r.rtype.into()))
+                .collect()
+        },
+    },
+    Field {
+        names: &["dns.resp.class"],
+        kind: Kind::Int,
+        extract: |p| {
+            dns(p)
+                .into_iter()
+                .flat_map(|d| &d.answers)
+                .map(|r| Value::Int(r.class.into()))
+                .collect()
+        },
+    },
+    Field {
+        names: &["dns.resp.ttl"],
+        kind: Kind::Int,
+        extract: |p| {
+            dns(p)
+                .into_iter()
+                .flat_map(|d| &d.answers)
+                .map(|r| Value::Int(r.ttl.into()))
+                .collect()
+        },
+    },
+    Field {
+        names: &["dns.a"],
+        kind: Kind::Ip,
+        extract: |p| {
+            dns(p)
+                .into_iter()
+                .flat_map(|d| &d.answers)
+                .filter_map(|r| match r.data {
+                    RData::A(ip) => Some(Value::Ip(IpAddr::V4(ip))),
+                    _ => None,
+                })
+                .collect()
+        },
+    },
+    Field {
+        names: &["dns.aaaa"],
+        kind: Kind::Ip,
+        extract: |p| {
+            dns(p)
+                .into_iter()
+                .flat_map(|d| &d.answers)
+                .filter_map(|r| match r.data {
+                    RData::Aaaa(ip) => Some(Value::Ip(IpAddr::V6(ip))),
+                    _ => None,
+                })
+                .collect()
+        },
+    },
+    Field {
+        names: &["dns.cname"],
+        kind: Kind::Str,
+        extract: |p| {
+            dns(p)
+                .into_iter()
+                .flat_map(|d| &d.answers)
+                .filter_map(|r| match &r.data {
+                    RData::Cname(name) => Some(Value::Str(name.clone())),
+                    _ => None,
+                })
+                .collect()
+        },
+    },
+    Field {
+        names: &["dns.count.queries"],
+        kind: Kind::Int,
+        extract: |p| int(dns(p).map(|d| d.questions.len() as u64)),
+    },
+    Field {
+        names: &["dns.count.answers"],
+        kind: Kind::Int,
+        extract: |p| int(dns(p).map(|d| d.answers.len() as u64)),
+    },
+    Field {
+        names: &["dns.count.auth_rr"],
+        kind: Kind::Int,
+        extract: |p| int(dns(p).map(|d| d.authorities.len() as u64)),
+    },
+    Field {
+        names: &["dns.count.add_rr"],
+        kind: Kind::Int,
+        extract: |p| int(dns(p).map(|d| d.additionals.len() as u64)),
+    },
+    Field {
+        names: &["dhcp.type"],
+        kind: Kind::Int,
+        extract: |p| int(dhcp(p).and_then(|d| d.message_type).map(|t| t.number())),
+    },
+    Field {
+        names: &["dhcp.xid", "dhcp.id"],
+        kind: Kind::Int,
+        extract: |p| int(dhcp(p).map(|d| d.xid)),
+    },
+    Field {
+        names: &["dhcp.ip.your"],
+        kind: Kind::Ip,
+        extract: |p| dhcp(p).map(|d| Value::Ip(IpAddr::V4(d.your_ip))).into_iter().collect(),
+    },
+    Field {
+        names: &["dhcp.hw.mac_addr"],
+        kind: Kind::Mac,
+        extract: |p| dhcp(p).map(|d| Value::Mac(d.client_mac.octets())).into_iter().collect(),
+    },
+    Field {
+        names: &["dhcp.option.server_id"],
+        kind: Kind::Ip,
+        extract: |p| {
+            dhcp(p)
+                .and_then(|d| d.server_id)
+                .map(|ip| Value::Ip(IpAddr::V4(ip)))
+                .into_iter()
+                .collect()
+        },
+    },
+    Field {
+        names: &["dhcp.option.hostname"],
+        kind: Kind::Str,
+        extract: |p| {
+            dhcp(p)
+                .and_then(|d| d.hostname.clone())
+                .map(Value::Str)
+                .into_iter()
+                .collect()
+        },
+    },
+    Field {
+        names: &["http.request.method"],
+        kind: Kind::Str,
+        extract: |p| match http(p).map(|h| &h.start) {
+            Some(StartLine::Request { method, .. }) => vec![Value::Str(method.clone())],
+            _ => Vec::new(),
+        },
+    },
+    Field {
+        names: &["http.request.uri"],
+        kind: Kind::Str,
+        extract: |p| match http(p).map(|h| &h.start) {
+            Some(StartLine::Request { target, .. }) => vec![Value::Str(target.clone())],
+            _ => Vec::new(),
+        },
+    },
+    Field {
+        names: &["http.request.version", "http.response.version"],
+        kind: Kind::Str,
+        extract: |p| match http(p).map(|h| &h.start) {
+            Some(StartLine::Request { version, .. }) | Some(StartLine::Response { version, .. }) => {
+                vec![Value::Str(version.clone())]
+            }
+            _ => Vec::new(),
+        },
+    },
+    Field {
+        names: &["http.response.code"],
+        kind: Kind::Int,
+        extract: |p| match http(p).map(|h| &h.start) {
+            Some(StartLine::Response { status, .. }) => int(Some(*status)),
+            _ => Vec::new(),
+        },
+    },
+    Field {
+        names: &["http.response.phrase"],
+        kind: Kind::Str,
+        extract: |p| match http(p).map(|h| &h.start) {
+            Some(StartLine::Response { reason, .. }) => vec![Value::Str(reason.clone())],
+            _ => Vec::new(),
+        },
+    },
+    Field {
+        names: &["http.host"],
+        kind: Kind::Str,
+        extract: |p| http_header(p, "Host"),
+    },
+    Field {
+        names: &["http.user_agent"],
+        kind: Kind::Str,
+        extract: |p| http_header(p, "User-Agent"),
+    },
+    Field {
+        names: &["http.content_type"],
+        kind: Kind::Str,
+        extract: |p| http_header(p, "Content-Type"),
+    },
+    Field {
+        names: &["http.content_length"],
+        kind: Kind::Int,
+        extract: |p| int(http(p).and_then(|h| h.content_length())),
+    },
+    Field {
+        names: &["http.authorization"],
+        kind: Kind::Str,
+        extract: |p| http_header(p, "Authorization"),
+    },
+    Field {
+        names: &["http.cookie"],
+        kind: Kind::Str,
+        extract: |p| http_header(p, "Cookie"),
+    },
+    Field {
+        names: &["tls.record.content_type"],
+        kind: Kind::Int,
+        extract: |p| int(tls(p).map(|t| t.content_type)),
+    },
+    Field {
+        names: &["tls.record.version"],
+        kind: Kind::Int,
+        extract: |p| int(tls(p).map(|t| t.version)),
+    },
+    Field {
+        names: &["tls.record.length"],
+        kind: Kind::Int,
+        extract: |p| int(tls(p).map(|t| t.length)),
+    },
+    Field {
+        names: &["tls.handshake.type"],
+        kind: Kind::Int,
+        extract: |p| int(tls(p).and_then(|t| t.handshake_type)),
+    },
+    Field {
+        names: &["tls.handshake.version"],
+        kind: Kind::Int,
+        extract: |p| int(client_hello(p).map(|h| h.version)),
+    },
+    Field {
+        names: &["tls.handshake.ciphersuite"],
+        kind: Kind::Int,
+        extract: |p| {
+            client_hello(p)
+                .into_iter()
+                .flat_map(|h| &h.ciphers)
+                .map(|c| Value::Int(*c as u64))
+                .collect()
+        },
+    },
+    Field {
+        names: &["tls.handshake.extensions_supported_version"],
+        kind: Kind::Int,
+        extract: |p| {
+            client_hello(p)
+                .into_iter()
+                .flat_map(|h| &h.supported_versions)
+                .map(|v| Value::Int(*v as u64))
+                .collect()
+        },
+    },
+    Field {
+        names: &["tls.handshake.extensions_server_name"],
+        kind: Kind::Str,
+        extract: |p| {
+            client_hello(p)
+                .and_then(|h| h.server_name.clone())
+                .map(Value::Str)
+                .into_iter()
+                .collect()
+        },
+    },
+    Field {
+        names: &["tls.handshake.extensions_alpn_str"],
+        kind: Kind::Str,
+        extract: |p| {
+            client_hello(p)
+                .into_iter()
+                .flat_map(|h| &h.alpn)
+                .map(|a| Value::Str(a.clone()))
+                .collect()
+        },
+    },
+    Field {
+        names: &["tls.handshake.ja3_full"],
+        kind: Kind::Str,
+        extract: |p| client_hello(p).map(|h| Value::Str(h.ja3_full())).into_iter().collect(),
+    },
+    Field {
+        names: &["tls.handshake.ja3"],
+        kind: Kind::Str,
+        extract: |p| client_hello(p).map(|h| Value::Str(h.ja3())).into_iter().collect(),
+    },
+    Field {
+        names: &["tls.handshake.ja4"],
+        kind: Kind::Str,
+        extract: |p| client_hello(p).map(|h| Value::Str(h.ja4())).into_iter().collect(),
+    },
+    Field {
+        names: &["arp.opcode"],
+        kind: Kind::Int,
+        extract: |p| int(p.arp.map(|a| a.opcode)),
+    },
+    Field {
+        names: &["arp.src", "arp.src.proto_ipv4"],
+        kind: Kind::Ip,
+        extract: |p| p.arp.map(|a| Value::Ip(a.src_ip)).into_iter().collect(),
+    },
+    Field {
+        names: &["arp.dst", "arp.dst.proto_ipv4"],
+        kind: Kind::Ip,
+        extract: |p| p.arp.map(|a| Value::Ip(a.dst_ip)).into_iter().collect(),
+    },
+    Field {
+        names: &["arp.src.hw_mac"],
+        kind: Kind::Mac,
+        extract: |p| p.arp.map(|a| Value::Mac(a.src_mac.octets())).into_iter().collect(),
+    },
+    Field {
+        names: &["arp.dst.hw_mac"],
+        kind: Kind::Mac,
+        extract: |p| p.arp.map(|a| Value::Mac(a.dst_mac.octets())).into_iter().collect(),
+    },
+    Field {
+        names: &["arp.hw.type"],
+        kind: Kind::Int,
+        extract: |p| int(p.arp.map(|a| a.hardware_type)),
+    },
+    Field {
+        names: &["arp.proto.type"],
+        kind: Kind::Int,
+        extract: |p| int(p.arp.map(|a| a.protocol_type)),
+    },
+    Field {
+        names: &["arp.hw.size"],
+        kind: Kind::Int,
+        extract: |p| int(p.arp.map(|a| a.hardware_size)),
+    },
+    Field {
+        names: &["arp.proto.size"],
+        kind: Kind::Int,
+        extract: |p| int(p.arp.map(|a| a.protocol_size)),
+    },
+    Field {
+        names: &["arp.isgratuitous"],
+        kind: Kind::Int,
+        extract: |p| int(p.arp.map(|a| a.is_gratuitous())),
+    },
+];
+
+struct Protocol {
+    name: &'static str,
+    present: fn(&Decoded) -> bool,
+}
+
+const PROTOCOLS: &[Protocol] = &[
+    Protocol {
+        name: "eth",
+        present: |_| true,
+    },
+    Protocol {
+        name: "vlan",
+        present: |p| p.eth.dot1q.is_some(),
+    },
+    Protocol {
+        name: "ip",
+        present: |p| !matches!(p.ip.protocol, Layer4::Arp),
+    },
+    Protocol {
+        name: "ipv4",
+        present: |p| p.ip.src.is_ipv4() && !matches!(p.ip.protocol, Layer4::Arp),
+    },
+    Protocol {
+        name: "ipv6",
+        present: |p| p.ip.src.is_ipv6(),
+    },
+    Protocol {
+        name: "arp",
+        present: |p| matches!(p.ip.protocol, Layer4::Arp),
+    },
+    Protocol {
+        name: "tcp",
+        present: |p| matches!(p.ip.protocol, Layer4::Tcp),
+    },
+    Protocol {
+        name: "udp",
+        present: |p| matches!(p.ip.protocol, Layer4::Udp),
+    },
+    Protocol {
+        name: "icmp",
+        present: |p| matches!(p.ip.protocol, Layer4::Icmp),
+    },
+    Protocol {
+        name: "icmpv6",
+        present: |p| matches!(p.ip.protocol, Layer4::ICMPv6),
+    },
+    Protocol {
+        name: "igmp",
+        present: |p| matches!(p.ip.protocol, Layer4::Igmp),
+    },
+    Protocol {
+        name: "dns",
+        present: |p| dns(p).is_some(),
+    },
+    Protocol {
+        name: "dhcp",
+        present: |p| dhcp(p).is_some(),
+    },
+    Protocol {
+        name: "http",
+        present: |p| http(p).is_some(),
+    },
+    Protocol {
+        name: "tls",
+        present: |p| tls(p).is_some(),
+    },
+];
+
+enum Node {
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Protocol(&'static Protocol),
+    Present(&'static Field),
+    Compare(&'static Field, Cmp, Value),
+    In(&'static Field, Vec<Value>),
+}
+
+impl Node {
+    fn eval(&self, packet: &Decoded) -> bool {
+        match self {
+            Node::And(a, b) => a.eval(packet) && b.eval(packet),
+            Node::Or(a, b) => a.eval(packet) || b.eval(packet),
+            Node::Not(a) => !a.eval(packet),
+            Node::Protocol(protocol) => (protocol.present)(packet),
+            Node::Present(field) => !(field.extract)(packet).is_empty(),
+            // A field that differs must exist, and "!=" on a field with two
+            // addresses means neither of them matches
+            Node::Compare(field, Cmp::Ne, value) => {
+                let found = (field.extract)(packet);
+                !found.is_empty() && !found.iter().any(|x| x.equals(value))
+            }
+            Node::Compare(field, cmp, value) => (field.extract)(packet)
+                .iter()
+                .any(|x| x.compare(*cmp, value)),
+            Node::In(field, set) => (field.extract)(packet)
+                .iter()
+                .any(|x| set.iter().any(|v| x.equals(v))),
+        }
+    }
+}
+
+struct Parser<'e> {
+    expression: &'e str,
+    tokens: Vec<Lexed>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error_at(&self, position: usize, message: &str) -> FilterError {
+        FilterError {
+            expression: String::from(self.expression),
+            position,
+            message: String::from(message),
+        }
+    }
+
+    /// Position of the current token, or the end of the expression
+    fn here(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map(|t| t.position)
+            .unwrap_or_else(|| self.expression.len())
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.token)
+    }
+
+    fn expr(&mut self) -> Result<Node, FilterError> {
+        let mut node = self.and()?;
+        while let Some(Token::Or) = self.peek() {
+            self.pos += 1;
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node, FilterError> {
+        let mut node = self.unary()?;
+        while let Some(Token::And) = self.peek() {
+            self.pos += 1;
+            node = Node::And(Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, FilterError> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.pos += 1;
+                Ok(Node::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::LParen) => {
+                let open = self.here();
+                self.pos += 1;
+                let node = self.expr()?;
+                match self.peek() {
+                    Some(Token::RParen) => {
+                        self.pos += 1;
+                        Ok(node)
+                    }
+                    _ => Err(self.error_at(open, "unmatched '('")),
+                }
+            }
+            Some(Token::Word(_)) => self.test(),
+            None => Err(self.error_at(self.here(), "filter ends early, expected a field or protocol")),
+            _ => Err(self.error_at(self.here(), "expected a field or protocol")),
+        }
+    }
+
+    fn test(&mut self) -> Result<Node, FilterError> {
+        let position = self.here();
+        let name = match self.peek() {
+            Some(Token::Word(name)) => name.to_lowercase(),
+            _ => unreachable!(),
+        };
+        self.pos += 1;
+
+        let field = FIELDS.iter().find(|f| f.names.contains(&&name[..]));
+        let protocol = PROTOCOLS.iter().find(|p| p.name == name);
+
+        match self.peek() {
+            Some(Token::Cmp(cmp)) => {
+                let cmp = *cmp;
+                let field = self.field(field, protocol, &name, position)?;
+                if !matches!(cmp, Cmp::Eq | Cmp::Ne) && field.kind != Kind::Int {
+                    return Err(self.error_at(
+                        self.tokens[self.pos].position,
+                        &format!("{} can only be compared with '==' or '!='", name),
+                    ));
+                }
+                self.pos += 1;
+                let value = self.value(field, &name)?;
+                Ok(Node::Compare(field, cmp, value))
+            }
+            Some(Token::In) => {
+                let field = self.field(field, protocol, &name, position)?;
+                self.pos += 1;
+                if let Some(Token::LBrace) = self.peek() {
+                    let open = self.here();
+                    self.pos += 1;
+                    let mut set = Vec::new();
+                    loop {
+                        match self.peek() {
+                            Some(Token::RBrace) => {
+                                self.pos += 1;
+                                break;
+                            }
+                            Some(Token::Comma) => self.pos += 1,
+                            None => return Err(self.error_at(open, "unmatched '{'")),
+                            _ => set.push(self.value(field, &name)?),
+                        }
+                    }
+                    if set.is_empty() {
+                        return Err(self.error_at(open, "empty set"));
+                    }
+                    Ok(Node::In(field, set))
+                } else {
+                    Ok(Node::In(field, vec![self.value(field, &name)?]))
+                }
+            }
+            _ => match (protocol, field) {
+                (Some(protocol), _) => Ok(Node::Protocol(protocol)),
+                (None, Some(field)) => Ok(Node::Present(field)),
+                (None, None) => Err(self.unknown(&name, position)),
+            },
+        }
+    }
+
+    fn field(
+        &self,
+        field: Option<&'static Field>,
+        protocol: Option<&'static Protocol>,
+        name: &str,
+        position: usize,
+    ) -> Result<&'static Field, FilterError> {
+        match (field, protocol) {
+            (Some(field), _) => Ok(field),
+            (None, Some(_)) => Err(self.error_at(
+                position,
+                &format!("'{}' is a protocol and has no value, compare one of its fields", name),
+            )),
+            (None, None) => Err(self.unknown(name, position)),
+        }
+    }
+
+    fn unknown(&self, name: &str, position: usize) -> FilterError {
+        let prefix = name.split('.').next().unwrap_or(name);
+        let related: Vec<&str> = FIELDS
+            .iter()
+            .flat_map(|f| f.names.iter().take(1))
+            .filter(|n| n.starts_with(prefix) && n[prefix.len()..].starts_with('.'))
+            .copied()
+            .collect();
+        match related.len() {
+            0 => self.error_at(position, &format!("unknown field or protocol '{}'", name)),
+            _ => self.error_at(
+                position,
+                &format!("unknown field '{}' (known {} fields: {})", name, prefix, related.join(", ")),
+            ),
+        }
+    }
+
+    fn value(&mut self, field: &Field, name: &str) -> Result<Value, FilterError> {
+        let position = self.here();
+        let text = match self.peek() {
+            Some(Token::Word(text)) | Some(Token::Str(text)) => text.clone(),
+            _ => {
+                return Err(self.error_at(
+                    position,
+                    &format!("expected {} for {}", field.kind.describe(), name),
+                ))
+            }
+        };
+        self.pos += 1;
+        let mismatch = || {
+            self.error_at(
+                position,
+                &format!("'{}' is not {} (needed for {})", text, field.kind.describe(), name),
+            )
+        };
+        match field.kind {
+            Kind::Int => parse_int(&text).map(Value::Int).ok_or_else(mismatch),
+            Kind::Ip => match text.contains('/') {
+                true => text
+                    .parse::<Cidr>()
+                    .map(Value::Net)
+                    .map_err(|e| self.error_at(position, &e)),
+                false => text.parse().map(Value::Ip).map_err(|_| mismatch()),
+            },
+            Kind::Mac => parse_mac(&text).map(Value::Mac).ok_or_else(mismatch),
+            Kind::Str => Ok(Value::Str(text)),
+            Kind::Proto => parse_int(&text)
+                .or_else(|| parse_protocol(&text))
+                .map(Value::Int)
+                .ok_or_else(mismatch),
+        }
+    }
+}
+
+fn parse_int(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn parse_mac(text: &str) -> Option<[u8; 6]> {
+    let mut mac = [0u8; 6];
+    hex::decode_to_slice(text.replace(&[':', '-'][..], ""), &mut mac).ok()?;
+    Some(mac)
+}
+
+fn parse_protocol(text: &str) -> Option<u64> {
+    [Layer4::Tcp, Layer4::Udp, Layer4::Icmp, Layer4::ICMPv6, Layer4::Igmp]
+        .iter()
+        .find(|p| p.to_string().eq_ignore_ascii_case(text))
+        .and_then(|p| p.number())
+        .map(u64::from)
+}
+
+/// Parse the `-Y` display filter, exiting with the parse error if it is invalid
+pub fn open(matches: &clap::ArgMatches) -> Option<DisplayFilter> {
+    let expression = matches.value_of("display-filter")?;
+    match DisplayFilter::parse(expression) {
+        Ok(filter) => Some(filter),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::ethernet::Ethernet;
+    use crate::packet::ip::IP;
+
+    /// An Ethernet/IPv4 frame from 00:11:22:33:44:55 and 10.0.0.1 to 10.0.0.2
+    /// carrying `transport` as IP protocol `protocol`
+    fn frame(protocol: u8, transport: &[u8]) -> Vec<u8> {
+        let mut frame = hex::decode("ffffffffffff0011223344550800").unwrap();
+        frame.extend(hex::decode("4500").unwrap());
+        frame.extend((20 + transport.len() as u16).to_be_bytes());
+        frame.extend([0, 1, 0, 0, 64, protocol, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+        frame.extend(transport);
+        frame
+    }
+
+    fn udp(src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(src_port.to_be_bytes());
+        data.extend(dst_port.to_be_bytes());
+        data.extend((8 + payload.len() as u16).to_be_bytes());
+        data.extend([0, 0]);
+        data.extend(payload);
+        frame(17, &data)
+    }
+
+    fn tcp(src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(src_port.to_be_bytes());
+        data.extend(dst_port.to_be_bytes());
+        data.extend(hex::decode("000003e8000007d05018ffff00000000").unwrap());
+        data.extend(payload);
+        frame(6, &data)
+    }
+
+    /// A query for the A record of example.com
+    fn dns_query() -> Vec<u8> {
+        udp(
+            40000,
+            53,
+            &hex::decode("123401000001000000000000076578616d706c6503636f6d0000010001").unwrap(),
+        )
+    }
+
+    fn matches(expression: &str, frame: Vec<u8>) -> bool {
+        let filter = DisplayFilter::parse(expression).unwrap();
+        let eth = Ethernet::try_from(frame).unwrap();
+        let ip = IP::new(&eth.payload, eth.ethertype).unwrap();
+        let transport = Transport::new(ip.payload, &ip.protocol).unwrap();
+        let icmp = Icmp::new(ip.payload, &ip.protocol).unwrap();
+        filter.matches(&Decoded {
+            len: eth.payload.len() as u32 + 14,
+            eth: &eth,
+            ip: &ip,
+            transport: transport.as_ref(),
+            icmp: icmp.as_ref(),
+            arp: ip.arp.as_ref(),
+        })
+    }
+
+    fn error(expression: &str) -> String {
+        DisplayFilter::parse(expression).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or_and_not_tighter_than_both() {
+        assert!(matches("tcp || udp && udp.dport == 53", dns_query()));
+        assert!(!matches("tcp || udp && udp.dport == 80", dns_query()));
+        assert!(!matches("(tcp || udp) && udp.dport == 80", dns_query()));
+        assert!(matches("udp.dport == 80 && tcp || udp", dns_query()));
+        assert!(matches("!tcp && udp", dns_query()));
+        assert!(!matches("!(tcp || udp)", dns_query()));
+        assert!(matches("not tcp and udp or tcp", dns_query()));
+        assert!(matches("!!udp", dns_query()));
+    }
+
+    #[test]
+    fn in_takes_a_network_or_a_set() {
+        assert!(matches("ip.src in 10.0.0.0/8", dns_query()));
+        assert!(!matches("ip.dst in 192.168.0.0/16", dns_query()));
+        assert!(matches("ip.addr == 10.0.0.2/32", dns_query()));
+        assert!(matches("udp.dport in {53, 80}", dns_query()));
+        assert!(!matches("udp.dport in {80 443}", dns_query()));
+        assert!(matches(
+            "ip.dst in {192.168.0.0/16 10.0.0.0/24}",
+            dns_query()
+        ));
+    }
+
+    #[test]
+    fn every_kind_of_field_compares() {
+        // Numbers, in decimal or hex and with every operator
+        assert!(matches("frame.len == 71 && udp.len == 29", dns_query()));
+        assert!(matches(
+            "udp.dport == 0x35 && udp.sport > 1024",
+            dns_query()
+        ));
+        assert!(matches(
+            "udp.sport >= 40000 && udp.sport <= 40000",
+            dns_query()
+        ));
+        assert!(!matches("udp.sport < 40000", dns_query()));
+        // Addresses
+        assert!(matches(
+            "ip.src == 10.0.0.1 && ip.src != 10.0.0.2",
+            dns_query()
+        ));
+        assert!(matches("eth.src == 00:11:22:33:44:55", dns_query()));
+        assert!(matches("eth.dst == ff-ff-ff-ff-ff-ff", dns_query()));
+        // Words and strings, without regard to case
+        assert!(matches("dns.qry.name == \"Example.com\"", dns_query()));
+        assert!(matches("transport.tag == dns", dns_query()));
+        // Protocols by name or number
+        assert!(matches("ip.proto == udp && ip.proto == 17", dns_query()));
+        // Presence of a field
+        assert!(matches("dns.id", dns_query()));
+        assert!(!matches("tcp.seq", dns_query()));
+    }
+
+    #[test]
+    fn application_protocols_are_bare_names() {
+        assert!(matches("dns && !dhcp && !http && !tls", dns_query()));
+        let request = tcp(40000, 8080, b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        assert!(matches(
+            "http && http.request.method == get",
+            request.clone()
+        ));
+        assert!(matches("http.host == \"example.com\" && !dns", request));
+        let server_hello = tcp(443, 40000, &hex::decode("160303000402000000").unwrap());
+        assert!(matches("tls && tls.handshake.type == 2", server_hello));
+    }
+
+    #[test]
+    fn unknown_names_say_what_is_known() {
+        assert!(error("tcp.bogus == 1")
+            .contains("unknown field 'tcp.bogus' (known tcp fields: tcp.sport,"));
+        assert!(error("bogus").contains("unknown field or protocol 'bogus'"));
+        assert!(error("tcp == 6").contains("'tcp' is a protocol and has no value"));
+    }
+
+    #[test]
+    fn bad_literals_name_what_was_expected() {
+        assert!(error("ip.src == 10.0.0.256")
+            .contains("'10.0.0.256' is not an IP address or CIDR network (needed for ip.src)"));
+        assert!(error("eth.src == 00:11:22").contains("is not a MAC address"));
+        assert!(error("tcp.port == http").contains("'http' is not a number (needed for tcp.port)"));
+        assert!(error("ip.proto == bogus").contains("is not a protocol name or number"));
+        assert!(
+            error("ip.src > 10.0.0.1").contains("ip.src can only be compared with '==' or '!='")
+        );
+        assert!(error("tcp.port ==").contains("expected a number for tcp.port"));
+        assert!(error("dns.qry.name == \"example").contains("unterminated string"));
+        assert!(error("tcp & udp").contains("use '&&' and '||' for logic"));
+        assert!(error("(tcp || udp").contains("unmatched '('"));
+        assert!(error("udp.port in {}").contains("empty set"));
+        assert!(error("tcp udp").contains("expected '&&', '||' or end of filter"));
+    }
+
+    #[test]
+    fn the_caret_points_at_the_error() {
+        assert!(error("udp && tcp.bogus == 1").ends_with("\n           ^"));
+        assert!(error("tcp &&").ends_with("\n          ^"));
+        // Counted in characters, not the bytes of the two byte 'é'
+        let message = error("dns.qry.name == \"é\" && bogus");
+        assert_eq!(
+            message.lines().last().unwrap(),
+            format!("    {}^", " ".repeat(23))
+        );
+    }
+}
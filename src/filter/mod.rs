@@ -0,0 +1,2 @@
+pub(crate) mod display;
+pub(crate) mod rules;
@@ -0,0 +1,241 @@
+use crate::packet::ip::Cidr;
+use crate::packet::protocol::*;
+use crate::packet::Decoded;
+use clap::ArgMatches;
+
+enum Condition {
+    Net(Cidr),
+    SrcNet(Cidr),
+    DstNet(Cidr),
+    Port(u16),
+    SrcPort(u16),
+    DstPort(u16),
+    Proto(Layer4),
+}
+
+const KINDS: usize = 7;
+
+impl Condition {
+    /// Conditions of the same kind are alternatives of each other
+    fn kind(&self) -> usize {
+        match self {
+            Condition::Net(_) => 0,
+            Condition::SrcNet(_) => 1,
+            Condition::DstNet(_) => 2,
+            Condition::Port(_) => 3,
+            Condition::SrcPort(_) => 4,
+            Condition::DstPort(_) => 5,
+            Condition::Proto(_) => 6,
+        }
+    }
+
+    fn matches(&self, packet: &Decoded) -> bool {
+        let ip = packet.ip;
+        let ports = packet.transport.map(|t| (t.src_port, t.dst_port));
+        match self {
+            Condition::Net(net) => net.contains(&ip.src) || net.contains(&ip.dst),
+            Condition::SrcNet(net) => net.contains(&ip.src),
+            Condition::DstNet(net) => net.contains(&ip.dst),
+            Condition::Port(port) => ports.is_some_and(|(s, d)| s == *port || d == *port),
+            Condition::SrcPort(port) => ports.is_some_and(|(s, _)| s == *port),
+            Condition::DstPort(port) => ports.is_some_and(|(_, d)| d == *port),
+            Condition::Proto(protocol) => ip.protocol == *protocol,
+        }
+    }
+}
+
+/// Conditions that hold together, every kind of condition present has to be
+/// satisfied by at least one of its values
+#[derive(Default)]
+struct Group {
+    conditions: Vec<Condition>,
+}
+
+impl Group {
+    fn matches(&self, packet: &Decoded) -> bool {
+        (0..KINDS).all(|kind| {
+            let mut of_kind = self.conditions.iter().filter(|c| c.kind() == kind).peekable();
+            of_kind.peek().is_none() || of_kind.any(|c| c.matches(packet))
+        })
+    }
+}
+
+/// The repeatable `--net`/`--port`/`--proto` style filters of `sniff`
+///
+/// Values of the same flag are OR'd and different flags are AND'd. Every
+/// value prefixed with `!` excludes on its own, so `--proto !arp --port !53`
+/// hides both ARP and DNS. Each `--exclude` hides what matches all of its
+/// conditions, so `--exclude src-net=10.0.0.5,port=22` hides SSH from the
+/// jump box and nothing else.
+#[derive(Default)]
+pub struct RuleSet {
+    include: Group,
+    exclude: Vec<Group>,
+}
+
+impl RuleSet {
+    pub fn is_empty(&self) -> bool {
+        self.include.conditions.is_empty() && self.exclude.is_empty()
+    }
+
+    pub fn matches(&self, packet: &Decoded) -> bool {
+        if !self.include.conditions.is_empty() && !self.include.matches(packet) {
+            return false;
+        }
+        !self.exclude.iter().any(|group| group.matches(packet))
+    }
+
+    fn add(&mut self, negated: bool, condition: Condition) {
+        match negated {
+            true => self.exclude.push(Group {
+                conditions: vec![condition],
+            }),
+            false => self.include.conditions.push(condition),
+        }
+    }
+}
+
+/// Builds the condition a flag's value stands for
+type Parse = fn(&str) -> Condition;
+
+fn parse_net(value: &str) -> Cidr {
+    value
+        .parse()
+        .unwrap_or_else(|e| panic!("Invalid network filter: {}", e))
+}
+
+fn parse_port(value: &str) -> u16 {
+    value
+        .parse()
+        .unwrap_or_else(|_| panic!("Invalid port filter: '{}' is not a port number", value))
+}
+
+fn parse_proto(value: &str) -> Layer4 {
+    if let Ok(number) = value.parse::<u8>() {
+        return Layer4::from(number);
+    }
+    [
+        Layer4::Tcp,
+        Layer4::Udp,
+        Layer4::Icmp,
+        Layer4::ICMPv6,
+        Layer4::Arp,
+        Layer4::Igmp,
+        Layer4::IPv6HopByHop,
+    ]
+    .into_iter()
+    .find(|p| p.to_string().eq_ignore_ascii_case(value))
+    .unwrap_or_else(|| panic!("Invalid protocol filter: '{}' is not a known protocol", value))
+}
+
+/// Every rule flag and how its values are read
+const FLAGS: [(&str, Parse); 7] = [
+    ("net", |v| Condition::Net(parse_net(v))),
+    ("src-net", |v| Condition::SrcNet(parse_net(v))),
+    ("dst-net", |v| Condition::DstNet(parse_net(v))),
+    ("port", |v| Condition::Port(parse_port(v))),
+    ("src-port", |v| Condition::SrcPort(parse_port(v))),
+    ("dst-port", |v| Condition::DstPort(parse_port(v))),
+    ("proto", |v| Condition::Proto(parse_proto(v))),
+];
+
+/// Collect the rule filters given on the command line
+pub fn open(matches: &ArgMatches) -> RuleSet {
+    let mut rules = RuleSet::default();
+    for (flag, condition) in FLAGS {
+        for value in matches.values_of(flag).into_iter().flatten() {
+            match value.strip_prefix('!') {
+                Some(value) => rules.add(true, condition(value.trim())),
+                None => rules.add(false, condition(value.trim())),
+            }
+        }
+    }
+    for value in matches.values_of("exclude").into_iter().flatten() {
+        rules.exclude.push(parse_exclude(value));
+    }
+    rules
+}
+
+/// An `--exclude` value, `flag=value` pairs separated by commas
+fn parse_exclude(value: &str) -> Group {
+    let conditions = value
+        .split(',')
+        .map(|pair| {
+            let (flag, value) = pair.split_once('=').unwrap_or_else(|| {
+                panic!("Invalid exclusion: '{}' is not of the form flag=value", pair)
+            });
+            let (_, condition) = FLAGS
+                .iter()
+                .find(|(name, _)| *name == flag.trim())
+                .unwrap_or_else(|| panic!("Invalid exclusion: '{}' is not a filter", flag.trim()));
+            condition(value.trim())
+        })
+        .collect();
+    Group { conditions }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::ethernet::Ethernet;
+    use crate::packet::ip::IP;
+    use crate::packet::transport::Transport;
+
+    /// An Ethernet/IPv4/UDP frame from 10.0.0.1:1024 to 10.0.0.2 on `port`
+    fn udp_frame(port: u16) -> Vec<u8> {
+        let mut frame =
+            hex::decode("ffffffffffff00112233445508004500002400010000401100000a0000010a000002")
+                .unwrap();
+        frame.extend_from_slice(&1024u16.to_be_bytes());
+        frame.extend_from_slice(&port.to_be_bytes());
+        frame.extend_from_slice(&[0, 16, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8]);
+        frame
+    }
+
+    fn matches(rules: &RuleSet, frame: Vec<u8>) -> bool {
+        let eth = Ethernet::try_from(frame).unwrap();
+        let ip = IP::new(&eth.payload, eth.ethertype).unwrap();
+        let transport = Transport::new(ip.payload, &ip.protocol).unwrap();
+        let packet = Decoded {
+            len: 0,
+            eth: &eth,
+            ip: &ip,
+            transport: transport.as_ref(),
+            icmp: None,
+            arp: None,
+        };
+        rules.matches(&packet)
+    }
+
+    #[test]
+    fn every_exclusion_stands_on_its_own() {
+        let mut rules = RuleSet::default();
+        rules.add(true, Condition::Proto(Layer4::Arp));
+        rules.add(true, Condition::Port(53));
+        assert!(!matches(&rules, udp_frame(53)));
+        assert!(matches(&rules, udp_frame(123)));
+    }
+
+    #[test]
+    fn an_exclusion_hides_only_what_matches_all_of_it() {
+        let mut rules = RuleSet::default();
+        rules
+            .exclude
+            .push(parse_exclude("src-net=10.0.0.1/32, port=22"));
+        assert!(!matches(&rules, udp_frame(22)));
+        assert!(matches(&rules, udp_frame(53)));
+        rules.exclude.push(parse_exclude("src-net=10.0.0.9/32,port=53"));
+        assert!(matches(&rules, udp_frame(53)));
+    }
+
+    #[test]
+    fn values_of_one_flag_are_alternatives() {
+        let mut rules = RuleSet::default();
+        rules.add(false, Condition::Port(53));
+        rules.add(false, Condition::Port(123));
+        rules.add(false, Condition::Proto(Layer4::Udp));
+        assert!(matches(&rules, udp_frame(53)));
+        assert!(matches(&rules, udp_frame(123)));
+        assert!(!matches(&rules, udp_frame(80)));
+    }
+}
@@ -1,5 +1,10 @@
+mod capture;
 mod commands;
+mod detect;
+mod filter;
+mod output;
 mod packet;
+mod reassembly;
 
 use clap::{Arg, ArgMatches, Command};
 
@@ -21,18 +26,139 @@ fn main() {
                         .help("Specific interface to sniff")
                         .required(false),
                 )
+                .arg(
+                    Arg::new("read")
+                        .short('r')
+                        .long("read")
+                        .takes_value(true)
+                        .conflicts_with("interface")
+                        .help("Read packets from a pcap/pcapng file instead of an interface")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("filter")
+                        .short('f')
+                        .long("filter")
+                        .takes_value(true)
+                        .help("BPF capture filter expression, e.g. \"tcp port 22\"")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("display-filter")
+                        .short('Y')
+                        .long("display-filter")
+                        .takes_value(true)
+                        .help("Filter on decoded fields, e.g. \"tcp.dport == 22 && ip.src in 10.0.0.0/8\"")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("write")
+                        .short('w')
+                        .long("write")
+                        .takes_value(true)
+                        .help("Save captured packets to a pcap/pcapng file (by extension)")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("rotate-size")
+                        .long("rotate-size")
+                        .takes_value(true)
+                        .requires("write")
+                        .help("Start a new output file after this many MB")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("rotate-seconds")
+                        .long("rotate-seconds")
+                        .takes_value(true)
+                        .requires("write")
+                        .help("Start a new output file after this many seconds of traffic")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("rotate-count")
+                        .long("rotate-count")
+                        .takes_value(true)
+                        .requires("write")
+                        .help("Start a new output file after this many packets")
+                        .required(false),
+                )
                 .arg(
                     Arg::new("mac")
                         .short('m')
                         .takes_value(true)
-                        .help("MAC Filter to apply to data")
+                        .help("MAC Filter to apply to data (added to the capture filter)")
                         .required(false),
                 )
                 .arg(
                     Arg::new("ip")
                         .short('p')
                         .takes_value(true)
-                        .help("IP Filter to apply to data. Can be iPv6 or iPv4")
+                        .help("IP Filter to apply to data. Can be iPv6 or iPv4 (added to the capture filter)")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("net")
+                        .long("net")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .help("CIDR network either side must be in, prefix with ! to exclude")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("src-net")
+                        .long("src-net")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .help("CIDR network the source must be in, prefix with ! to exclude")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("dst-net")
+                        .long("dst-net")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .help("CIDR network the destination must be in, prefix with ! to exclude")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("port")
+                        .long("port")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .help("Port either side must use, prefix with ! to exclude")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("src-port")
+                        .long("src-port")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .help("Source port to match, prefix with ! to exclude")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("dst-port")
+                        .long("dst-port")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .help("Destination port to match, prefix with ! to exclude")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("proto")
+                        .long("proto")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .help("Protocol to match (tcp, udp, icmp, icmpv6, arp, igmp or a number), prefix with ! to exclude")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("exclude")
+                        .long("exclude")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .help("Hide packets matching all of these filters, e.g. \"src-net=10.0.0.5/32,port=22\"")
                         .required(false),
                 )
                 .arg(
@@ -42,6 +168,23 @@ fn main() {
                         .help("Disables colors in output")
                         .required(false),
                 )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .takes_value(true)
+                        .possible_values(["text", "table", "csv", "json"])
+                        .default_value("text")
+                        .help("Output format: pipe separated text, aligned table, csv with a header row, or one json object per line")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("verbose")
+                        .short('v')
+                        .long("verbose")
+                        .takes_value(false)
+                        .help("Show IP header details (TTL, ID, fragmentation, TOS, IPv6 extension headers) with each packet")
+                        .required(false),
+                )
                 .arg(
                     Arg::new("rdns")
                         .short('d')
@@ -50,6 +193,67 @@ fn main() {
                         .required(false),
                 ),
         )
+        .subcommand(
+            Command::new("follow")
+                .about("print the reassembled client and server data of one TCP conversation")
+                .arg(
+                    Arg::new("interface")
+                        .short('i')
+                        .long("interface")
+                        .takes_value(true)
+                        .help("Specific interface to sniff")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("read")
+                        .short('r')
+                        .long("read")
+                        .takes_value(true)
+                        .conflicts_with("interface")
+                        .help("Read packets from a pcap/pcapng file instead of an interface")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("filter")
+                        .short('f')
+                        .long("filter")
+                        .takes_value(true)
+                        .help("BPF capture filter expression, e.g. \"tcp port 22\"")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("stream")
+                        .short('s')
+                        .long("stream")
+                        .takes_value(true)
+                        .conflicts_with("display-filter")
+                        .help("Index of the TCP conversation in order of appearance, starting at 0 (default)")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("display-filter")
+                        .short('Y')
+                        .long("display-filter")
+                        .takes_value(true)
+                        .help("Follow the conversation of the first TCP packet matching this filter, e.g. \"tcp.port == 80\"")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("hex")
+                        .short('x')
+                        .long("hex")
+                        .takes_value(false)
+                        .help("Show the data as a hexdump instead of text")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("no-format")
+                        .long("no-format")
+                        .takes_value(false)
+                        .help("Disables colors in output, server data is indented instead")
+                        .required(false),
+                ),
+        )
         .subcommand(
             Command::new("anomaly")
                 .about("search for data that seems a-typical to the given setup")
@@ -67,6 +271,63 @@ fn main() {
                         .help("Specific interface to sniff")
                         .required(false),
                 )
+                .arg(
+                    Arg::new("read")
+                        .short('r')
+                        .long("read")
+                        .takes_value(true)
+                        .conflicts_with("interface")
+                        .help("Read packets from a pcap/pcapng file instead of an interface")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("filter")
+                        .short('f')
+                        .long("filter")
+                        .takes_value(true)
+                        .help("BPF capture filter expression, e.g. \"tcp port 22\"")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("display-filter")
+                        .short('Y')
+                        .long("display-filter")
+                        .takes_value(true)
+                        .help("Filter on decoded fields, e.g. \"tcp.dport == 22 && ip.src in 10.0.0.0/8\"")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("write")
+                        .short('w')
+                        .long("write")
+                        .takes_value(true)
+                        .help("Save flagged packets to a pcap/pcapng file (by extension), pcapng keeps the reasons as comments")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("rotate-size")
+                        .long("rotate-size")
+                        .takes_value(true)
+                        .requires("write")
+                        .help("Start a new output file after this many MB")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("rotate-seconds")
+                        .long("rotate-seconds")
+                        .takes_value(true)
+                        .requires("write")
+                        .help("Start a new output file after this many seconds of traffic")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("rotate-count")
+                        .long("rotate-count")
+                        .takes_value(true)
+                        .requires("write")
+                        .help("Start a new output file after this many packets")
+                        .required(false),
+                )
                 .arg(
                     Arg::new("no-format")
                         .long("no-format")
@@ -74,6 +335,22 @@ fn main() {
                         .help("Disables colors in output")
                         .required(false),
                 )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .takes_value(true)
+                        .possible_values(["text", "table", "csv", "json"])
+                        .default_value("text")
+                        .help("Output format: pipe separated text, aligned table, csv with a header row, or one json object per line")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("show-passwords")
+                        .long("show-passwords")
+                        .takes_value(false)
+                        .help("Show cleartext passwords in CLEARTEXT_CREDENTIALS alerts instead of masking them")
+                        .required(false),
+                )
                 .arg(
                     Arg::new("rdns")
                         .short('d')
@@ -97,6 +374,8 @@ fn process_command(matches: ArgMatches) {
         commands::sniff::sniff(matches)
     } else if let Some(matches) = matches.subcommand_matches("anomaly") {
         commands::anomaly::anomaly(matches)
+    } else if let Some(matches) = matches.subcommand_matches("follow") {
+        commands::follow::follow(matches)
     } else if let Some(matches) = matches.subcommand_matches("init") {
         commands::init::init(matches)
     } else {
@@ -0,0 +1,380 @@
+pub(crate) mod record;
+
+use self::record::Record;
+use clap::ArgMatches;
+use term::StdoutTerminal;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+    Csv,
+    Table,
+}
+
+impl Format {
+    /// Formats meant for a person rather than another program
+    pub fn is_human(&self) -> bool {
+        matches!(self, Format::Text | Format::Table)
+    }
+}
+
+/// Output format chosen with `--output`
+pub fn open(matches: &ArgMatches) -> Format {
+    match matches.value_of("output") {
+        Some("json") => Format::Json,
+        Some("csv") => Format::Csv,
+        Some("table") => Format::Table,
+        _ => Format::Text,
+    }
+}
+
+/// Column widths of the table format, fixed up front so every row lines up
+struct Columns {
+    number: usize,
+    time: usize,
+    addr: usize,
+    tag: usize,
+    len: usize,
+    info: usize,
+}
+
+impl Columns {
+    fn new(rdns: bool, alerts: bool) -> Columns {
+        Columns {
+            number: 7,
+            time: 14,
+            // Longest IPv6 address, plus room for a " (hostname)" suffix
+            addr: if rdns { 39 + 32 } else { 39 },
+            tag: 8,
+            len: 6,
+            // Alerts put their reasons after the info column, so keep it narrow
+            info: if alerts { 40 } else { 60 },
+        }
+    }
+}
+
+/// How records are laid out as lines, whatever they are written to
+struct Layout {
+    format: Format,
+    alerts: bool,
+    columns: Columns,
+}
+
+impl Layout {
+    fn new(format: Format, rdns: bool, alerts: bool) -> Layout {
+        Layout {
+            format,
+            alerts,
+            columns: Columns::new(rdns, alerts),
+        }
+    }
+
+    /// Column headings for the formats that have them
+    fn header(&self) -> Option<String> {
+        let line = match self.format {
+            Format::Csv => {
+                let mut fields = vec![
+                    "number",
+                    "timestamp",
+                    "relative_time",
+                    "src_mac",
+                    "dst_mac",
+                    "vlan",
+                    "src_ip",
+                    "dst_ip",
+                    "src_host",
+                    "dst_host",
+                    "src_port",
+                    "dst_port",
+                    "protocol",
+                    "ttl",
+                    "tag",
+                    "len",
+                    "info",
+                ];
+                if self.alerts {
+                    fields.push("reasons");
+                }
+                fields.join(",")
+            }
+            Format::Table => {
+                let c = &self.columns;
+                let mut line = format!(
+                    "{:>number$} {:>time$} {:<addr$} {:<addr$} {:<tag$} {:>len$} {:<info$}",
+                    "No.",
+                    "Time",
+                    "Source",
+                    "Destination",
+                    "Protocol",
+                    "Length",
+                    "Info",
+                    number = c.number,
+                    time = c.time,
+                    addr = c.addr,
+                    tag = c.tag,
+                    len = c.len,
+                    info = c.info,
+                );
+                if self.alerts {
+                    line.push_str(" Reasons");
+                }
+                line
+            }
+            Format::Text | Format::Json => return None,
+        };
+        Some(String::from(line.trim_end()))
+    }
+
+    fn line(&self, record: &Record) -> String {
+        match self.format {
+            Format::Text => self.text(record),
+            Format::Table => self.table(record),
+            Format::Csv => self.csv(record),
+            Format::Json => serde_json::to_string(record).unwrap(),
+        }
+    }
+
+    /// Source and destination as shown to a person, ARP is addressed by MAC
+    fn endpoints(record: &Record) -> (String, String) {
+        if record.link_only {
+            return (record.src_mac.clone(), record.dst_mac.clone());
+        }
+        let named = |ip, host: &Option<String>| match host {
+            Some(host) => format!("{} ({})", ip, host),
+            None => format!("{}", ip),
+        };
+        (
+            named(record.src_ip, &record.src_host),
+            named(record.dst_ip, &record.dst_host),
+        )
+    }
+
+    fn reasons(record: &Record) -> String {
+        match &record.reasons {
+            Some(reasons) => reasons.iter().map(|r| format!("{};", r)).collect(),
+            None => String::new(),
+        }
+    }
+
+    fn text(&self, record: &Record) -> String {
+        let (src, dst) = Self::endpoints(record);
+        let mut line = format!(
+            "{} | {:.9} | {} | {} | {} | {} | {}",
+            record.number, record.relative_time, src, dst, record.tag, record.len, record.info
+        );
+        if self.alerts {
+            line.push_str(" | ");
+            line.push_str(&Self::reasons(record));
+        }
+        line
+    }
+
+    fn table(&self, record: &Record) -> String {
+        let c = &self.columns;
+        let (src, dst) = Self::endpoints(record);
+        let mut line = format!(
+            "{:>number$} {:>time$.6} {:<addr$} {:<addr$} {:<tag$} {:>len$} {:<info$}",
+            record.number,
+            record.relative_time,
+            fit(&src, c.addr),
+            fit(&dst, c.addr),
+            fit(&record.tag, c.tag),
+            record.len,
+            fit(&record.info, c.info),
+            number = c.number,
+            time = c.time,
+            addr = c.addr,
+            tag = c.tag,
+            len = c.len,
+            info = c.info,
+        );
+        if self.alerts {
+            line.push(' ');
+            line.push_str(&Self::reasons(record));
+        }
+        String::from(line.trim_end())
+    }
+
+    fn csv(&self, record: &Record) -> String {
+        let optional = |value: Option<String>| value.unwrap_or_default();
+        let mut fields = vec![
+            record.number.to_string(),
+            format!("{:.6}", record.timestamp),
+            format!("{:.9}", record.relative_time),
+            record.src_mac.clone(),
+            record.dst_mac.clone(),
+            optional(record.vlan.map(|v| v.to_string())),
+            record.src_ip.to_string(),
+            record.dst_ip.to_string(),
+            optional(record.src_host.clone()),
+            optional(record.dst_host.clone()),
+            optional(record.src_port.map(|p| p.to_string())),
+            optional(record.dst_port.map(|p| p.to_string())),
+            record.protocol.clone(),
+            optional(record.ttl.map(|t| t.to_string())),
+            record.tag.clone(),
+            record.len.to_string(),
+            record.info.clone(),
+        ];
+        if self.alerts {
+            fields.push(record.reasons.as_ref().map(|r| r.join(";")).unwrap_or_default());
+        }
+        fields
+            .iter()
+            .map(|f| csv_escape(f))
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+}
+
+/// Writes [`Record`]s to the terminal in the format chosen on the command line
+pub struct Printer {
+    term: Box<StdoutTerminal>,
+    color: bool,
+    rdns: bool,
+    layout: Layout,
+}
+
+impl Printer {
+    /// `alerts` adds the reasons column anomaly reports for every record
+    pub fn new(matches: &ArgMatches, term: Box<StdoutTerminal>, alerts: bool) -> Printer {
+        let format = open(matches);
+        let rdns = matches.is_present("rdns");
+        Printer {
+            term,
+            color: format.is_human() && !matches.is_present("no-format"),
+            rdns,
+            layout: Layout::new(format, rdns, alerts),
+        }
+    }
+
+    /// Column headings for the formats that have them
+    pub fn header(&mut self) {
+        if let Some(line) = self.layout.header() {
+            writeln!(self.term, "{}", line).unwrap();
+        }
+    }
+
+    pub fn write(&mut self, mut record: Record, color: Option<term::color::Color>) {
+        if self.rdns {
+            record.resolve();
+        }
+        let line = self.layout.line(&record);
+        if let (true, Some(color)) = (self.color, color) {
+            self.term.fg(color).unwrap();
+        }
+        writeln!(self.term, "{}", line).unwrap();
+        if self.color {
+            self.term.reset().unwrap();
+        }
+    }
+}
+
+/// Cut a value down to a column width, marking that it was cut
+fn fit(value: &str, width: usize) -> String {
+    if value.chars().count() <= width {
+        return String::from(value);
+    }
+    let mut cut: String = value.chars().take(width - 1).collect();
+    cut.push('~');
+    cut
+}
+
+fn csv_escape(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        String::from(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+
+    fn alert(src: &str, dst: &str) -> Record {
+        let src: IpAddr = src.parse().unwrap();
+        let mut record = Record::alert(
+            7,
+            1_700_000_000.25,
+            1.5,
+            src,
+            dst.parse().unwrap(),
+            "SCAN",
+            "ports 22,80 \"fast\"",
+        );
+        record.reasons = Some(vec![String::from("PORT_SCAN (10.0.0.1)")]);
+        record
+    }
+
+    #[test]
+    fn text_keeps_the_pipe_separated_line() {
+        let layout = Layout::new(Format::Text, false, true);
+        assert_eq!(layout.header(), None);
+        assert_eq!(
+            layout.line(&alert("10.0.0.1", "10.0.0.2")),
+            "7 | 1.500000000 | 10.0.0.1 | 10.0.0.2 | SCAN | 0 | ports 22,80 \"fast\" | PORT_SCAN (10.0.0.1);"
+        );
+    }
+
+    #[test]
+    fn csv_quotes_what_needs_it() {
+        let layout = Layout::new(Format::Csv, false, true);
+        let header = layout.header().unwrap();
+        assert!(header.starts_with("number,timestamp,relative_time,src_mac"));
+        assert!(header.ends_with(",info,reasons"));
+        assert_eq!(
+            layout.line(&alert("10.0.0.1", "10.0.0.2")),
+            "7,1700000000.250000,1.500000000,,,,10.0.0.1,10.0.0.2,,,,,SCAN,,SCAN,0,\
+             \"ports 22,80 \"\"fast\"\"\",PORT_SCAN (10.0.0.1)"
+        );
+        assert!(!Layout::new(Format::Csv, false, false)
+            .header()
+            .unwrap()
+            .ends_with("reasons"));
+    }
+
+    #[test]
+    fn json_has_one_object_per_record() {
+        let layout = Layout::new(Format::Json, false, true);
+        assert_eq!(layout.header(), None);
+        let json: serde_json::Value =
+            serde_json::from_str(&layout.line(&alert("10.0.0.1", "10.0.0.2"))).unwrap();
+        assert_eq!(json["src_ip"], "10.0.0.1");
+        assert_eq!(json["protocol"], "SCAN");
+        assert_eq!(json["reasons"], serde_json::json!(["PORT_SCAN (10.0.0.1)"]));
+        assert!(json["vlan"].is_null());
+        assert!(json.get("src_host").is_none());
+        assert!(json.get("link_only").is_none());
+    }
+
+    #[test]
+    fn table_columns_line_up_whatever_the_address() {
+        let layout = Layout::new(Format::Table, false, true);
+        let header = layout.header().unwrap();
+        let short = layout.line(&alert("10.0.0.1", "10.0.0.2"));
+        let long = layout.line(&alert("2001:db8:1111:2222:3333:4444:5555:6666", "::1"));
+        let column = |line: &str, text: &str| line.find(text).unwrap();
+        assert_eq!(column(&header, "Protocol"), column(&short, "SCAN"));
+        assert_eq!(column(&short, "SCAN"), column(&long, "SCAN"));
+        assert_eq!(column(&header, "Reasons"), column(&long, "PORT_SCAN"));
+        assert!(short.starts_with("      7       1.500000 10.0.0.1 "));
+    }
+
+    #[test]
+    fn fit_marks_what_it_cuts() {
+        assert_eq!(fit("abc", 3), "abc");
+        assert_eq!(fit("abcdef", 4), "abc~");
+        assert_eq!(fit("éééé", 3), "éé~");
+    }
+
+    #[test]
+    fn csv_escape_quotes_separators_quotes_and_newlines() {
+        assert_eq!(csv_escape("plain text"), "plain text");
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_escape("two\nlines"), "\"two\nlines\"");
+    }
+}
@@ -0,0 +1,219 @@
+use crate::packet::classify::Content;
+use crate::packet::error::ParseError;
+use crate::packet::ethernet::Ethernet;
+use crate::packet::protocol::*;
+use crate::packet::Decoded;
+use dns_lookup::lookup_addr;
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr};
+
+/// Tag of rows for frames that could not be decoded
+pub const MALFORMED_TAG: &str = "MALFORMED";
+
+/// The highest layer the packet was decoded to: the application, the content
+/// its stream was recognised as, or the transport or IP protocol
+fn protocol(packet: &Decoded) -> String {
+    match packet.transport {
+        Some(transport) => match (&transport.application, transport.content) {
+            (Some(application), _) => String::from(application.name()),
+            (None, Some(content)) if content != Content::Unknown => content.to_string(),
+            _ => transport.protocol.to_string(),
+        },
+        None => packet.ip.protocol.to_string(),
+    }
+}
+
+/// One packet (or anomaly alert) in the shape the structured outputs share
+#[derive(Serialize)]
+pub struct Record {
+    pub number: u64,
+    pub timestamp: f64,
+    pub relative_time: f64,
+    pub src_mac: String,
+    pub dst_mac: String,
+    pub vlan: Option<u16>,
+    pub src_ip: IpAddr,
+    pub dst_ip: IpAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub src_host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dst_host: Option<String>,
+    pub src_port: Option<u16>,
+    pub dst_port: Option<u16>,
+    pub protocol: String,
+    pub ttl: Option<u8>,
+    pub tag: String,
+    pub len: u32,
+    pub info: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasons: Option<Vec<String>>,
+    /// ARP and malformed frames have no IP endpoints, they are shown by MAC
+    #[serde(skip)]
+    pub(crate) link_only: bool,
+}
+
+impl Record {
+    pub fn new(
+        number: u64,
+        timestamp: f64,
+        relative_time: f64,
+        packet: &Decoded,
+        tag: &str,
+        info: &str,
+    ) -> Record {
+        Record {
+            number,
+            timestamp,
+            relative_time,
+            src_mac: packet.eth.src.to_string(),
+            dst_mac: packet.eth.dst.to_string(),
+            vlan: packet.eth.dot1q.as_ref().map(|v| v.vid),
+            src_ip: packet.ip.src,
+            dst_ip: packet.ip.dst,
+            src_host: None,
+            dst_host: None,
+            src_port: packet.transport.map(|t| t.src_port),
+            dst_port: packet.transport.map(|t| t.dst_port),
+            protocol: protocol(packet),
+            ttl: match (&packet.ip.ipv4, &packet.ip.ipv6) {
+                (Some(v4), _) => Some(v4.ttl),
+                (_, Some(v6)) => Some(v6.hop_limit),
+                _ => None,
+            },
+            tag: String::from(tag),
+            len: packet.len,
+            info: String::from(info),
+            reasons: None,
+            link_only: packet.ip.protocol == Layer4::Arp,
+        }
+    }
+
+    /// A frame the dissectors gave up on, with whatever Ethernet header was read
+    pub fn malformed(
+        number: u64,
+        timestamp: f64,
+        relative_time: f64,
+        len: u32,
+        eth: Option<&Ethernet>,
+        error: &ParseError,
+    ) -> Record {
+        let unspecified = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+        Record {
+            number,
+            timestamp,
+            relative_time,
+            src_mac: eth.map(|e| e.src.to_string()).unwrap_or_default(),
+            dst_mac: eth.map(|e| e.dst.to_string()).unwrap_or_default(),
+            vlan: eth.and_then(|e| e.dot1q.as_ref()).map(|v| v.vid),
+            src_ip: unspecified,
+            dst_ip: unspecified,
+            src_host: None,
+            dst_host: None,
+            src_port: None,
+            dst_port: None,
+            protocol: eth.map(|e| e.ethertype.to_string()).unwrap_or_default(),
+            ttl: None,
+            tag: String::from(match error.is_malformed() {
+                true => MALFORMED_TAG,
+                false => "???",
+            }),
+            len,
+            info: error.to_string(),
+            reasons: None,
+            link_only: true,
+        }
+    }
+
+    /// An alert about a conversation as a whole rather than any one packet of it
+    pub fn alert(
+        number: u64,
+        timestamp: f64,
+        relative_time: f64,
+        src_ip: IpAddr,
+        dst_ip: IpAddr,
+        tag: &str,
+        info: &str,
+    ) -> Record {
+        Record {
+            number,
+            timestamp,
+            relative_time,
+            src_mac: String::new(),
+            dst_mac: String::new(),
+            vlan: None,
+            src_ip,
+            dst_ip,
+            src_host: None,
+            dst_host: None,
+            src_port: None,
+            dst_port: None,
+            protocol: String::from(tag),
+            ttl: None,
+            tag: String::from(tag),
+            len: 0,
+            info: String::from(info),
+            reasons: None,
+            link_only: false,
+        }
+    }
+
+    /// Attach reverse DNS names, ARP records are left alone like the text output does
+    pub fn resolve(&mut self) {
+        if self.link_only {
+            return;
+        }
+        self.src_host = lookup_addr(&self.src_ip).ok();
+        self.dst_host = lookup_addr(&self.dst_ip).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::ip::IP;
+    use crate::packet::transport::Transport;
+
+    /// The protocol of a record for an Ethernet/IPv4 frame from 10.0.0.1 port
+    /// 40000 to 10.0.0.2 `port` carrying `payload` over IP protocol `protocol`
+    fn protocol_of(protocol: u8, port: u16, payload: &[u8]) -> String {
+        let mut transport = vec![0x9c, 0x40];
+        transport.extend(port.to_be_bytes());
+        match protocol {
+            // A bare SYN with a 20 byte header
+            6 => transport.extend([0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x02, 0, 0, 0, 0, 0, 0]),
+            _ => {
+                transport.extend((8 + payload.len() as u16).to_be_bytes());
+                transport.extend([0, 0]);
+            }
+        }
+        transport.extend(payload);
+        let mut frame = hex::decode("ffffffffffff0011223344550800").unwrap();
+        frame.extend(hex::decode("4500").unwrap());
+        frame.extend((20 + transport.len() as u16).to_be_bytes());
+        frame.extend([0, 1, 0, 0, 64, protocol, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+        frame.extend(transport);
+        let eth = Ethernet::try_from(frame).unwrap();
+        let ip = IP::new(&eth.payload, eth.ethertype).unwrap();
+        let transport = Transport::new(ip.payload, &ip.protocol).unwrap();
+        let decoded = Decoded {
+            len: 0,
+            eth: &eth,
+            ip: &ip,
+            transport: transport.as_ref(),
+            icmp: None,
+            arp: None,
+        };
+        Record::new(1, 0.0, 0.0, &decoded, "", "").protocol
+    }
+
+    #[test]
+    fn names_the_highest_layer_decoded() {
+        let query = hex::decode("000101000001000000000000076578616d706c6503636f6d0000010001");
+        assert_eq!(protocol_of(17, 53, &query.unwrap()), "DNS");
+        // Recognised by its content, whatever the port says
+        assert_eq!(protocol_of(6, 443, b"SSH-2.0-OpenSSH_9.6\r\n"), "SSH");
+        assert_eq!(protocol_of(6, 443, b""), "TCP");
+        assert_eq!(protocol_of(17, 9999, b"\x01\x02\x03"), "UDP");
+        assert_eq!(protocol_of(47, 0, b""), Layer4::from(47).to_string());
+    }
+}
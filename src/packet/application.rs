@@ -0,0 +1,58 @@
+use super::dhcp::Dhcp;
+use super::dns::Dns;
+use super::http::Http;
+use super::protocol::*;
+use super::tls::Tls;
+use std::fmt;
+
+/// What a transport payload carries, when one of the decoders knows it
+pub enum Application {
+    Dns(Dns),
+    Dhcp(Dhcp),
+    Http(Http),
+    Tls(Tls),
+}
+
+impl Application {
+    /// Decode a single segment or datagram by the ports it uses
+    pub fn new(
+        protocol: &Layer4,
+        src_port: u16,
+        dst_port: u16,
+        payload: &[u8],
+    ) -> Option<Application> {
+        let uses = |port: u16| src_port == port || dst_port == port;
+        match protocol {
+            Layer4::Udp if uses(53) || uses(5353) => Dns::new(payload).ok().map(Application::Dns),
+            Layer4::Udp if uses(67) || uses(68) => Dhcp::new(payload).ok().map(Application::Dhcp),
+            Layer4::Tcp if uses(53) => Dns::from_tcp(payload).ok().map(Application::Dns),
+            // HTTP and TLS are recognised by content, they run on too many ports to list
+            Layer4::Tcp if Http::detect(payload) => Http::new(payload).ok().map(Application::Http),
+            Layer4::Tcp if Tls::detect(payload) => Tls::new(payload).ok().map(Application::Tls),
+            _ => None,
+        }
+    }
+}
+
+impl Application {
+    /// The protocol's short name, e.g. `DNS`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Application::Dns(_) => "DNS",
+            Application::Dhcp(_) => "DHCP",
+            Application::Http(_) => "HTTP",
+            Application::Tls(_) => "TLS",
+        }
+    }
+}
+
+impl fmt::Display for Application {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Application::Dns(dns) => write!(f, "{}", dns),
+            Application::Dhcp(dhcp) => write!(f, "{}", dhcp),
+            Application::Http(http) => write!(f, "{}", http),
+            Application::Tls(tls) => write!(f, "{}", tls),
+        }
+    }
+}
@@ -1,38 +1,75 @@
+use super::error::{require, ParseError};
+use super::ethernet::MacAddr;
+use byteorder::{BigEndian, ByteOrder};
 use std::fmt;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
+
+/// Ethernet/IPv4 ARP, the only combination decoded
+pub(crate) const ARP_PACKET_SIZE: usize = 28;
+
+const HARDWARE_ETHERNET: u16 = 1;
+const PROTOCOL_IPV4: u16 = 0x0800;
+
+pub const REQUEST: u16 = 1;
+pub const REPLY: u16 = 2;
 
 pub struct Arp {
-    src_ip: IpAddr,
-    dst_ip: IpAddr,
-    src_mac: String,
-    dst_mac: String,
+    pub hardware_type: u16,
+    pub protocol_type: u16,
+    pub hardware_size: u8,
+    pub protocol_size: u8,
     pub opcode: u16,
+    pub src_mac: MacAddr,
+    pub src_ip: IpAddr,
+    pub dst_mac: MacAddr,
+    pub dst_ip: IpAddr,
 }
 
 impl Arp {
-    pub fn new(src_ip: IpAddr, dst_ip: IpAddr, data: &[u8]) -> Option<Arp> {
-        Some(Arp {
-            src_ip,
-            dst_ip,
-            src_mac: format!(
-                "{:02X?}:{:02X?}:{:02X?}:{:02X?}:{:02X?}:{:02X?}",
-                data[8], data[9], data[10], data[11], data[12], data[13]
-            ),
-            dst_mac: format!(
-                "{:02X?}:{:02X?}:{:02X?}:{:02X?}:{:02X?}:{:02X?}",
-                data[18], data[19], data[20], data[21], data[22], data[23]
-            ),
-            opcode: ((data[6] as u16) << 8) | data[7] as u16,
+    pub fn new(data: &[u8]) -> Result<Arp, ParseError> {
+        require("ARP", data, 8)?;
+        let hardware_type = BigEndian::read_u16(&data[0..2]);
+        let protocol_type = BigEndian::read_u16(&data[2..4]);
+        let (hardware_size, protocol_size) = (data[4], data[5]);
+        if hardware_type != HARDWARE_ETHERNET
+            || protocol_type != PROTOCOL_IPV4
+            || hardware_size != 6
+            || protocol_size != 4
+        {
+            return Err(ParseError::Unsupported {
+                layer: "ARP",
+                protocol: format!("hardware {} over 0x{:04x}", hardware_type, protocol_type),
+            });
+        }
+        require("ARP", data, ARP_PACKET_SIZE)?;
+        Ok(Arp {
+            hardware_type,
+            protocol_type,
+            hardware_size,
+            protocol_size,
+            opcode: BigEndian::read_u16(&data[6..8]),
+            src_mac: MacAddr::from(&data[8..14]),
+            src_ip: IpAddr::V4(Ipv4Addr::new(data[14], data[15], data[16], data[17])),
+            dst_mac: MacAddr::from(&data[18..24]),
+            dst_ip: IpAddr::V4(Ipv4Addr::new(data[24], data[25], data[26], data[27])),
         })
     }
+
+    /// An announcement of the sender's own binding rather than a question or answer
+    pub fn is_gratuitous(&self) -> bool {
+        self.src_ip == self.dst_ip
+    }
 }
 
 impl fmt::Display for Arp {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.opcode {
-            1 => write!(f, "Who has {}? Tell {}", self.dst_ip, self.src_ip),
-            2 => write!(f, "{} is at {}", self.src_ip, self.src_mac),
-            _ => write!(f, "ARP"),
+            _ if self.is_gratuitous() => {
+                write!(f, "Gratuitous ARP for {} ({})", self.src_ip, self.src_mac)
+            }
+            REQUEST => write!(f, "Who has {}? Tell {}", self.dst_ip, self.src_ip),
+            REPLY => write!(f, "{} is at {}", self.src_ip, self.src_mac),
+            opcode => write!(f, "ARP opcode {}", opcode),
         }
     }
 }
@@ -0,0 +1,164 @@
+use super::dns::Dns;
+use super::http::Http;
+use super::protocol::*;
+use super::tls::Tls;
+use std::fmt;
+
+/// What a payload looks like it carries, going by its bytes rather than its ports
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Content {
+    Ssh,
+    Tls,
+    Http,
+    Dns,
+    Smb,
+    /// Data that matches none of the signatures
+    Unknown,
+}
+
+impl fmt::Display for Content {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Content::Ssh => "SSH",
+            Content::Tls => "TLS",
+            Content::Http => "HTTP",
+            Content::Dns => "DNS",
+            Content::Smb => "SMB",
+            Content::Unknown => "UNKNOWN",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Match a payload against the known signatures, `None` when there is no payload
+pub fn identify(protocol: &Layer4, payload: &[u8]) -> Option<Content> {
+    if payload.is_empty() {
+        return None;
+    }
+    let content = match protocol {
+        Layer4::Tcp if payload.starts_with(b"SSH-") => Content::Ssh,
+        Layer4::Tcp if Tls::detect(payload) => Content::Tls,
+        Layer4::Tcp if Http::detect(payload) => Content::Http,
+        Layer4::Tcp if is_smb(payload) => Content::Smb,
+        Layer4::Tcp if Dns::from_tcp(payload).is_ok_and(has_records) => Content::Dns,
+        Layer4::Udp if Dns::new(payload).is_ok_and(has_records) => Content::Dns,
+        _ => Content::Unknown,
+    };
+    Some(content)
+}
+
+/// What the content should be for a port label, for the labels the signatures cover
+pub fn expected(protocol: &Layer4, port_tag: &str) -> Option<Content> {
+    match (protocol, port_tag) {
+        (Layer4::Tcp, "SSH") => Some(Content::Ssh),
+        (Layer4::Tcp, "HTTPS") => Some(Content::Tls),
+        (Layer4::Tcp, "HTTP") => Some(Content::Http),
+        (Layer4::Tcp, "SMB") => Some(Content::Smb),
+        (_, "DNS" | "MDNS") => Some(Content::Dns),
+        _ => None,
+    }
+}
+
+/// Twelve bytes of anything parse as an empty DNS header, a message has to
+/// ask or answer something, mDNS announcements only answer
+fn has_records(dns: Dns) -> bool {
+    !dns.questions.is_empty() || !dns.answers.is_empty()
+}
+
+/// SMB1 or SMB2 magic, bare or behind the 4 byte NetBIOS session header
+fn is_smb(data: &[u8]) -> bool {
+    let magic = |d: &[u8]| d.starts_with(b"\xffSMB") || d.starts_with(b"\xfeSMB");
+    magic(data) || (data.len() > 4 && data[0] == 0 && magic(&data[4..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::transport::Transport;
+
+    /// A PSH/ACK TCP segment carrying `payload`
+    fn tcp(src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(src_port.to_be_bytes());
+        data.extend(dst_port.to_be_bytes());
+        data.extend(hex::decode("000003e8000007d05018ffff00000000").unwrap());
+        data.extend(payload);
+        data
+    }
+
+    /// A UDP datagram carrying `payload`
+    fn udp(src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(src_port.to_be_bytes());
+        data.extend(dst_port.to_be_bytes());
+        data.extend((8 + payload.len() as u16).to_be_bytes());
+        data.extend([0, 0]);
+        data.extend(payload);
+        data
+    }
+
+    /// Content and mismatched port label the way anomaly sees them
+    fn classify(protocol: &Layer4, data: &[u8]) -> (Option<Content>, Option<String>) {
+        let transport = Transport::new(data, protocol).unwrap().unwrap();
+        (transport.content, transport.port_mismatch())
+    }
+
+    /// An mDNS announcement of an IPP printer, no questions and two answers
+    /// with compressed names as RFC 6762 has responders send them
+    const MDNS_ANNOUNCEMENT: &str = "000084000000000200000000\
+        045f697070045f746370056c6f63616c00000c000100001194000a077072696e746572c00c\
+        077072696e746572c01600018001000000780004c0a80132";
+
+    #[test]
+    fn ssh_on_the_https_port_is_a_mismatch() {
+        let data = tcp(50000, 443, b"SSH-2.0-OpenSSH_9.6\r\n");
+        assert_eq!(
+            classify(&Layer4::Tcp, &data),
+            (Some(Content::Ssh), Some(String::from("HTTPS")))
+        );
+    }
+
+    #[test]
+    fn tls_on_the_ssh_port_is_a_mismatch() {
+        let data = tcp(50000, 22, &hex::decode("160301020001").unwrap());
+        assert_eq!(
+            classify(&Layer4::Tcp, &data),
+            (Some(Content::Tls), Some(String::from("SSH")))
+        );
+    }
+
+    #[test]
+    fn http_on_an_unlabelled_port_is_not() {
+        let data = tcp(50000, 8080, b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        let transport = Transport::new(&data, &Layer4::Tcp).unwrap().unwrap();
+        assert_eq!(transport.content, Some(Content::Http));
+        assert_eq!(transport.port_mismatch(), None);
+        assert_eq!(transport.get_tag(), "HTTP");
+    }
+
+    #[test]
+    fn a_segment_from_mid_stream_is_left_alone() {
+        let data = tcp(443, 50000, &[0x8e, 0x21, 0x07, 0xd4, 0x5a, 0x90, 0x11]);
+        assert_eq!(classify(&Layer4::Tcp, &data), (None, None));
+        assert_eq!(identify(&Layer4::Tcp, &data[20..]), Some(Content::Unknown));
+    }
+
+    #[test]
+    fn an_mdns_announcement_is_dns() {
+        let message = hex::decode(MDNS_ANNOUNCEMENT).unwrap();
+        let dns = Dns::new(&message).unwrap();
+        assert!(dns.questions.is_empty());
+        assert_eq!(dns.answers[1].name, "printer.local");
+        let data = udp(5353, 5353, &message);
+        assert_eq!(classify(&Layer4::Udp, &data), (Some(Content::Dns), None));
+    }
+
+    #[test]
+    fn an_empty_dns_header_is_not() {
+        let data = udp(5353, 5353, &[0; 12]);
+        assert_eq!(
+            classify(&Layer4::Udp, &data),
+            (Some(Content::Unknown), Some(String::from("MDNS")))
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use super::error::{require, ParseError};
+use super::ethernet::MacAddr;
+use byteorder::{BigEndian, ByteOrder};
+use std::fmt;
+use std::net::Ipv4Addr;
+
+/// Fixed BOOTP header plus the magic cookie in front of the DHCP options
+const BOOTP_SIZE: usize = 240;
+
+const MAGIC_COOKIE: u32 = 0x6382_5363;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum MessageType {
+    Discover,
+    Offer,
+    Request,
+    Decline,
+    Ack,
+    Nak,
+    Release,
+    Inform,
+    Unknown(u8),
+}
+
+impl From<u8> for MessageType {
+    fn from(n: u8) -> Self {
+        match n {
+            1 => Self::Discover,
+            2 => Self::Offer,
+            3 => Self::Request,
+            4 => Self::Decline,
+            5 => Self::Ack,
+            6 => Self::Nak,
+            7 => Self::Release,
+            8 => Self::Inform,
+            unknown => Self::Unknown(unknown),
+        }
+    }
+}
+
+impl MessageType {
+    pub fn number(&self) -> u8 {
+        match self {
+            MessageType::Discover => 1,
+            MessageType::Offer => 2,
+            MessageType::Request => 3,
+            MessageType::Decline => 4,
+            MessageType::Ack => 5,
+            MessageType::Nak => 6,
+            MessageType::Release => 7,
+            MessageType::Inform => 8,
+            MessageType::Unknown(x) => *x,
+        }
+    }
+}
+
+impl fmt::Display for MessageType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MessageType::Discover => write!(f, "Discover"),
+            MessageType::Offer => write!(f, "Offer"),
+            MessageType::Request => write!(f, "Request"),
+            MessageType::Decline => write!(f, "Decline"),
+            MessageType::Ack => write!(f, "ACK"),
+            MessageType::Nak => write!(f, "NAK"),
+            MessageType::Release => write!(f, "Release"),
+            MessageType::Inform => write!(f, "Inform"),
+            MessageType::Unknown(x) => write!(f, "Unknown ({})", x),
+        }
+    }
+}
+
+/// A BOOTP message with the DHCP options a blue team cares about
+pub struct Dhcp {
+    /// 1 for requests from clients, 2 for replies from servers
+    pub op: u8,
+    pub xid: u32,
+    pub client_ip: Ipv4Addr,
+    /// Address the server is handing out
+    pub your_ip: Ipv4Addr,
+    pub relay_ip: Ipv4Addr,
+    pub client_mac: MacAddr,
+    pub message_type: Option<MessageType>,
+    pub requested_ip: Option<Ipv4Addr>,
+    pub server_id: Option<Ipv4Addr>,
+    pub routers: Vec<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr>,
+    /// Seconds
+    pub lease_time: Option<u32>,
+    pub hostname: Option<String>,
+}
+
+fn ipv4(data: &[u8]) -> Ipv4Addr {
+    Ipv4Addr::new(data[0], data[1], data[2], data[3])
+}
+
+fn ipv4_list(data: &[u8]) -> Vec<Ipv4Addr> {
+    data.chunks_exact(4).map(ipv4).collect()
+}
+
+impl Dhcp {
+    pub fn new(data: &[u8]) -> Result<Dhcp, ParseError> {
+        require("BOOTP", data, BOOTP_SIZE)?;
+        if BigEndian::read_u32(&data[236..240]) != MAGIC_COOKIE {
+            return Err(ParseError::Unsupported {
+                layer: "BOOTP",
+                protocol: String::from("without the DHCP magic cookie"),
+            });
+        }
+        let mut dhcp = Dhcp {
+            op: data[0],
+            xid: BigEndian::read_u32(&data[4..8]),
+            client_ip: ipv4(&data[12..16]),
+            your_ip: ipv4(&data[16..20]),
+            relay_ip: ipv4(&data[24..28]),
+            client_mac: MacAddr::from(&data[28..34]),
+            message_type: None,
+            requested_ip: None,
+            server_id: None,
+            routers: Vec::new(),
+            dns_servers: Vec::new(),
+            lease_time: None,
+            hostname: None,
+        };
+        let mut options = &data[BOOTP_SIZE..];
+        while let Some((&code, rest)) = options.split_first() {
+            match code {
+                0 => {
+                    options = rest;
+                    continue;
+                }
+                255 => break,
+                _ => {}
+            }
+            let len = match rest.first() {
+                Some(&len) if (len as usize) < rest.len() => len as usize,
+                _ => {
+                    return Err(ParseError::Truncated {
+                        layer: "DHCP option",
+                        needed: rest.first().map_or(1, |&len| len as usize + 1),
+                        available: rest.len(),
+                    })
+                }
+            };
+            let value = &rest[1..1 + len];
+            match (code, len) {
+                (53, 1) => dhcp.message_type = Some(MessageType::from(value[0])),
+                (50, 4) => dhcp.requested_ip = Some(ipv4(value)),
+                (54, 4) => dhcp.server_id = Some(ipv4(value)),
+                (3, _) => dhcp.routers = ipv4_list(value),
+                (6, _) => dhcp.dns_servers = ipv4_list(value),
+                (51, 4) => dhcp.lease_time = Some(BigEndian::read_u32(value)),
+                (12, _) => dhcp.hostname = Some(String::from_utf8_lossy(value).into_owned()),
+                _ => {}
+            }
+            options = &rest[1 + len..];
+        }
+        Ok(dhcp)
+    }
+
+    /// Sent by a server rather than a client
+    pub fn is_reply(&self) -> bool {
+        self.op == 2
+    }
+}
+
+impl fmt::Display for Dhcp {
+    /// e.g. `Offer 10.0.0.20 to 00:11:22:33:44:55 server 10.0.0.1 router 10.0.0.1 lease 86400s`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.message_type {
+            Some(message_type) => write!(f, "{}", message_type)?,
+            None => write!(f, "BOOTP {}", if self.is_reply() { "Reply" } else { "Request" })?,
+        }
+        let list = |ips: &[Ipv4Addr]| {
+            ips.iter()
+                .map(|ip| ip.to_string())
+                .collect::<Vec<String>>()
+                .join(",")
+        };
+        if !self.your_ip.is_unspecified() {
+            write!(f, " {}", self.your_ip)?;
+        }
+        if let Some(ip) = self.requested_ip {
+            write!(f, " {}", ip)?;
+        }
+        let direction = if self.is_reply() { "to" } else { "from" };
+        write!(f, " {} {}", direction, self.client_mac)?;
+        if let Some(hostname) = &self.hostname {
+            write!(f, " ({})", hostname)?;
+        }
+        if let Some(server) = self.server_id {
+            write!(f, " server {}", server)?;
+        }
+        if !self.routers.is_empty() {
+            write!(f, " router {}", list(&self.routers))?;
+        }
+        if !self.dns_servers.is_empty() {
+            write!(f, " dns {}", list(&self.dns_servers))?;
+        }
+        if let Some(lease) = self.lease_time {
+            write!(f, " lease {}s", lease)?;
+        }
+        if !self.client_ip.is_unspecified() {
+            write!(f, " client {}", self.client_ip)?;
+        }
+        if !self.relay_ip.is_unspecified() {
+            write!(f, " relay {}", self.relay_ip)?;
+        }
+        write!(f, " xid 0x{:08x}", self.xid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A BOOTP message with transaction 0x12345678 for client 00:11:22:33:44:55
+    /// handing out `your_ip`, followed by the DHCP `options`
+    fn bootp(op: u8, your_ip: [u8; 4], options: &[u8]) -> Vec<u8> {
+        let mut data = vec![op, 1, 6, 0, 0x12, 0x34, 0x56, 0x78, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.extend(your_ip);
+        data.extend([0; 8]);
+        data.extend([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        data.extend([0; 10 + 64 + 128]);
+        data.extend(MAGIC_COOKIE.to_be_bytes());
+        data.extend(options);
+        data
+    }
+
+    #[test]
+    fn decodes_an_offer() {
+        let options = hex::decode(
+            "350102 36040a000001 03040a000001 06080808080801010101 330400015180 00ff"
+                .replace(' ', ""),
+        )
+        .unwrap();
+        let dhcp = Dhcp::new(&bootp(2, [10, 0, 0, 20], &options)).unwrap();
+        assert!(dhcp.is_reply());
+        assert!(dhcp.message_type == Some(MessageType::Offer));
+        assert_eq!(dhcp.server_id, Some(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(dhcp.lease_time, Some(86400));
+        assert_eq!(
+            dhcp.to_string(),
+            "Offer 10.0.0.20 to 00:11:22:33:44:55 server 10.0.0.1 router 10.0.0.1 \
+             dns 8.8.8.8,1.1.1.1 lease 86400s xid 0x12345678"
+        );
+    }
+
+    #[test]
+    fn decodes_a_request_with_a_hostname() {
+        let options =
+            hex::decode("3501033204 0a000014 0c066c6170746f70 ff".replace(' ', "")).unwrap();
+        let dhcp = Dhcp::new(&bootp(1, [0; 4], &options)).unwrap();
+        assert_eq!(dhcp.requested_ip, Some(Ipv4Addr::new(10, 0, 0, 20)));
+        assert_eq!(
+            dhcp.to_string(),
+            "Request 10.0.0.20 from 00:11:22:33:44:55 (laptop) xid 0x12345678"
+        );
+    }
+
+    #[test]
+    fn skips_options_of_the_wrong_length() {
+        // A two byte message type and a three byte server identifier
+        let options = hex::decode("35020201 36030a0000 ff".replace(' ', "")).unwrap();
+        let dhcp = Dhcp::new(&bootp(2, [0; 4], &options)).unwrap();
+        assert!(dhcp.message_type.is_none());
+        assert!(dhcp.server_id.is_none());
+        assert_eq!(
+            dhcp.to_string(),
+            "BOOTP Reply to 00:11:22:33:44:55 xid 0x12345678"
+        );
+    }
+
+    #[test]
+    fn rejects_what_is_not_dhcp() {
+        let mut data = bootp(1, [0; 4], &[255]);
+        assert!(matches!(
+            Dhcp::new(&data[..BOOTP_SIZE - 1]),
+            Err(ParseError::Truncated { layer: "BOOTP", .. })
+        ));
+        // An option whose length runs past the end
+        data.truncate(BOOTP_SIZE);
+        data.extend([12, 6, b'l', b'a']);
+        assert_eq!(
+            Dhcp::new(&data).err(),
+            Some(ParseError::Truncated {
+                layer: "DHCP option",
+                needed: 7,
+                available: 3
+            })
+        );
+        data[236] = 0;
+        assert!(matches!(
+            Dhcp::new(&data),
+            Err(ParseError::Unsupported { layer: "BOOTP", .. })
+        ));
+    }
+}
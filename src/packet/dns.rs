@@ -0,0 +1,350 @@
+use super::error::{require, ParseError};
+use byteorder::{BigEndian, ByteOrder};
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Compression pointers followed while reading one name, more means a loop
+const MAX_POINTERS: usize = 32;
+
+pub struct Question {
+    pub name: String,
+    pub qtype: u16,
+    pub class: u16,
+}
+
+pub enum RData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Ns(String),
+    Ptr(String),
+    Mx {
+        preference: u16,
+        exchange: String,
+    },
+    Txt(Vec<String>),
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+    },
+    Soa {
+        mname: String,
+        rname: String,
+        serial: u32,
+    },
+    Other(Vec<u8>),
+}
+
+impl fmt::Display for RData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RData::A(ip) => write!(f, "{}", ip),
+            RData::Aaaa(ip) => write!(f, "{}", ip),
+            RData::Cname(name) | RData::Ns(name) | RData::Ptr(name) => write!(f, "{}", name),
+            RData::Mx {
+                preference,
+                exchange,
+            } => write!(f, "{} {}", exchange, preference),
+            RData::Txt(strings) => write!(f, "\"{}\"", strings.join("\" \"")),
+            RData::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } => write!(f, "{}:{} {} {}", target, port, priority, weight),
+            RData::Soa {
+                mname,
+                rname,
+                serial,
+            } => write!(f, "{} {} {}", mname, rname, serial),
+            RData::Other(data) => write!(f, "{} bytes", data.len()),
+        }
+    }
+}
+
+pub struct ResourceRecord {
+    pub name: String,
+    pub rtype: u16,
+    /// Without the mDNS cache-flush bit
+    pub class: u16,
+    pub ttl: u32,
+    pub data: RData,
+}
+
+/// A DNS (or mDNS) message
+pub struct Dns {
+    pub id: u16,
+    pub response: bool,
+    pub opcode: u8,
+    pub rcode: u8,
+    pub questions: Vec<Question>,
+    pub answers: Vec<ResourceRecord>,
+    pub authorities: Vec<ResourceRecord>,
+    pub additionals: Vec<ResourceRecord>,
+}
+
+/// Mnemonic of a query or record type, as `dig` prints it
+pub fn type_name(rtype: u16) -> String {
+    match rtype {
+        1 => String::from("A"),
+        2 => String::from("NS"),
+        5 => String::from("CNAME"),
+        6 => String::from("SOA"),
+        10 => String::from("NULL"),
+        12 => String::from("PTR"),
+        15 => String::from("MX"),
+        16 => String::from("TXT"),
+        28 => String::from("AAAA"),
+        33 => String::from("SRV"),
+        41 => String::from("OPT"),
+        255 => String::from("ANY"),
+        other => format!("TYPE{}", other),
+    }
+}
+
+fn rcode_name(rcode: u8) -> String {
+    match rcode {
+        0 => String::from("NoError"),
+        1 => String::from("FormErr"),
+        2 => String::from("ServFail"),
+        3 => String::from("NXDomain"),
+        4 => String::from("NotImp"),
+        5 => String::from("Refused"),
+        other => format!("RCODE{}", other),
+    }
+}
+
+/// Read a possibly compressed name at `offset`, returning it and where the
+/// bytes after it start
+fn read_name(message: &[u8], mut offset: usize) -> Result<(String, usize), ParseError> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        require("DNS", message, offset + 1)?;
+        let len = message[offset] as usize;
+        match len & 0xc0 {
+            0x00 if len == 0 => {
+                end.get_or_insert(offset + 1);
+                break;
+            }
+            0x00 => {
+                require("DNS", message, offset + 1 + len)?;
+                let label = &message[offset + 1..offset + 1 + len];
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                offset += 1 + len;
+            }
+            0xc0 => {
+                require("DNS", message, offset + 2)?;
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return Err(ParseError::BadLength {
+                        layer: "DNS name pointer chain",
+                        length: pointers,
+                    });
+                }
+                end.get_or_insert(offset + 2);
+                offset = (BigEndian::read_u16(&message[offset..offset + 2]) & 0x3fff) as usize;
+            }
+            _ => {
+                return Err(ParseError::Unsupported {
+                    layer: "DNS name",
+                    protocol: format!("label type 0x{:02x}", len & 0xc0),
+                })
+            }
+        }
+    }
+    let name = match labels.is_empty() {
+        true => String::from("<Root>"),
+        false => labels.join("."),
+    };
+    Ok((name, end.unwrap()))
+}
+
+fn read_records(
+    message: &[u8],
+    offset: &mut usize,
+    count: u16,
+) -> Result<Vec<ResourceRecord>, ParseError> {
+    let mut records = Vec::new();
+    for _ in 0..count {
+        let (name, next) = read_name(message, *offset)?;
+        require("DNS", message, next + 10)?;
+        let rtype = BigEndian::read_u16(&message[next..next + 2]);
+        let class = BigEndian::read_u16(&message[next + 2..next + 4]) & 0x7fff;
+        let ttl = BigEndian::read_u32(&message[next + 4..next + 8]);
+        let len = BigEndian::read_u16(&message[next + 8..next + 10]) as usize;
+        let start = next + 10;
+        require("DNS", message, start + len)?;
+        let rdata = &message[start..start + len];
+        let name_at = |at: usize| read_name(message, at).map(|(name, _)| name);
+        let data = match (rtype, len) {
+            (1, 4) => RData::A(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3])),
+            (28, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(rdata);
+                RData::Aaaa(Ipv6Addr::from(octets))
+            }
+            (2, _) => RData::Ns(name_at(start)?),
+            (5, _) => RData::Cname(name_at(start)?),
+            (12, _) => RData::Ptr(name_at(start)?),
+            (15, 3..) => RData::Mx {
+                preference: BigEndian::read_u16(&rdata[0..2]),
+                exchange: name_at(start + 2)?,
+            },
+            (16, _) => {
+                let mut strings = Vec::new();
+                let mut rest = rdata;
+                while let Some((&n, tail)) = rest.split_first() {
+                    let n = (n as usize).min(tail.len());
+                    strings.push(String::from_utf8_lossy(&tail[..n]).into_owned());
+                    rest = &tail[n..];
+                }
+                RData::Txt(strings)
+            }
+            (33, 7..) => RData::Srv {
+                priority: BigEndian::read_u16(&rdata[0..2]),
+                weight: BigEndian::read_u16(&rdata[2..4]),
+                port: BigEndian::read_u16(&rdata[4..6]),
+                target: name_at(start + 6)?,
+            },
+            (6, _) => {
+                let (mname, after) = read_name(message, start)?;
+                let (rname, after) = read_name(message, after)?;
+                require("DNS", message, after + 4)?;
+                RData::Soa {
+                    mname,
+                    rname,
+                    serial: BigEndian::read_u32(&message[after..after + 4]),
+                }
+            }
+            _ => RData::Other(rdata.to_vec()),
+        };
+        records.push(ResourceRecord {
+            name,
+            rtype,
+            class,
+            ttl,
+            data,
+        });
+        *offset = start + len;
+    }
+    Ok(records)
+}
+
+impl Dns {
+    pub fn new(message: &[u8]) -> Result<Dns, ParseError> {
+        require("DNS", message, 12)?;
+        let flags = BigEndian::read_u16(&message[2..4]);
+        let counts: Vec<u16> = (0..4)
+            .map(|i| BigEndian::read_u16(&message[4 + i * 2..6 + i * 2]))
+            .collect();
+        let mut offset = 12;
+        let mut questions = Vec::new();
+        for _ in 0..counts[0] {
+            let (name, next) = read_name(message, offset)?;
+            require("DNS", message, next + 4)?;
+            questions.push(Question {
+                name,
+                qtype: BigEndian::read_u16(&message[next..next + 2]),
+                class: BigEndian::read_u16(&message[next + 2..next + 4]) & 0x7fff,
+            });
+            offset = next + 4;
+        }
+        Ok(Dns {
+            id: BigEndian::read_u16(&message[0..2]),
+            response: flags & 0x8000 != 0,
+            opcode: ((flags >> 11) & 0x0f) as u8,
+            rcode: (flags & 0x000f) as u8,
+            questions,
+            answers: read_records(message, &mut offset, counts[1])?,
+            authorities: read_records(message, &mut offset, counts[2])?,
+            additionals: read_records(message, &mut offset, counts[3])?,
+        })
+    }
+
+    /// DNS over TCP puts a two byte length in front of every message
+    pub fn from_tcp(payload: &[u8]) -> Result<Dns, ParseError> {
+        require("DNS over TCP", payload, 2)?;
+        let len = BigEndian::read_u16(&payload[0..2]) as usize;
+        require("DNS over TCP", payload, 2 + len)?;
+        Dns::new(&payload[2..2 + len])
+    }
+}
+
+impl fmt::Display for Dns {
+    /// Close to tcpdump, `A? example.com` for queries and
+    /// `A example.com 93.184.216.34` for answers
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.opcode != 0 {
+            write!(f, "opcode {} ", self.opcode)?;
+        }
+        if self.response && self.rcode != 0 {
+            write!(f, "{} ", rcode_name(self.rcode))?;
+        }
+        let parts: Vec<String> = match (self.response, self.answers.is_empty()) {
+            (true, false) => self
+                .answers
+                .iter()
+                .map(|r| format!("{} {} {}", type_name(r.rtype), r.name, r.data))
+                .collect(),
+            _ => self
+                .questions
+                .iter()
+                .map(|q| format!("{}? {}", type_name(q.qtype), q.name))
+                .collect(),
+        };
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A hand-built answer to `A? www.example.com`, a CNAME and an A record whose names
+    /// both point back into the question
+    const RESPONSE: &str = concat!(
+        "1a2b81800001000200000000",
+        "03777777076578616d706c6503636f6d0000010001",
+        "c00c0005000100000e100002c010",
+        "c0100001000100000e1000045db8d822",
+    );
+
+    #[test]
+    fn follows_compression_pointers() {
+        let message = hex::decode(RESPONSE).unwrap();
+        let dns = Dns::new(&message).unwrap();
+        assert!(dns.response);
+        assert_eq!(dns.questions[0].name, "www.example.com");
+        assert_eq!(dns.answers[0].name, "www.example.com");
+        assert!(matches!(&dns.answers[0].data, RData::Cname(name) if name == "example.com"));
+        assert_eq!(dns.answers[1].name, "example.com");
+        assert_eq!(dns.answers[1].ttl, 3600);
+        assert_eq!(
+            dns.to_string(),
+            "CNAME www.example.com example.com, A example.com 93.184.216.34"
+        );
+    }
+
+    #[test]
+    fn reads_names_over_tcp() {
+        let message = hex::decode(RESPONSE).unwrap();
+        let mut payload = (message.len() as u16).to_be_bytes().to_vec();
+        payload.extend(&message);
+        assert_eq!(Dns::from_tcp(&payload).unwrap().answers.len(), 2);
+        assert!(Dns::from_tcp(&payload[..payload.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn stops_at_pointer_loops() {
+        // The question's name points at itself
+        let message = hex::decode("000001000001000000000000c00c00010001").unwrap();
+        assert!(Dns::new(&message).is_err());
+        // And past the end of the message
+        let message = hex::decode("000001000001000000000000c0ff00010001").unwrap();
+        assert!(Dns::new(&message).is_err());
+    }
+}
@@ -0,0 +1,61 @@
+use std::fmt;
+
+/// Why a dissector gave up on a frame
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseError {
+    /// Fewer bytes were captured than the header needs
+    Truncated {
+        layer: &'static str,
+        needed: usize,
+        available: usize,
+    },
+    BadVersion { layer: &'static str, version: u8 },
+    /// A length field that contradicts the header it is in
+    BadLength { layer: &'static str, length: usize },
+    /// Well formed, but nothing here knows how to decode it
+    Unsupported { layer: &'static str, protocol: String },
+}
+
+impl ParseError {
+    /// Unsupported protocols are not the sender's fault, everything else is
+    pub fn is_malformed(&self) -> bool {
+        !matches!(self, ParseError::Unsupported { .. })
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Truncated {
+                layer,
+                needed,
+                available,
+            } => write!(
+                f,
+                "Truncated {} header ({} bytes needed, {} captured)",
+                layer, needed, available
+            ),
+            ParseError::BadVersion { layer, version } => {
+                write!(f, "Bad {} version {}", layer, version)
+            }
+            ParseError::BadLength { layer, length } => {
+                write!(f, "Bad {} length {}", layer, length)
+            }
+            ParseError::Unsupported { layer, protocol } => {
+                write!(f, "Unsupported {} protocol {}", layer, protocol)
+            }
+        }
+    }
+}
+
+/// Fail with [`ParseError::Truncated`] unless `data` holds at least `needed` bytes
+pub fn require(layer: &'static str, data: &[u8], needed: usize) -> Result<(), ParseError> {
+    if data.len() < needed {
+        return Err(ParseError::Truncated {
+            layer,
+            needed,
+            available: data.len(),
+        });
+    }
+    Ok(())
+}
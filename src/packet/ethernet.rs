@@ -1,9 +1,17 @@
+use super::error::{require, ParseError};
 use super::protocol::*;
 use byteorder::{ByteOrder, LittleEndian};
-use std::{fmt::Display, io, vec::Vec};
+use std::{fmt::Display, str::FromStr, vec::Vec};
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct MacAddr([u8; 6]);
 
+impl MacAddr {
+    pub fn octets(&self) -> [u8; 6] {
+        self.0
+    }
+}
+
 impl Display for MacAddr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         // Outsourcing the MAC display logic to here
@@ -28,23 +36,25 @@ impl From<&[u8]> for MacAddr {
 
 impl From<String> for MacAddr {
     fn from(data: String) -> Self {
-        let mut hexes = [0u8; 6];
-        hex::decode_to_slice(data.replace(':', ""), &mut hexes).expect("Decoding failed");
-        MacAddr(hexes)
+        data.parse().expect("Decoding failed")
     }
 }
 
-impl PartialEq for MacAddr {
-    fn eq(&self, other: &Self) -> bool {
-        println!("{:?} | {:?}", self.0, other.0);
-        self.0 == other.0
+impl FromStr for MacAddr {
+    type Err = hex::FromHexError;
+
+    /// Six hex octets, colons between them optional, e.g. `00:11:22:33:44:55`
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        let mut hexes = [0u8; 6];
+        hex::decode_to_slice(data.replace(':', ""), &mut hexes)?;
+        Ok(MacAddr(hexes))
     }
 }
 
 pub struct Dot1Q {
-    pcp: u8,
-    dei: bool,
-    vid: u16,
+    pub pcp: u8,
+    pub dei: bool,
+    pub vid: u16,
 }
 
 impl From<&[u8]> for Dot1Q {
@@ -63,7 +73,8 @@ impl From<&[u8]> for Dot1Q {
     }
 }
 
-pub(crate) const ETHER_FRAME_MIN_SIZE: usize = 18;
+pub(crate) const ETHER_HEADER_SIZE: usize = 14;
+pub(crate) const DOT1Q_HEADER_SIZE: usize = 18;
 
 pub struct Ethernet {
     pub dst: MacAddr,
@@ -74,29 +85,25 @@ pub struct Ethernet {
 }
 
 impl TryFrom<Vec<u8>> for Ethernet {
-    type Error = io::Error;
+    type Error = ParseError;
 
     /// Try to read an Ethernet packet from bytes
     ///
     /// ## Errors
-    /// * [`std::io::ErrorKind::UnexpectedEof`] - If input data array too short
+    /// * [`ParseError::Truncated`] - If input data array too short
     fn try_from(packet_bytes: Vec<u8>) -> Result<Self, Self::Error> {
-        if packet_bytes.len() < ETHER_FRAME_MIN_SIZE {
-            return Err(Self::Error::new(
-                io::ErrorKind::UnexpectedEof,
-                "Not enough bytes to construct Ethernet packet",
-            ));
-        }
+        require("Ethernet", &packet_bytes, ETHER_HEADER_SIZE)?;
         if packet_bytes[12] == 129 && packet_bytes[13] == 0 {
+            require("802.1Q", &packet_bytes, DOT1Q_HEADER_SIZE)?;
             return Ok(Ethernet {
                 dst: MacAddr::from(&packet_bytes[0..6]),
                 src: MacAddr::from(&packet_bytes[6..12]),
-                dot1q: Some(Dot1Q::from(&packet_bytes[12..15])),
+                dot1q: Some(Dot1Q::from(&packet_bytes[12..16])),
                 ethertype: Layer3::from(LittleEndian::read_u16(&[
-                    packet_bytes[15],
-                    packet_bytes[14],
+                    packet_bytes[17],
+                    packet_bytes[16],
                 ])),
-                payload: packet_bytes[16..].to_vec(),
+                payload: packet_bytes[18..].to_vec(),
             });
         }
         Ok(Ethernet {
@@ -0,0 +1,197 @@
+use super::error::ParseError;
+use std::fmt;
+
+const METHODS: [&str; 9] = [
+    "GET", "POST", "HEAD", "PUT", "DELETE", "OPTIONS", "PATCH", "CONNECT", "TRACE",
+];
+
+pub enum StartLine {
+    Request {
+        method: String,
+        target: String,
+        version: String,
+    },
+    Response {
+        version: String,
+        status: u16,
+        reason: String,
+    },
+}
+
+/// The head of an HTTP/1.x request or response, the body is left alone
+pub struct Http {
+    pub start: StartLine,
+    pub headers: Vec<(String, String)>,
+    /// Whether the blank line ending the headers was seen
+    pub complete: bool,
+}
+
+impl Http {
+    /// Does this look like the start of an HTTP/1.x message, whatever the port
+    pub fn detect(data: &[u8]) -> bool {
+        data.starts_with(b"HTTP/1.")
+            || METHODS.iter().any(|m| {
+                data.len() > m.len() && data.starts_with(m.as_bytes()) && data[m.len()] == b' '
+            })
+    }
+
+    pub fn new(data: &[u8]) -> Result<Http, ParseError> {
+        if !Http::detect(data) {
+            return Err(ParseError::Unsupported {
+                layer: "TCP payload",
+                protocol: String::from("(not HTTP)"),
+            });
+        }
+        let head_end = find(data, b"\r\n\r\n");
+        let head = String::from_utf8_lossy(&data[..head_end.unwrap_or(data.len())]);
+        let mut lines = head.split("\r\n");
+        let first = lines.next().unwrap_or_default();
+        let mut parts = first.splitn(3, ' ');
+        let (a, b, c) = (
+            parts.next().unwrap_or_default(),
+            parts.next().unwrap_or_default(),
+            parts.next().unwrap_or_default(),
+        );
+        let start = match a.starts_with("HTTP/1.") {
+            true => StartLine::Response {
+                version: String::from(a),
+                status: b.parse().map_err(|_| ParseError::Unsupported {
+                    layer: "HTTP",
+                    protocol: format!("status '{}'", b),
+                })?,
+                reason: String::from(c),
+            },
+            false => StartLine::Request {
+                method: String::from(a),
+                target: String::from(b),
+                version: String::from(c),
+            },
+        };
+        // A header cut off at the end of the segment is not worth keeping
+        let whole_lines = match head_end {
+            Some(_) => lines.count(),
+            None => lines.count().saturating_sub(1),
+        };
+        let headers = head
+            .split("\r\n")
+            .skip(1)
+            .take(whole_lines)
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (String::from(name.trim()), String::from(value.trim())))
+            .collect();
+        Ok(Http {
+            start,
+            headers,
+            complete: head_end.is_some(),
+        })
+    }
+
+    /// First value of a header, names are case-insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn content_length(&self) -> Option<u64> {
+        self.header("Content-Length")?.parse().ok()
+    }
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).position(|w| w == needle)
+}
+
+impl fmt::Display for Http {
+    /// `GET /path Host: example.com` or `HTTP/1.1 200 OK text/html`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.start {
+            StartLine::Request { method, target, .. } => {
+                write!(f, "{} {}", method, target)?;
+                if let Some(host) = self.header("Host") {
+                    write!(f, " Host: {}", host)?;
+                }
+            }
+            StartLine::Response {
+                version,
+                status,
+                reason,
+            } => {
+                write!(f, "{} {} {}", version, status, reason)?;
+                if let Some(content_type) = self.header("Content-Type") {
+                    write!(f, " {}", content_type)?;
+                }
+            }
+        }
+        if !self.complete {
+            write!(f, " [partial headers]")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_a_request_line_and_headers() {
+        let data =
+            b"GET /index.html HTTP/1.1\r\nHost: example.com\r\nuser-agent:  curl/8.0 \r\n\r\nbody";
+        let http = Http::new(data).unwrap();
+        match &http.start {
+            StartLine::Request {
+                method,
+                target,
+                version,
+            } => assert_eq!(
+                (&method[..], &target[..], &version[..]),
+                ("GET", "/index.html", "HTTP/1.1")
+            ),
+            StartLine::Response { .. } => panic!("read a request as a response"),
+        }
+        assert!(http.complete);
+        assert_eq!(http.headers.len(), 2);
+        assert_eq!(http.header("User-Agent"), Some("curl/8.0"));
+        assert_eq!(http.header("Cookie"), None);
+        assert_eq!(http.to_string(), "GET /index.html Host: example.com");
+    }
+
+    #[test]
+    fn reads_a_status_line() {
+        let data =
+            b"HTTP/1.1 404 Not Found\r\nContent-Type: text/html\r\nContent-Length: 153\r\n\r\n";
+        let http = Http::new(data).unwrap();
+        match &http.start {
+            StartLine::Response { status, reason, .. } => {
+                assert_eq!((*status, &reason[..]), (404, "Not Found"))
+            }
+            StartLine::Request { .. } => panic!("read a response as a request"),
+        }
+        assert_eq!(http.content_length(), Some(153));
+        assert_eq!(http.to_string(), "HTTP/1.1 404 Not Found text/html");
+        assert!(Http::new(b"HTTP/1.1 OK\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn drops_a_header_cut_off_by_the_segment() {
+        let http = Http::new(b"POST /login HTTP/1.1\r\nHost: example.com\r\nAuthori").unwrap();
+        assert!(!http.complete);
+        assert_eq!(http.headers.len(), 1);
+        assert_eq!(
+            http.to_string(),
+            "POST /login Host: example.com [partial headers]"
+        );
+    }
+
+    #[test]
+    fn detects_only_known_methods_and_versions() {
+        assert!(Http::detect(b"OPTIONS * HTTP/1.1\r\n"));
+        assert!(Http::detect(b"HTTP/1.0 200 OK\r\n"));
+        assert!(!Http::detect(b"GETTING /"));
+        assert!(!Http::detect(b"GET"));
+        assert!(!Http::detect(b"HTTP/2 200\r\n"));
+        assert!(!Http::detect(b"SSH-2.0-OpenSSH_9.6\r\n"));
+    }
+}
@@ -1,20 +1,81 @@
+use super::error::{require, ParseError};
+use super::ip::IP;
 use super::protocol::*;
+use byteorder::{BigEndian, ByteOrder};
 use std::fmt;
+use std::net::IpAddr;
+
+const ECHO_REPLY: u8 = 0;
+const ECHO_REQUEST: u8 = 8;
+const ECHO_REQUEST_V6: u8 = 128;
+const ECHO_REPLY_V6: u8 = 129;
+const UNREACHABLE: u8 = 3;
+const UNREACHABLE_V6: u8 = 1;
+const PORT_UNREACHABLE: u8 = 3;
+const PORT_UNREACHABLE_V6: u8 = 4;
 
 pub struct Icmp<'a> {
-    icmp_type: u8,
-    icmp_code: u8,
-    protocol: &'a Layer4,
+    pub icmp_type: u8,
+    pub icmp_code: u8,
+    pub protocol: &'a Layer4,
+    /// Only echo requests and replies carry these
+    pub identifier: Option<u16>,
+    pub sequence: Option<u16>,
+    /// Everything after the 8 byte header, the echoed data for echo messages
+    pub payload: &'a [u8],
 }
 
 impl<'a> Icmp<'a> {
-    pub fn new(data: &[u8], protocol: &'a Layer4) -> Option<Icmp<'a>> {
+    /// `Ok(None)` for anything that is not ICMP
+    pub fn new(data: &'a [u8], protocol: &'a Layer4) -> Result<Option<Icmp<'a>>, ParseError> {
         match protocol {
-            Layer4::Icmp | Layer4::ICMPv6 => Some(Icmp {
-                icmp_type: data[0],
-                icmp_code: data[1],
-                protocol,
-            }),
+            Layer4::Icmp | Layer4::ICMPv6 => {
+                // Type, code and checksum
+                require("ICMP", data, 4)?;
+                let mut icmp = Icmp {
+                    icmp_type: data[0],
+                    icmp_code: data[1],
+                    protocol,
+                    identifier: None,
+                    sequence: None,
+                    payload: data.get(8..).unwrap_or(&[]),
+                };
+                if icmp.is_echo_request() || icmp.is_echo_reply() {
+                    require("ICMP echo", data, 8)?;
+                    icmp.identifier = Some(BigEndian::read_u16(&data[4..6]));
+                    icmp.sequence = Some(BigEndian::read_u16(&data[6..8]));
+                }
+                Ok(Some(icmp))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub fn is_echo_request(&self) -> bool {
+        matches!(
+            (self.protocol, self.icmp_type),
+            (Layer4::Icmp, ECHO_REQUEST) | (Layer4::ICMPv6, ECHO_REQUEST_V6)
+        )
+    }
+
+    pub fn is_echo_reply(&self) -> bool {
+        matches!(
+            (self.protocol, self.icmp_type),
+            (Layer4::Icmp, ECHO_REPLY) | (Layer4::ICMPv6, ECHO_REPLY_V6)
+        )
+    }
+
+    /// Source, destination and destination port of the UDP datagram a port
+    /// unreachable message quotes
+    pub fn port_unreachable(&self) -> Option<(IpAddr, IpAddr, u16)> {
+        let layer3 = match (self.protocol, self.icmp_type, self.icmp_code) {
+            (Layer4::Icmp, UNREACHABLE, PORT_UNREACHABLE) => Layer3::IPv4,
+            (Layer4::ICMPv6, UNREACHABLE_V6, PORT_UNREACHABLE_V6) => Layer3::IPv6,
+            _ => return None,
+        };
+        let quoted = IP::new(self.payload, layer3).ok()?;
+        match (quoted.protocol, quoted.payload.get(2..4)) {
+            (Layer4::Udp, Some(port)) => Some((quoted.src, quoted.dst, BigEndian::read_u16(port))),
             _ => None,
         }
     }
@@ -22,6 +83,20 @@ impl<'a> Icmp<'a> {
 
 impl std::fmt::Display for Icmp<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let (Some(identifier), Some(sequence)) = (self.identifier, self.sequence) {
+            let name = match self.is_echo_request() {
+                true => "Echo Request",
+                false => "Echo Reply",
+            };
+            return write!(
+                f,
+                "{} (id 0x{:04x}, seq {}, {} bytes)",
+                name,
+                identifier,
+                sequence,
+                self.payload.len()
+            );
+        }
         match self.protocol {
             Layer4::Icmp => match self.icmp_type {
                 0 => write!(f, "Echo Reply"),
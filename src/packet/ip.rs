@@ -1,72 +1,405 @@
-use byteorder::{ByteOrder, LittleEndian};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use std::fmt;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
 
-use super::arp::Arp;
+use super::arp::{Arp, ARP_PACKET_SIZE};
+use super::error::{require, ParseError};
 use super::protocol::*;
 
+pub struct Ipv4Header {
+    /// Header length in 32-bit words
+    pub ihl: u8,
+    pub dscp: u8,
+    pub ecn: u8,
+    pub total_length: u16,
+    pub identification: u16,
+    pub dont_fragment: bool,
+    pub more_fragments: bool,
+    /// Offset of this fragment in 8-byte units
+    pub fragment_offset: u16,
+    pub ttl: u8,
+    pub checksum: u16,
+    pub options: Vec<u8>,
+}
+
+impl Ipv4Header {
+    fn new(data: &[u8]) -> Result<Ipv4Header, ParseError> {
+        require("IPv4", data, 20)?;
+        if data[0] >> 4 != 4 {
+            return Err(ParseError::BadVersion {
+                layer: "IPv4",
+                version: data[0] >> 4,
+            });
+        }
+        let ihl = data[0] & 0x0f;
+        let header_len = ihl as usize * 4;
+        if header_len < 20 {
+            return Err(ParseError::BadLength {
+                layer: "IPv4 header",
+                length: header_len,
+            });
+        }
+        require("IPv4", data, header_len)?;
+        let total_length = BigEndian::read_u16(&data[2..4]);
+        if (total_length as usize) < header_len {
+            return Err(ParseError::BadLength {
+                layer: "IPv4 total",
+                length: total_length as usize,
+            });
+        }
+        let flags_offset = BigEndian::read_u16(&data[6..8]);
+        Ok(Ipv4Header {
+            ihl,
+            dscp: data[1] >> 2,
+            ecn: data[1] & 0b11,
+            total_length,
+            identification: BigEndian::read_u16(&data[4..6]),
+            dont_fragment: flags_offset & 0x4000 != 0,
+            more_fragments: flags_offset & 0x2000 != 0,
+            fragment_offset: flags_offset & 0x1fff,
+            ttl: data[8],
+            checksum: BigEndian::read_u16(&data[10..12]),
+            options: data[20..header_len].to_vec(),
+        })
+    }
+
+    pub fn header_len(&self) -> usize {
+        self.ihl as usize * 4
+    }
+}
+
+impl fmt::Display for Ipv4Header {
+    /// Close to `tcpdump -v`, e.g. `(tos 0x0, ttl 64, id 1, offset 0, flags [DF], length 60)`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags = match (self.dont_fragment, self.more_fragments) {
+            (true, true) => "DF+",
+            (true, false) => "DF",
+            (false, true) => "+",
+            (false, false) => "none",
+        };
+        write!(
+            f,
+            "(tos 0x{:x}, ttl {}, id {}, offset {}, flags [{}], length {}",
+            (self.dscp << 2) | self.ecn,
+            self.ttl,
+            self.identification,
+            self.fragment_offset as u32 * 8,
+            flags,
+            self.total_length
+        )?;
+        if !self.options.is_empty() {
+            write!(f, ", options {} bytes", self.options.len())?;
+        }
+        write!(f, ")")
+    }
+}
+
+pub enum Ipv6Extension {
+    HopByHop,
+    Routing {
+        routing_type: u8,
+        segments_left: u8,
+    },
+    Fragment {
+        /// Offset of this fragment in 8-byte units
+        offset: u16,
+        more_fragments: bool,
+        identification: u32,
+    },
+    DestinationOptions,
+    Authentication,
+    /// Everything after an ESP header is encrypted, so the walk stops here
+    EncapsulatingSecurityPayload,
+}
+
+impl fmt::Display for Ipv6Extension {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ipv6Extension::HopByHop => write!(f, "HBH"),
+            Ipv6Extension::Routing {
+                routing_type,
+                segments_left,
+            } => write!(f, "RT type {} left {}", routing_type, segments_left),
+            Ipv6Extension::Fragment {
+                offset,
+                more_fragments,
+                identification,
+            } => write!(
+                f,
+                "frag id {} offset {}{}",
+                identification,
+                *offset as u32 * 8,
+                if *more_fragments { " +" } else { "" }
+            ),
+            Ipv6Extension::DestinationOptions => write!(f, "DSTOPT"),
+            Ipv6Extension::Authentication => write!(f, "AH"),
+            Ipv6Extension::EncapsulatingSecurityPayload => write!(f, "ESP"),
+        }
+    }
+}
+
+pub struct Ipv6Header {
+    pub traffic_class: u8,
+    pub flow_label: u32,
+    pub payload_length: u16,
+    pub hop_limit: u8,
+    /// Extension headers in the order they appeared
+    pub extensions: Vec<Ipv6Extension>,
+}
+
+impl Ipv6Header {
+    /// Parse the fixed header and walk the extension header chain,
+    /// returning the upper-layer protocol and where its header starts
+    fn new(data: &[u8]) -> Result<(Ipv6Header, u8, usize, usize), ParseError> {
+        require("IPv6", data, 40)?;
+        if data[0] >> 4 != 6 {
+            return Err(ParseError::BadVersion {
+                layer: "IPv6",
+                version: data[0] >> 4,
+            });
+        }
+        let payload_length = BigEndian::read_u16(&data[4..6]);
+        // A zero payload length is a jumbogram, take whatever was captured
+        let end = match payload_length {
+            0 => data.len(),
+            len => (40 + len as usize).min(data.len()),
+        };
+        let mut extensions = Vec::new();
+        let mut next = data[6];
+        let mut offset = 40;
+        loop {
+            if next == 50 {
+                extensions.push(Ipv6Extension::EncapsulatingSecurityPayload);
+                break;
+            }
+            if !matches!(next, 0 | 43 | 44 | 51 | 60) || offset + 8 > end {
+                break;
+            }
+            let header = &data[offset..end];
+            let len = extension_len(next, header);
+            if len > header.len() {
+                break;
+            }
+            extensions.push(match next {
+                0 => Ipv6Extension::HopByHop,
+                43 => Ipv6Extension::Routing {
+                    routing_type: header[2],
+                    segments_left: header[3],
+                },
+                44 => {
+                    let offset_flags = BigEndian::read_u16(&header[2..4]);
+                    Ipv6Extension::Fragment {
+                        offset: offset_flags >> 3,
+                        more_fragments: offset_flags & 0x1 == 1,
+                        identification: BigEndian::read_u32(&header[4..8]),
+                    }
+                }
+                51 => Ipv6Extension::Authentication,
+                _ => Ipv6Extension::DestinationOptions,
+            });
+            next = header[0];
+            offset += len;
+            // Past a fragment header comes fragment data, which only an atomic
+            // fragment has its upper layer headers in
+            let fragmented = match extensions.last() {
+                Some(Ipv6Extension::Fragment {
+                    offset,
+                    more_fragments,
+                    ..
+                }) => *offset != 0 || *more_fragments,
+                _ => false,
+            };
+            if fragmented {
+                break;
+            }
+        }
+        let header = Ipv6Header {
+            traffic_class: ((BigEndian::read_u16(&data[0..2]) >> 4) & 0xff) as u8,
+            flow_label: BigEndian::read_u32(&data[0..4]) & 0xfffff,
+            payload_length,
+            hop_limit: data[7],
+            extensions,
+        };
+        Ok((header, next, offset, end))
+    }
+
+    pub fn fragment(&self) -> Option<&Ipv6Extension> {
+        self.extensions
+            .iter()
+            .find(|e| matches!(e, Ipv6Extension::Fragment { .. }))
+    }
+}
+
+/// Length of the extension header `next` names, which starts `header`
+fn extension_len(next: u8, header: &[u8]) -> usize {
+    match next {
+        44 => 8,
+        51 => (header[1] as usize + 2) * 4,
+        _ => (header[1] as usize + 1) * 8,
+    }
+}
+
+impl fmt::Display for Ipv6Header {
+    /// Close to `tcpdump -v`, e.g. `(class 0x0, flowlabel 0x1, hlim 64, payload length 40)`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "(class 0x{:x}, flowlabel 0x{:x}, hlim {}, payload length {}",
+            self.traffic_class, self.flow_label, self.hop_limit, self.payload_length
+        )?;
+        if !self.extensions.is_empty() {
+            let extensions: Vec<String> = self.extensions.iter().map(|e| e.to_string()).collect();
+            write!(f, ", ext [{}]", extensions.join(", "))?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Where a packet sits in a fragmented datagram
+pub struct Fragment {
+    /// IPv4 identifications are widened to the 32 bits IPv6 uses
+    pub id: u32,
+    /// Offset in bytes
+    pub offset: usize,
+    pub more: bool,
+}
+
+impl fmt::Display for Fragment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Fragment id 0x{:x} offset {}", self.id, self.offset)?;
+        if self.more {
+            write!(f, " (more)")?;
+        }
+        Ok(())
+    }
+}
+
 pub struct IP<'a> {
     pub src: IpAddr,
     pub dst: IpAddr,
     pub protocol: Layer4,
     pub arp: Option<Arp>,
+    pub ipv4: Option<Ipv4Header>,
+    pub ipv6: Option<Ipv6Header>,
     pub payload: &'a [u8],
 }
 
 impl<'a> IP<'a> {
-    pub fn new(data: &[u8], protocol: Layer3) -> Option<IP> {
+    pub fn new(data: &'a [u8], protocol: Layer3) -> Result<IP<'a>, ParseError> {
         match protocol {
             Layer3::Arp => {
-                let src = IpAddr::V4(Ipv4Addr::new(data[14], data[15], data[16], data[17]));
-                let dst = IpAddr::V4(Ipv4Addr::new(data[24], data[25], data[26], data[27]));
-                Some(IP {
-                    src,
-                    dst,
+                let arp = Arp::new(data)?;
+                Ok(IP {
+                    src: arp.src_ip,
+                    dst: arp.dst_ip,
                     protocol: Layer4::Arp,
-                    arp: Arp::new(src, dst, data),
-                    payload: &data[20..],
+                    arp: Some(arp),
+                    ipv4: None,
+                    ipv6: None,
+                    payload: &data[ARP_PACKET_SIZE..],
                 })
             }
-            Layer3::IPv4 => Some(IP {
-                src: IpAddr::V4(Ipv4Addr::new(data[12], data[13], data[14], data[15])),
-                dst: IpAddr::V4(Ipv4Addr::new(data[16], data[17], data[18], data[19])),
-                protocol: Layer4::from(data[9]),
-                arp: None,
-                payload: &data[20..],
+            Layer3::IPv4 => {
+                let header = Ipv4Header::new(data)?;
+                // Total length also drops any Ethernet padding after the datagram,
+                // a snapshot length shorter than it is not the sender's fault
+                let start = header.header_len();
+                let end = (header.total_length as usize).min(data.len());
+                Ok(IP {
+                    src: IpAddr::V4(Ipv4Addr::new(data[12], data[13], data[14], data[15])),
+                    dst: IpAddr::V4(Ipv4Addr::new(data[16], data[17], data[18], data[19])),
+                    protocol: Layer4::from(data[9]),
+                    arp: None,
+                    ipv4: Some(header),
+                    ipv6: None,
+                    payload: &data[start..end],
+                })
+            }
+            Layer3::IPv6 => {
+                let (header, next, start, end) = Ipv6Header::new(data)?;
+                Ok(IP {
+                    src: IpAddr::V6(Ipv6Addr::new(
+                        LittleEndian::read_u16(&[data[9], data[8]]),
+                        LittleEndian::read_u16(&[data[11], data[10]]),
+                        LittleEndian::read_u16(&[data[13], data[12]]),
+                        LittleEndian::read_u16(&[data[15], data[14]]),
+                        LittleEndian::read_u16(&[data[17], data[16]]),
+                        LittleEndian::read_u16(&[data[19], data[18]]),
+                        LittleEndian::read_u16(&[data[21], data[20]]),
+                        LittleEndian::read_u16(&[data[23], data[22]]),
+                    )),
+                    dst: IpAddr::V6(Ipv6Addr::new(
+                        // 25 24 39 38
+                        LittleEndian::read_u16(&[data[25], data[24]]),
+                        LittleEndian::read_u16(&[data[27], data[26]]),
+                        LittleEndian::read_u16(&[data[29], data[28]]),
+                        LittleEndian::read_u16(&[data[31], data[30]]),
+                        LittleEndian::read_u16(&[data[33], data[32]]),
+                        LittleEndian::read_u16(&[data[35], data[34]]),
+                        LittleEndian::read_u16(&[data[37], data[36]]),
+                        LittleEndian::read_u16(&[data[39], data[38]]),
+                    )),
+                    protocol: Layer4::from(next),
+                    arp: None,
+                    ipv4: None,
+                    ipv6: Some(header),
+                    payload: &data[start.min(end)..end],
+                })
+            }
+            Layer3::Unknown(ethertype) => Err(ParseError::Unsupported {
+                layer: "Ethernet",
+                protocol: format!("0x{:04x}", ethertype),
             }),
-            Layer3::IPv6 => Some(IP {
-                src: IpAddr::V6(Ipv6Addr::new(
-                    LittleEndian::read_u16(&[data[9], data[8]]),
-                    LittleEndian::read_u16(&[data[11], data[10]]),
-                    LittleEndian::read_u16(&[data[13], data[12]]),
-                    LittleEndian::read_u16(&[data[15], data[14]]),
-                    LittleEndian::read_u16(&[data[17], data[16]]),
-                    LittleEndian::read_u16(&[data[19], data[18]]),
-                    LittleEndian::read_u16(&[data[21], data[20]]),
-                    LittleEndian::read_u16(&[data[23], data[22]]),
-                )),
-                dst: IpAddr::V6(Ipv6Addr::new(
-                    // 25 24 39 38
-                    LittleEndian::read_u16(&[data[25], data[24]]),
-                    LittleEndian::read_u16(&[data[27], data[26]]),
-                    LittleEndian::read_u16(&[data[29], data[28]]),
-                    LittleEndian::read_u16(&[data[31], data[30]]),
-                    LittleEndian::read_u16(&[data[33], data[32]]),
-                    LittleEndian::read_u16(&[data[35], data[34]]),
-                    LittleEndian::read_u16(&[data[37], data[36]]),
-                    LittleEndian::read_u16(&[data[39], data[38]]),
-                )),
-                protocol: Layer4::from(data[6]),
-                arp: None,
-                payload: &data[40..],
+        }
+    }
+
+    /// `None` for whole datagrams, including IPv6 atomic fragments
+    pub fn fragment(&self) -> Option<Fragment> {
+        if let Some(header) = &self.ipv4 {
+            if header.more_fragments || header.fragment_offset != 0 {
+                return Some(Fragment {
+                    id: header.identification as u32,
+                    offset: header.fragment_offset as usize * 8,
+                    more: header.more_fragments,
+                });
+            }
+        }
+        match self.ipv6.as_ref().and_then(|h| h.fragment()) {
+            Some(Ipv6Extension::Fragment {
+                offset,
+                more_fragments,
+                identification,
+            }) if *offset != 0 || *more_fragments => Some(Fragment {
+                id: *identification,
+                offset: *offset as usize * 8,
+                more: *more_fragments,
             }),
-            _ => {
-                println!("{}", protocol);
-                None
+            _ => None,
+        }
+    }
+
+    /// The upper layer protocol and header of a datagram rebuilt from its
+    /// fragments, past the IPv6 extension headers its fragmentable part starts with
+    pub fn upper_layer<'b>(&self, datagram: &'b [u8]) -> (Layer4, &'b [u8]) {
+        let mut next = match (&self.ipv6, self.protocol.number()) {
+            (Some(_), Some(next)) => next,
+            _ => return (self.protocol, datagram),
+        };
+        let mut rest = datagram;
+        while matches!(next, 0 | 43 | 51 | 60) && rest.len() >= 8 {
+            let len = extension_len(next, rest);
+            if len > rest.len() {
+                break;
             }
+            next = rest[0];
+            rest = &rest[len..];
         }
+        (Layer4::from(next), rest)
     }
 }
 
+#[allow(dead_code, clippy::explicit_counter_loop)]
 pub fn ip_network_id(ip: IpAddr, cidr: &u16) -> Option<u32> {
     let ip: String = ip.to_string();
 
@@ -84,3 +417,186 @@ pub fn ip_network_id(ip: IpAddr, cidr: &u16) -> Option<u32> {
     let end = data >> (32 - cidr);
     Some(end)
 }
+
+/// An IPv4 or IPv6 network in CIDR notation, e.g. `10.0.0.0/8` or `fe80::/10`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cidr {
+    pub network: IpAddr,
+    pub prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    /// A bare address is treated as a single host network
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let network: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("'{}' is not an IP address", addr))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => match prefix.parse::<u8>() {
+                Ok(len) if len <= max => len,
+                _ => return Err(format!("'{}' is not a valid prefix length for {}", prefix, network)),
+            },
+            None => max,
+        };
+        Ok(Cidr { network, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An IPv4 header of `ihl` words from 10.0.0.1 to 10.0.0.2 claiming
+    /// `total_length`, padded with zeroed options up to `ihl`
+    fn ipv4(ihl: u8, total_length: u16) -> Vec<u8> {
+        let mut data = vec![0x40 | ihl, 0, 0, 0, 0, 1, 0x40, 0, 64, 17, 0, 0];
+        data[2..4].copy_from_slice(&total_length.to_be_bytes());
+        data.extend([10, 0, 0, 1, 10, 0, 0, 2]);
+        data.resize(20.max(ihl as usize * 4), 0);
+        data
+    }
+
+    #[test]
+    fn rejects_a_truncated_ipv4_header() {
+        let data = ipv4(5, 20);
+        assert_eq!(
+            IP::new(&data[..19], Layer3::IPv4).err(),
+            Some(ParseError::Truncated {
+                layer: "IPv4",
+                needed: 20,
+                available: 19
+            })
+        );
+        // Options the header length promises but the capture lacks
+        let data = ipv4(6, 24);
+        assert_eq!(
+            IP::new(&data[..20], Layer3::IPv4).err(),
+            Some(ParseError::Truncated {
+                layer: "IPv4",
+                needed: 24,
+                available: 20
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_ipv4_lengths() {
+        assert_eq!(
+            IP::new(&ipv4(4, 20), Layer3::IPv4).err(),
+            Some(ParseError::BadLength {
+                layer: "IPv4 header",
+                length: 16
+            })
+        );
+        assert_eq!(
+            IP::new(&ipv4(6, 20), Layer3::IPv4).err(),
+            Some(ParseError::BadLength {
+                layer: "IPv4 total",
+                length: 20
+            })
+        );
+        let mut data = ipv4(5, 20);
+        data[0] = 0x65;
+        assert_eq!(
+            IP::new(&data, Layer3::IPv4).err(),
+            Some(ParseError::BadVersion {
+                layer: "IPv4",
+                version: 6
+            })
+        );
+    }
+
+    #[test]
+    fn ipv4_payload_ends_at_the_total_length() {
+        // A UDP header in a minimum size Ethernet frame, padded to 46 bytes
+        let mut data = ipv4(5, 28);
+        data.extend([0x9c, 0x40, 0, 53, 0, 8, 0, 0]);
+        data.resize(46, 0);
+        let ip = IP::new(&data, Layer3::IPv4).unwrap();
+        assert_eq!(ip.payload, &[0x9c, 0x40, 0, 53, 0, 8, 0, 0]);
+        assert!(ip.protocol == Layer4::Udp);
+        assert_eq!(ip.src, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        // A snapshot length cutting the datagram short keeps what was captured
+        let ip = IP::new(&data[..24], Layer3::IPv4).unwrap();
+        assert_eq!(ip.payload, &[0x9c, 0x40, 0, 53]);
+    }
+
+    #[test]
+    fn shows_ipv4_options() {
+        let data = ipv4(6, 24);
+        let ip = IP::new(&data, Layer3::IPv4).unwrap();
+        let header = ip.ipv4.unwrap();
+        assert_eq!(header.header_len(), 24);
+        assert!(ip.payload.is_empty());
+        assert_eq!(
+            header.to_string(),
+            "(tos 0x0, ttl 64, id 1, offset 0, flags [DF], length 24, options 4 bytes)"
+        );
+    }
+
+    /// IPv6 from 2001:db8::1 to 2001:db8::2 carrying `extensions` then 8 bytes
+    /// that look like a destination options header followed by UDP
+    fn ipv6(next: u8, extensions: &str) -> Vec<u8> {
+        let extensions = hex::decode(extensions).unwrap();
+        let mut data = hex::decode("6000000000000000").unwrap();
+        data[6] = next;
+        data[4..6].copy_from_slice(&(extensions.len() as u16 + 8).to_be_bytes());
+        data.extend(hex::decode("20010db8000000000000000000000001").unwrap());
+        data.extend(hex::decode("20010db8000000000000000000000002").unwrap());
+        data.extend(extensions);
+        data.extend(hex::decode("1100000000000000").unwrap());
+        data
+    }
+
+    #[test]
+    fn walk_stops_at_a_fragment_header() {
+        // Second fragment, offset 8 bytes, of a datagram whose first bytes were destination options
+        let data = ipv6(44, "3c00000800001234");
+        let ip = IP::new(&data, Layer3::IPv6).unwrap();
+        assert!(ip.protocol == Layer4::from(60));
+        assert_eq!(ip.payload.len(), 8);
+        let fragment = ip.fragment().unwrap();
+        assert_eq!(
+            (fragment.id, fragment.offset, fragment.more),
+            (0x1234, 8, false)
+        );
+    }
+
+    #[test]
+    fn walk_goes_on_past_an_atomic_fragment() {
+        let data = ipv6(44, "3c00000000001234");
+        let ip = IP::new(&data, Layer3::IPv6).unwrap();
+        assert!(ip.protocol == Layer4::Udp);
+        assert_eq!(ip.ipv6.as_ref().unwrap().extensions.len(), 2);
+        assert!(ip.fragment().is_none());
+    }
+}
@@ -1,14 +1,30 @@
-//use self::ethernet::Ethernet;
+use self::arp::Arp;
+use self::ethernet::Ethernet;
+use self::icmp::Icmp;
+use self::ip::IP;
+use self::transport::Transport;
 
+pub(crate) mod application;
 pub(crate) mod arp;
+pub(crate) mod classify;
+pub(crate) mod dhcp;
+pub(crate) mod dns;
+pub(crate) mod error;
 pub(crate) mod ethernet;
+pub(crate) mod http;
 pub(crate) mod icmp;
 pub(crate) mod ip;
 pub(crate) mod protocol;
+pub(crate) mod tls;
 pub(crate) mod transport;
 
-// Can really take advantage of structured-enums here and through the packet processing logic
-// pub(crate) enum Packet {
-//     Ethernet(Ethernet),
-//     Unknown,
-// }
+/// Every layer decoded from a single frame, for code that needs to look
+/// across layers at once (filters, output formats)
+pub struct Decoded<'a> {
+    pub len: u32,
+    pub eth: &'a Ethernet,
+    pub ip: &'a IP<'a>,
+    pub transport: Option<&'a Transport<'a>>,
+    pub icmp: Option<&'a Icmp<'a>>,
+    pub arp: Option<&'a Arp>,
+}
@@ -1,5 +1,6 @@
 use std::fmt;
 
+#[derive(Clone, Copy, PartialEq)]
 pub enum Layer3 {
     IPv4,
     IPv6,
@@ -29,6 +30,7 @@ impl From<u16> for Layer3 {
     }
 }
 
+#[derive(Clone, Copy, PartialEq)]
 pub enum Layer4 {
     Tcp,
     Udp,
@@ -50,7 +52,23 @@ impl fmt::Display for Layer4 {
             Layer4::Arp => write!(f, "ARP"),
             Layer4::Igmp => write!(f, "IGMP"),
             Layer4::IPv6HopByHop => write!(f, "IPv6HbH"),
-            Layer4::Unknown(x) => write!(f, "Unknown Layer 3! ({})", x),
+            Layer4::Unknown(x) => write!(f, "Unknown Layer 4! ({})", x),
+        }
+    }
+}
+
+impl Layer4 {
+    /// IP protocol number, ARP is carried directly over Ethernet and has none
+    pub fn number(&self) -> Option<u8> {
+        match self {
+            Layer4::IPv6HopByHop => Some(0x00),
+            Layer4::Icmp => Some(0x01),
+            Layer4::Igmp => Some(0x02),
+            Layer4::Tcp => Some(0x06),
+            Layer4::Udp => Some(0x11),
+            Layer4::ICMPv6 => Some(0x3A),
+            Layer4::Unknown(x) => Some(*x),
+            Layer4::Arp => None,
         }
     }
 }
@@ -0,0 +1,478 @@
+use super::error::{require, ParseError};
+use byteorder::{BigEndian, ByteOrder};
+use md5::{Digest, Md5};
+use sha2::Sha256;
+use std::fmt;
+
+const HANDSHAKE: u8 = 22;
+const CLIENT_HELLO: u8 = 1;
+
+const SERVER_NAME: u16 = 0x0000;
+const SUPPORTED_GROUPS: u16 = 0x000a;
+const EC_POINT_FORMATS: u16 = 0x000b;
+const SIGNATURE_ALGORITHMS: u16 = 0x000d;
+const ALPN: u16 = 0x0010;
+const SUPPORTED_VERSIONS: u16 = 0x002b;
+
+/// Largest record the spec allows for ciphertext, anything bigger is not TLS
+const MAX_RECORD: usize = (1 << 14) + 2048;
+
+/// The fields of a ClientHello that fingerprints are built from
+pub struct ClientHello {
+    pub version: u16,
+    pub ciphers: Vec<u16>,
+    /// Extension types in the order they were offered
+    pub extensions: Vec<u16>,
+    pub server_name: Option<String>,
+    pub alpn: Vec<String>,
+    pub supported_versions: Vec<u16>,
+    pub groups: Vec<u16>,
+    pub point_formats: Vec<u8>,
+    pub signature_algorithms: Vec<u16>,
+}
+
+/// The first TLS record of a segment, with the ClientHello when it holds one
+pub struct Tls {
+    pub content_type: u8,
+    pub version: u16,
+    pub length: u16,
+    pub handshake_type: Option<u8>,
+    pub client_hello: Option<ClientHello>,
+    /// Whether the whole handshake message was captured, a ClientHello can
+    /// span several segments
+    pub complete: bool,
+}
+
+impl Tls {
+    /// Does this look like the start of a TLS record, whatever the port
+    pub fn detect(data: &[u8]) -> bool {
+        data.len() >= 5
+            && (20..=24).contains(&data[0])
+            && data[1] == 3
+            && data[2] <= 4
+            && BigEndian::read_u16(&data[3..5]) as usize <= MAX_RECORD
+    }
+
+    pub fn new(data: &[u8]) -> Result<Tls, ParseError> {
+        if !Tls::detect(data) {
+            return Err(ParseError::Unsupported {
+                layer: "TCP payload",
+                protocol: String::from("(not TLS)"),
+            });
+        }
+        let length = BigEndian::read_u16(&data[3..5]);
+        let mut tls = Tls {
+            content_type: data[0],
+            version: BigEndian::read_u16(&data[1..3]),
+            length,
+            handshake_type: None,
+            client_hello: None,
+            complete: true,
+        };
+        if tls.content_type != HANDSHAKE {
+            return Ok(tls);
+        }
+        // Later handshake messages are encrypted in TLS 1.3, only the type byte is trusted
+        let fragment = &data[5..];
+        tls.handshake_type = fragment.first().copied();
+        if tls.handshake_type != Some(CLIENT_HELLO) {
+            return Ok(tls);
+        }
+        require("TLS handshake", fragment, 4)?;
+        let body_len = BigEndian::read_u24(&fragment[1..4]) as usize;
+        match fragment.len() >= 4 + body_len {
+            true => tls.client_hello = Some(ClientHello::new(&fragment[4..4 + body_len])?),
+            false => tls.complete = false,
+        }
+        Ok(tls)
+    }
+
+    /// Bytes needed for the whole first handshake message, once the header is in
+    pub fn handshake_len(data: &[u8]) -> Option<usize> {
+        match data.len() >= 9 && data[0] == HANDSHAKE {
+            true => Some(9 + BigEndian::read_u24(&data[6..9]) as usize),
+            false => None,
+        }
+    }
+}
+
+impl ClientHello {
+    fn new(data: &[u8]) -> Result<ClientHello, ParseError> {
+        let mut reader = Reader { data, pos: 0 };
+        let version = reader.u16()?;
+        reader.skip(32)?;
+        let session_id = reader.u8()? as usize;
+        reader.skip(session_id)?;
+        let ciphers_len = reader.u16()? as usize;
+        let ciphers = reader.u16_list(ciphers_len)?;
+        let compression = reader.u8()? as usize;
+        reader.skip(compression)?;
+        let mut hello = ClientHello {
+            version,
+            ciphers,
+            extensions: Vec::new(),
+            server_name: None,
+            alpn: Vec::new(),
+            supported_versions: Vec::new(),
+            groups: Vec::new(),
+            point_formats: Vec::new(),
+            signature_algorithms: Vec::new(),
+        };
+        // SSL 3.0 hellos may stop before the extensions
+        if reader.remaining() == 0 {
+            return Ok(hello);
+        }
+        let extensions_len = reader.u16()? as usize;
+        let mut extensions = Reader {
+            data: reader.take(extensions_len)?,
+            pos: 0,
+        };
+        while extensions.remaining() >= 4 {
+            let kind = extensions.u16()?;
+            let len = extensions.u16()? as usize;
+            let mut value = Reader {
+                data: extensions.take(len)?,
+                pos: 0,
+            };
+            hello.extensions.push(kind);
+            // A malformed extension still counts for the fingerprint
+            let _ = hello.extension(kind, &mut value);
+        }
+        Ok(hello)
+    }
+
+    fn extension(&mut self, kind: u16, value: &mut Reader) -> Result<(), ParseError> {
+        match kind {
+            SERVER_NAME => {
+                let mut names = Reader {
+                    data: value.take_u16()?,
+                    pos: 0,
+                };
+                while names.remaining() > 0 {
+                    let name_type = names.u8()?;
+                    let name = names.take_u16()?;
+                    if name_type == 0 {
+                        self.server_name = Some(String::from_utf8_lossy(name).into_owned());
+                    }
+                }
+            }
+            SUPPORTED_GROUPS => {
+                let len = value.u16()? as usize;
+                self.groups = value.u16_list(len)?;
+            }
+            EC_POINT_FORMATS => {
+                let len = value.u8()? as usize;
+                self.point_formats = value.take(len)?.to_vec();
+            }
+            SIGNATURE_ALGORITHMS => {
+                let len = value.u16()? as usize;
+                self.signature_algorithms = value.u16_list(len)?;
+            }
+            ALPN => {
+                let mut protocols = Reader {
+                    data: value.take_u16()?,
+                    pos: 0,
+                };
+                while protocols.remaining() > 0 {
+                    let len = protocols.u8()? as usize;
+                    let protocol = protocols.take(len)?;
+                    self.alpn
+                        .push(String::from_utf8_lossy(protocol).into_owned());
+                }
+            }
+            SUPPORTED_VERSIONS => {
+                let len = value.u8()? as usize;
+                self.supported_versions = value.u16_list(len)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// The highest version offered, from supported_versions when TLS 1.3 is in play
+    pub fn max_version(&self) -> u16 {
+        self.supported_versions
+            .iter()
+            .copied()
+            .filter(|v| !is_grease(*v))
+            .max()
+            .unwrap_or(self.version)
+    }
+
+    /// The JA3 string, `version,ciphers,extensions,groups,point formats`
+    pub fn ja3_full(&self) -> String {
+        let join = |values: &[u16]| {
+            values
+                .iter()
+                .filter(|v| !is_grease(**v))
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>()
+                .join("-")
+        };
+        let point_formats: Vec<String> = self.point_formats.iter().map(|p| p.to_string()).collect();
+        format!(
+            "{},{},{},{},{}",
+            self.version,
+            join(&self.ciphers),
+            join(&self.extensions),
+            join(&self.groups),
+            point_formats.join("-")
+        )
+    }
+
+    /// The MD5 of the JA3 string, which is how JA3 fingerprints are shared
+    pub fn ja3(&self) -> String {
+        hex::encode(Md5::digest(self.ja3_full().as_bytes()))
+    }
+
+    /// The JA4 fingerprint, e.g. `t13d1516h2_8daaf6152771_e5627efa2ab1`
+    pub fn ja4(&self) -> String {
+        let ciphers: Vec<u16> = self
+            .ciphers
+            .iter()
+            .copied()
+            .filter(|c| !is_grease(*c))
+            .collect();
+        let extensions: Vec<u16> = self
+            .extensions
+            .iter()
+            .copied()
+            .filter(|e| !is_grease(*e))
+            .collect();
+        let version = match self.max_version() {
+            0x0304 => "13",
+            0x0303 => "12",
+            0x0302 => "11",
+            0x0301 => "10",
+            0x0300 => "s3",
+            _ => "00",
+        };
+        let sni = match self.server_name.is_some() {
+            true => 'd',
+            false => 'i',
+        };
+        let alpn = match self.alpn.first().map(|a| a.as_bytes()) {
+            Some([first, .., last])
+                if first.is_ascii_alphanumeric() && last.is_ascii_alphanumeric() =>
+            {
+                format!("{}{}", *first as char, *last as char)
+            }
+            Some([first, .., last]) => {
+                let (first, last) = (format!("{:02x}", first), format!("{:02x}", last));
+                format!("{}{}", &first[..1], &last[1..])
+            }
+            Some([only]) => format!("{}{}", *only as char, *only as char),
+            _ => String::from("00"),
+        };
+        let mut sorted_ciphers = ciphers.clone();
+        sorted_ciphers.sort_unstable();
+        // SNI and ALPN are already in the first part
+        let mut sorted_extensions: Vec<u16> = extensions
+            .iter()
+            .copied()
+            .filter(|e| *e != SERVER_NAME && *e != ALPN)
+            .collect();
+        sorted_extensions.sort_unstable();
+        let mut extension_part = hex_list(&sorted_extensions);
+        if !self.signature_algorithms.is_empty() {
+            extension_part = format!(
+                "{}_{}",
+                extension_part,
+                hex_list(&self.signature_algorithms)
+            );
+        }
+        format!(
+            "t{}{}{:02}{:02}{}_{}_{}",
+            version,
+            sni,
+            ciphers.len().min(99),
+            extensions.len().min(99),
+            alpn,
+            truncated_sha256(&sorted_ciphers, &hex_list(&sorted_ciphers)),
+            truncated_sha256(&sorted_extensions, &extension_part)
+        )
+    }
+}
+
+/// GREASE values (RFC 8701) are random filler and left out of fingerprints
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+fn hex_list(values: &[u16]) -> String {
+    values
+        .iter()
+        .map(|v| format!("{:04x}", v))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// First 12 hex digits of the SHA-256 of `text`, all zeroes when `values` is empty
+fn truncated_sha256(values: &[u16], text: &str) -> String {
+    match values.is_empty() {
+        true => String::from("000000000000"),
+        false => hex::encode(Sha256::digest(text.as_bytes()))[..12].to_string(),
+    }
+}
+
+pub fn version_name(version: u16) -> String {
+    match version {
+        0x0300 => String::from("SSL 3.0"),
+        0x0301 => String::from("TLS 1.0"),
+        0x0302 => String::from("TLS 1.1"),
+        0x0303 => String::from("TLS 1.2"),
+        0x0304 => String::from("TLS 1.3"),
+        _ => format!("0x{:04x}", version),
+    }
+}
+
+fn handshake_name(handshake_type: u8) -> String {
+    match handshake_type {
+        0 => String::from("HelloRequest"),
+        1 => String::from("ClientHello"),
+        2 => String::from("ServerHello"),
+        4 => String::from("NewSessionTicket"),
+        8 => String::from("EncryptedExtensions"),
+        11 => String::from("Certificate"),
+        12 => String::from("ServerKeyExchange"),
+        13 => String::from("CertificateRequest"),
+        14 => String::from("ServerHelloDone"),
+        15 => String::from("CertificateVerify"),
+        16 => String::from("ClientKeyExchange"),
+        20 => String::from("Finished"),
+        _ => String::from("Encrypted Handshake Message"),
+    }
+}
+
+/// Bounds-checked reads over a handshake message
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
+        require("TLS ClientHello", &self.data[self.pos..], len)?;
+        self.pos += len;
+        Ok(&self.data[self.pos - len..self.pos])
+    }
+
+    fn take_u16(&mut self) -> Result<&'a [u8], ParseError> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), ParseError> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8, ParseError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ParseError> {
+        Ok(BigEndian::read_u16(self.take(2)?))
+    }
+
+    fn u16_list(&mut self, len: usize) -> Result<Vec<u16>, ParseError> {
+        Ok(self
+            .take(len)?
+            .chunks_exact(2)
+            .map(BigEndian::read_u16)
+            .collect())
+    }
+}
+
+impl fmt::Display for Tls {
+    /// `ClientHello TLS 1.3 SNI example.com ALPN h2 ja4 t13d...`, or the record type otherwise
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.content_type, self.handshake_type) {
+            (20, _) => write!(f, "Change Cipher Spec"),
+            (21, _) => write!(f, "Alert"),
+            (23, _) => write!(f, "Application Data"),
+            (24, _) => write!(f, "Heartbeat"),
+            (_, Some(handshake_type)) => write!(f, "{}", handshake_name(handshake_type)),
+            _ => write!(f, "Handshake"),
+        }?;
+        if let Some(hello) = &self.client_hello {
+            write!(f, " {}", version_name(hello.max_version()))?;
+            if let Some(server_name) = &hello.server_name {
+                write!(f, " SNI {}", server_name)?;
+            }
+            if !hello.alpn.is_empty() {
+                write!(f, " ALPN {}", hello.alpn.join(","))?;
+            }
+            write!(f, " ja3 {} ja4 {}", hello.ja3(), hello.ja4())?;
+        }
+        if !self.complete {
+            write!(f, " [partial]")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The hello behind the JA3 README's example, `769,47-53-5-10-...,0-10-11,23-24-25,0`
+    const JA3_EXAMPLE: &str = concat!(
+        "160301006b010000670301000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c",
+        "1d1e1f000018002f00350005000ac009c00ac013c01400320038001300040100002600000010000e",
+        "00000b6578616d706c652e636f6d000a00080006001700180019000b00020100",
+    );
+
+    /// A Chrome ClientHello, GREASE and all, which the JA4 README fingerprints
+    /// as `t13d1516h2_8daaf6152771_e5627efa2ab1`
+    const CHROME: &str = concat!(
+        "1603010148010001440303000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c",
+        "1d1e1f20202122232425262728292a2b2c2d2e2f303132333435363738393a3b3c3d3e3f00200a0a",
+        "130113021303c02bc02fc02cc030cca9cca8c013c014009c009d002f0035010000db0a0a00000000",
+        "0010000e00000b6578616d706c652e636f6d00170000ff01000100000a000a00081a1a001d001700",
+        "18000b00020100002300000010000e000c02683208687474702f312e31000500050100000000000d",
+        "0012001004030804040105030805050108060601001200000033002b00291a1a000100001d002000",
+        "00000000000000000000000000000000000000000000000000000000000000002d00020101002b00",
+        "07062a2a03040303001b00030200024469000500030268323a3a0001000015001400000000000000",
+        "00000000000000000000000000",
+    );
+
+    fn hello(fixture: &str) -> ClientHello {
+        let tls = Tls::new(&hex::decode(fixture).unwrap()).unwrap();
+        assert!(tls.complete);
+        tls.client_hello.unwrap()
+    }
+
+    #[test]
+    fn ja3_matches_the_published_example() {
+        let hello = hello(JA3_EXAMPLE);
+        assert_eq!(
+            hello.ja3_full(),
+            "769,47-53-5-10-49161-49162-49171-49172-50-56-19-4,0-10-11,23-24-25,0"
+        );
+        assert_eq!(hello.ja3(), "ada70206e40642a3e4461f35503241d5");
+    }
+
+    #[test]
+    fn ja4_matches_chrome() {
+        let hello = hello(CHROME);
+        assert_eq!(hello.server_name.as_deref(), Some("example.com"));
+        assert_eq!(hello.alpn, vec!["h2", "http/1.1"]);
+        assert_eq!(hello.max_version(), 0x0304);
+        assert_eq!(hello.ja4(), "t13d1516h2_8daaf6152771_e5627efa2ab1");
+        // GREASE never reaches JA3 either
+        assert!(hello.ja3_full().starts_with("771,4865-4866-4867-49195-"));
+        assert!(!hello.ja3_full().contains("2570"));
+    }
+
+    #[test]
+    fn waits_for_a_hello_split_over_segments() {
+        let data = hex::decode(CHROME).unwrap();
+        let tls = Tls::new(&data[..100]).unwrap();
+        assert!(!tls.complete && tls.client_hello.is_none());
+        assert_eq!(Tls::handshake_len(&data[..100]), Some(data.len()));
+    }
+}
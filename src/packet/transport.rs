@@ -1,67 +1,301 @@
+use super::application::Application;
+use super::classify::{self, Content};
+use super::error::{require, ParseError};
 use super::protocol::*;
+use byteorder::{BigEndian, ByteOrder};
 use std::fmt;
 
+#[derive(Clone, Copy, PartialEq)]
+pub struct TcpFlags(pub u8);
+
+impl TcpFlags {
+    pub const FIN: u8 = 0x01;
+    pub const SYN: u8 = 0x02;
+    pub const RST: u8 = 0x04;
+    pub const PSH: u8 = 0x08;
+    pub const ACK: u8 = 0x10;
+    pub const URG: u8 = 0x20;
+    pub const ECE: u8 = 0x40;
+    pub const CWR: u8 = 0x80;
+
+    pub fn has(&self, flag: u8) -> bool {
+        self.0 & flag != 0
+    }
+}
+
+impl fmt::Display for TcpFlags {
+    /// tcpdump's notation, e.g. `S.` for SYN/ACK
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 == 0 {
+            return write!(f, "none");
+        }
+        let names = [
+            (Self::FIN, 'F'),
+            (Self::SYN, 'S'),
+            (Self::RST, 'R'),
+            (Self::PSH, 'P'),
+            (Self::ACK, '.'),
+            (Self::URG, 'U'),
+            (Self::ECE, 'E'),
+            (Self::CWR, 'W'),
+        ];
+        for (flag, name) in names {
+            if self.has(flag) {
+                write!(f, "{}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub enum TcpOption {
+    Mss(u16),
+    WindowScale(u8),
+    SackPermitted,
+    Sack(Vec<(u32, u32)>),
+    Timestamps { value: u32, echo: u32 },
+    Unknown { kind: u8, data: Vec<u8> },
+}
+
+impl fmt::Display for TcpOption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TcpOption::Mss(mss) => write!(f, "mss {}", mss),
+            TcpOption::WindowScale(shift) => write!(f, "wscale {}", shift),
+            TcpOption::SackPermitted => write!(f, "sackOK"),
+            TcpOption::Sack(blocks) => {
+                write!(f, "sack {}", blocks.len())?;
+                for (left, right) in blocks {
+                    write!(f, " {{{}:{}}}", left, right)?;
+                }
+                Ok(())
+            }
+            TcpOption::Timestamps { value, echo } => write!(f, "TS val {} ecr {}", value, echo),
+            TcpOption::Unknown { kind, data } => write!(f, "unknown-{} 0x{}", kind, hex::encode(data)),
+        }
+    }
+}
+
+pub struct TcpHeader {
+    pub seq: u32,
+    pub ack: u32,
+    /// Header length in 32-bit words
+    pub data_offset: u8,
+    pub flags: TcpFlags,
+    pub window: u16,
+    pub checksum: u16,
+    pub urgent: u16,
+    pub options: Vec<TcpOption>,
+}
+
+impl TcpHeader {
+    fn new(data: &[u8]) -> Result<TcpHeader, ParseError> {
+        require("TCP", data, 20)?;
+        let data_offset = data[12] >> 4;
+        if data_offset < 5 {
+            return Err(ParseError::BadLength {
+                layer: "TCP",
+                length: data_offset as usize * 4,
+            });
+        }
+        let header_len = data_offset as usize * 4;
+        require("TCP", data, header_len)?;
+        Ok(TcpHeader {
+            seq: BigEndian::read_u32(&data[4..8]),
+            ack: BigEndian::read_u32(&data[8..12]),
+            data_offset,
+            flags: TcpFlags(data[13]),
+            window: BigEndian::read_u16(&data[14..16]),
+            checksum: BigEndian::read_u16(&data[16..18]),
+            urgent: BigEndian::read_u16(&data[18..20]),
+            options: Self::parse_options(&data[20..header_len]),
+        })
+    }
+
+    fn header_len(&self) -> usize {
+        self.data_offset as usize * 4
+    }
+
+    fn parse_options(mut data: &[u8]) -> Vec<TcpOption> {
+        let mut options = Vec::new();
+        while let Some(&kind) = data.first() {
+            match kind {
+                // End of option list
+                0 => break,
+                // No-operation padding
+                1 => {
+                    data = &data[1..];
+                    continue;
+                }
+                _ => {}
+            }
+            let len = match data.get(1) {
+                Some(&len) if len >= 2 && len as usize <= data.len() => len as usize,
+                _ => break,
+            };
+            let value = &data[2..len];
+            options.push(match (kind, value.len()) {
+                (2, 2) => TcpOption::Mss(BigEndian::read_u16(value)),
+                (3, 1) => TcpOption::WindowScale(value[0]),
+                (4, 0) => TcpOption::SackPermitted,
+                (5, n) if n % 8 == 0 => TcpOption::Sack(
+                    value
+                        .chunks(8)
+                        .map(|b| (BigEndian::read_u32(&b[0..4]), BigEndian::read_u32(&b[4..8])))
+                        .collect(),
+                ),
+                (8, 8) => TcpOption::Timestamps {
+                    value: BigEndian::read_u32(&value[0..4]),
+                    echo: BigEndian::read_u32(&value[4..8]),
+                },
+                _ => TcpOption::Unknown {
+                    kind,
+                    data: value.to_vec(),
+                },
+            });
+            data = &data[len..];
+        }
+        options
+    }
+}
+
 pub struct Transport<'a> {
     pub src_port: u16,
     pub dst_port: u16,
     pub protocol: &'a Layer4,
+    pub tcp: Option<TcpHeader>,
     pub payload: &'a [u8],
+    pub application: Option<Application>,
+    /// What the payload looks like it carries, from the start of its stream when known
+    pub content: Option<Content>,
 }
 
 impl<'a> Transport<'a> {
-    pub fn new(data: &'a [u8], protocol: &'a Layer4) -> Option<Transport<'a>> {
+    /// `Ok(None)` for anything that is not TCP or UDP
+    pub fn new(data: &'a [u8], protocol: &'a Layer4) -> Result<Option<Transport<'a>>, ParseError> {
         match protocol {
-            Layer4::Tcp => Some(Transport {
-                src_port: ((data[0] as u16) << 8) | data[1] as u16,
-                dst_port: ((data[2] as u16) << 8) | data[3] as u16,
-                protocol,
-                payload: &data[20..],
-            }),
-            Layer4::Udp => Some(Transport {
-                src_port: ((data[0] as u16) << 8) | data[1] as u16,
-                dst_port: ((data[2] as u16) << 8) | data[3] as u16,
-                protocol,
-                payload: &data[8..],
-            }),
-            _ => None,
+            Layer4::Tcp => {
+                let tcp = TcpHeader::new(data)?;
+                Ok(Some(Transport::with_application(Transport {
+                    src_port: BigEndian::read_u16(&data[0..2]),
+                    dst_port: BigEndian::read_u16(&data[2..4]),
+                    protocol,
+                    payload: &data[tcp.header_len()..],
+                    tcp: Some(tcp),
+                    application: None,
+                    content: None,
+                })))
+            }
+            Layer4::Udp => {
+                require("UDP", data, 8)?;
+                let length = BigEndian::read_u16(&data[4..6]) as usize;
+                if length < 8 {
+                    return Err(ParseError::BadLength {
+                        layer: "UDP",
+                        length,
+                    });
+                }
+                Ok(Some(Transport::with_application(Transport {
+                    src_port: BigEndian::read_u16(&data[0..2]),
+                    dst_port: BigEndian::read_u16(&data[2..4]),
+                    protocol,
+                    tcp: None,
+                    // Anything past the UDP length is padding from the layer below
+                    payload: &data[8..length.min(data.len())],
+                    application: None,
+                    content: None,
+                })))
+            }
+            _ => Ok(None),
         }
     }
 
-    pub fn get_tag(&self) -> String {
+    fn with_application(mut transport: Transport<'a>) -> Transport<'a> {
+        transport.application = Application::new(
+            transport.protocol,
+            transport.src_port,
+            transport.dst_port,
+            transport.payload,
+        );
+        // Only the first bytes of a TCP stream say what it carries, a segment
+        // matching nothing is left for the stream tracker to judge
+        transport.content = classify::identify(transport.protocol, transport.payload)
+            .filter(|c| *c != Content::Unknown || *transport.protocol == Layer4::Udp);
+        transport
+    }
+
+    /// The label of the well-known port on either side, or the bare protocol
+    pub fn port_tag(&self) -> String {
         match self.src_port {
             20..=21 => String::from("FTP"),
             22 => String::from("SSH"),
+            23 => String::from("TELNET"),
             25 => String::from("SMTP"),
             53 => String::from("DNS"),
             67 => String::from("DHCP"),
             68 => String::from("DHCP"),
             80 => String::from("HTTP"),
             110 => String::from("POP3"),
+            139 => String::from("SMB"),
             143 => String::from("IMAP"),
             443 => String::from("HTTPS"),
+            445 => String::from("SMB"),
             5353 => String::from("MDNS"),
             _ => match self.dst_port {
                 20..=21 => String::from("FTP"),
                 22 => String::from("SSH"),
+                23 => String::from("TELNET"),
                 25 => String::from("SMTP"),
                 53 => String::from("DNS"),
                 67 => String::from("DHCP"),
                 68 => String::from("DHCP"),
                 80 => String::from("HTTP"),
                 110 => String::from("POP3"),
+                139 => String::from("SMB"),
                 143 => String::from("IMAP"),
                 443 => String::from("HTTPS"),
+                445 => String::from("SMB"),
                 5353 => String::from("MDNS"),
                 _ => format!("{}", self.protocol),
             },
         }
     }
 
+    /// The port label when the content disagrees with it
+    pub fn port_mismatch(&self) -> Option<String> {
+        let content = self.content?;
+        let port = self.port_tag();
+        let mismatch = match classify::expected(self.protocol, &port) {
+            Some(expected) => expected != content,
+            None => content != Content::Unknown && port != self.protocol.to_string(),
+        };
+        match mismatch {
+            true => Some(port),
+            false => None,
+        }
+    }
+
+    /// The content label when the payload was recognised, the port label otherwise
+    pub fn get_tag(&self) -> String {
+        let port = self.port_tag();
+        let unlabelled = port == self.protocol.to_string();
+        match (self.content, self.port_mismatch()) {
+            (Some(Content::Unknown), Some(_)) => self.protocol.to_string(),
+            (Some(content), Some(_)) => content.to_string(),
+            // Recognised content on an unlabelled port, e.g. HTTP on 8080
+            (Some(content), None) if unlabelled && content != Content::Unknown => {
+                content.to_string()
+            }
+            _ => port,
+        }
+    }
+
     pub fn get_color(&self) -> term::color::Color {
         let tag = &self.get_tag()[..];
         match tag {
             "FTP" => term::color::WHITE,
             "SSH" => term::color::WHITE,
+            "TELNET" => term::color::WHITE,
             "SMTP" => term::color::WHITE,
             "DNS" => term::color::CYAN,
             "DHCP" => term::color::WHITE,
@@ -69,6 +303,8 @@ impl<'a> Transport<'a> {
             "POP3" => term::color::WHITE,
             "IMAP" => term::color::WHITE,
             "HTTPS" => term::color::GREEN,
+            "TLS" => term::color::GREEN,
+            "SMB" => term::color::WHITE,
             "MDNS" => term::color::CYAN,
             _ => match &self.protocol {
                 Layer4::Tcp => term::color::BRIGHT_CYAN,
@@ -83,8 +319,10 @@ impl std::fmt::Display for Transport<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let tag = &self.get_tag()[..];
         let out = match tag {
+            _ if self.application.is_some() => self.application.as_ref().unwrap().to_string(),
             "FTP" => String::from("FTP Data"),
             "SSH" => String::from("SSH Data"),
+            "TELNET" => String::from("Telnet Data"),
             "SMTP" => String::from("SMTP Data"),
             "DNS" => String::from("DNS Data"),
             "DHCP" => String::from("DHCP Data"),
@@ -93,12 +331,132 @@ impl std::fmt::Display for Transport<'_> {
             "IMAP" => String::from("IMAP Data"),
             "HTTPS" => String::from("HTTPS Data"),
             "MDNS" => String::from("MDNS Data"),
+            "SMB" => String::from("SMB Data"),
+            "TLS" => String::from("TLS Data"),
             _ => String::new(),
         };
+        let out = match (out.len(), self.port_mismatch()) {
+            (_, None) => out,
+            (0, Some(port)) => format!("Unidentified data on {} port", port),
+            (_, Some(port)) => format!("{} on {} port", out, port),
+        };
         let port_dir = format!("{} -> {}", self.src_port, self.dst_port);
         match out.len() {
             0 => write!(f, "{}", port_dir),
             _ => write!(f, "{} ({})", out, port_dir),
+        }?;
+        if let Some(tcp) = &self.tcp {
+            write!(f, " [{}] seq {}", tcp.flags, tcp.seq)?;
+            if tcp.flags.has(TcpFlags::ACK) {
+                write!(f, " ack {}", tcp.ack)?;
+            }
+            write!(f, " win {}", tcp.window)?;
+            if tcp.flags.has(TcpFlags::URG) {
+                write!(f, " urg {}", tcp.urgent)?;
+            }
+            if !tcp.options.is_empty() {
+                let options: Vec<String> = tcp.options.iter().map(|o| o.to_string()).collect();
+                write!(f, " options [{}]", options.join(","))?;
+            }
+            write!(f, " len {}", self.payload.len())?;
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A TCP header from port 40000 to 9999 with seq 1 and window 65535,
+    /// padded with end of options up to a whole number of words
+    fn tcp(flags: u8, options: &str) -> Vec<u8> {
+        let mut options = hex::decode(options).unwrap();
+        options.resize(options.len().div_ceil(4) * 4, 0);
+        let mut data = hex::decode("9c40270f00000001000000000000ffff00000000").unwrap();
+        data[12] = (5 + options.len() as u8 / 4) << 4;
+        data[13] = flags;
+        data.extend(options);
+        data
+    }
+
+    fn options(options: &str) -> Vec<String> {
+        let header = TcpHeader::new(&tcp(TcpFlags::SYN, options)).unwrap();
+        header.options.iter().map(|o| o.to_string()).collect()
+    }
+
+    #[test]
+    fn reads_the_options_of_a_syn() {
+        assert_eq!(
+            options(
+                "020405b4 0402 080a0000000a00000000 01 030307"
+                    .replace(' ', "")
+                    .as_str()
+            ),
+            vec!["mss 1460", "sackOK", "TS val 10 ecr 0", "wscale 7"]
+        );
+        assert_eq!(
+            options("0101050a00000064000000c8"),
+            vec!["sack 1 {100:200}"]
+        );
+        assert_eq!(options("1e04abcd"), vec!["unknown-30 0xabcd"]);
+    }
+
+    #[test]
+    fn stops_at_a_truncated_option() {
+        // Timestamps claiming 10 bytes with 6 left
+        assert_eq!(options("020405b4080a00000001"), vec!["mss 1460"]);
+        // A length byte cut off by the end of the header
+        assert_eq!(options("01010102"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn stops_at_a_zero_length_option() {
+        // Walking it would never move on
+        assert_eq!(options("020405b40300020405b4"), vec!["mss 1460"]);
+        assert_eq!(options("0301"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn rejects_a_bad_data_offset() {
+        let mut data = tcp(TcpFlags::SYN, "");
+        data[12] = 4 << 4;
+        assert_eq!(
+            TcpHeader::new(&data).err(),
+            Some(ParseError::BadLength {
+                layer: "TCP",
+                length: 16
+            })
+        );
+        data[12] = 6 << 4;
+        assert_eq!(
+            TcpHeader::new(&data).err(),
+            Some(ParseError::Truncated {
+                layer: "TCP",
+                needed: 24,
+                available: 20
+            })
+        );
+    }
+
+    #[test]
+    fn shows_flags_like_tcpdump() {
+        assert_eq!(TcpFlags(0).to_string(), "none");
+        assert_eq!(TcpFlags(TcpFlags::SYN | TcpFlags::ACK).to_string(), "S.");
+        assert_eq!(TcpFlags(TcpFlags::PSH | TcpFlags::ACK).to_string(), "P.");
+        assert_eq!(TcpFlags(0xff).to_string(), "FSRP.UEW");
+
+        let data = tcp(TcpFlags::SYN, "020405b40402");
+        let transport = Transport::new(&data, &Layer4::Tcp).unwrap().unwrap();
+        assert_eq!(
+            transport.to_string(),
+            "40000 -> 9999 [S] seq 1 win 65535 options [mss 1460,sackOK] len 0"
+        );
+        let data = tcp(TcpFlags::URG | TcpFlags::ACK | TcpFlags::FIN, "");
+        let transport = Transport::new(&data, &Layer4::Tcp).unwrap().unwrap();
+        assert_eq!(
+            transport.to_string(),
+            "40000 -> 9999 [F.U] seq 1 ack 0 win 65535 urg 0 len 0"
+        );
     }
 }
@@ -0,0 +1,368 @@
+use crate::packet::ip::IP;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// Datagrams still missing fragments after this many seconds are given up on
+const TIMEOUT: f64 = 30.0;
+
+/// Datagrams held at once, the oldest is given up on to make room
+const MAX_PENDING: usize = 4096;
+
+/// Fragment bytes held across all pending datagrams
+const MAX_BUFFERED: usize = 16 * 1024 * 1024;
+
+/// Largest datagram IPv4 and (non-jumbo) IPv6 can describe
+const MAX_DATAGRAM: usize = 65535;
+
+/// Fragments kept of one datagram, a 1500 byte MTU splits the largest into 45
+const MAX_FRAGMENTS: usize = 64;
+
+/// RFC 791 only guarantees a 68-byte MTU, leaving 48 bytes after the largest
+/// IPv4 header, so no honest sender needs to split a datagram any finer
+const MIN_FRAGMENT: usize = 48;
+
+/// Tag of rows for fragments still waiting on the rest of their datagram
+pub const FRAGMENT_TAG: &str = "FRAG";
+
+type Key = (IpAddr, IpAddr, u32, u8);
+
+struct Pending {
+    first_seen: f64,
+    /// Offset and data of every fragment kept, in arrival order
+    fragments: Vec<(usize, Vec<u8>)>,
+    /// Known once the fragment without the more-fragments flag arrives
+    total: Option<usize>,
+    buffered: usize,
+}
+
+impl Pending {
+    /// The whole datagram, if every byte up to the end has arrived
+    fn assemble(&self) -> Option<Vec<u8>> {
+        let total = self.total?;
+        let mut ranges: Vec<(usize, usize)> = self
+            .fragments
+            .iter()
+            .map(|(offset, data)| (*offset, offset + data.len()))
+            .collect();
+        ranges.sort_unstable();
+        let mut covered = 0;
+        for (start, end) in ranges {
+            if start > covered {
+                return None;
+            }
+            covered = covered.max(end);
+        }
+        if covered < total {
+            return None;
+        }
+        // Later fragments are written first so the earliest copy of overlapping bytes wins
+        let mut datagram = vec![0; total];
+        for (offset, data) in self.fragments.iter().rev() {
+            let end = (offset + data.len()).min(total);
+            if *offset < end {
+                datagram[*offset..end].copy_from_slice(&data[..end - offset]);
+            }
+        }
+        Some(datagram)
+    }
+}
+
+pub enum Datagram {
+    /// The packet was never fragmented
+    Whole,
+    /// Fragments of the datagram are still missing
+    Pending,
+    /// This fragment completed the datagram, here is its payload
+    Reassembled(Vec<u8>),
+}
+
+/// What became of a packet handed to [`Fragments::add`], and whether it looked like evasion
+pub struct Outcome {
+    pub datagram: Datagram,
+    /// It covers bytes an earlier fragment already carried (teardrop and friends)
+    pub overlap: bool,
+    /// It is smaller than any fragment a real link needs (tiny fragment attacks)
+    pub tiny: bool,
+    /// It reaches past the largest possible datagram (ping of death)
+    pub oversized: bool,
+    /// Its datagram already had as many fragments as are kept, so the whole
+    /// datagram was dropped (fragment floods)
+    pub too_many: bool,
+}
+
+/// A datagram given up on before all of its fragments arrived
+pub struct Incomplete {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub id: u32,
+    pub protocol: u8,
+    pub received: usize,
+    pub total: Option<usize>,
+}
+
+/// IPv4 and IPv6 fragment reassembly, keyed on (src, dst, id, protocol)
+#[derive(Default)]
+pub struct Fragments {
+    pending: HashMap<Key, Pending>,
+    buffered: usize,
+    given_up: Vec<Incomplete>,
+}
+
+impl Fragments {
+    pub fn add(&mut self, ip: &IP, time: f64) -> Outcome {
+        let mut outcome = Outcome {
+            datagram: Datagram::Whole,
+            overlap: false,
+            tiny: false,
+            oversized: false,
+            too_many: false,
+        };
+        let fragment = match ip.fragment() {
+            Some(fragment) => fragment,
+            None => return outcome,
+        };
+        outcome.datagram = Datagram::Pending;
+        let end = fragment.offset + ip.payload.len();
+        outcome.tiny = fragment.more && ip.payload.len() < MIN_FRAGMENT;
+        // The length field also counts the IPv4 header, or the IPv6 extension
+        // headers ahead of the fragment header
+        let header = match (&ip.ipv4, &ip.ipv6) {
+            (Some(header), _) => header.header_len(),
+            (None, Some(header)) => {
+                (header.payload_length as usize).saturating_sub(ip.payload.len())
+            }
+            (None, None) => 0,
+        };
+        if header + end > MAX_DATAGRAM {
+            // Never buffered, there is no valid datagram to rebuild
+            outcome.oversized = true;
+            return outcome;
+        }
+
+        let key = (ip.src, ip.dst, fragment.id, ip.protocol.number().unwrap_or(0));
+        if !self.pending.contains_key(&key) {
+            while self.pending.len() >= MAX_PENDING {
+                self.give_up_oldest();
+            }
+        }
+        let pending = self.pending.entry(key).or_insert(Pending {
+            first_seen: time,
+            fragments: Vec::new(),
+            total: None,
+            buffered: 0,
+        });
+        if pending.fragments.len() >= MAX_FRAGMENTS {
+            // Every fragment is checked against all the others, so rather than
+            // let one datagram grow without bound it is thrown away
+            self.buffered -= pending.buffered;
+            self.pending.remove(&key);
+            outcome.too_many = true;
+            return outcome;
+        }
+        outcome.overlap = pending
+            .fragments
+            .iter()
+            .any(|(offset, data)| fragment.offset < offset + data.len() && *offset < end);
+        if !fragment.more {
+            // Two different last fragments are as much an overlap as shared bytes
+            outcome.overlap |= pending.total.is_some_and(|total| total != end);
+            pending.total.get_or_insert(end);
+        }
+        pending.fragments.push((fragment.offset, ip.payload.to_vec()));
+        pending.buffered += ip.payload.len();
+        self.buffered += ip.payload.len();
+
+        // Nothing is complete before the last fragment and as many bytes as it implies are in
+        if pending.total.is_some_and(|total| pending.buffered >= total) {
+            if let Some(datagram) = pending.assemble() {
+                self.buffered -= pending.buffered;
+                self.pending.remove(&key);
+                outcome.datagram = Datagram::Reassembled(datagram);
+            }
+        }
+        while self.buffered > MAX_BUFFERED {
+            self.give_up_oldest();
+        }
+        outcome
+    }
+
+    /// Give up on datagrams older than the timeout, returning them along with
+    /// any dropped earlier to stay within the memory limits
+    pub fn expire(&mut self, now: f64) -> Vec<Incomplete> {
+        let expired: Vec<Key> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| now - p.first_seen > TIMEOUT)
+            .map(|(k, _)| *k)
+            .collect();
+        for key in expired {
+            self.give_up(key);
+        }
+        std::mem::take(&mut self.given_up)
+    }
+
+    /// Give up on everything still pending, for the end of a capture
+    pub fn flush(&mut self) -> Vec<Incomplete> {
+        let keys: Vec<Key> = self.pending.keys().copied().collect();
+        for key in keys {
+            self.give_up(key);
+        }
+        std::mem::take(&mut self.given_up)
+    }
+
+    fn give_up_oldest(&mut self) {
+        let oldest = self
+            .pending
+            .iter()
+            .min_by(|a, b| a.1.first_seen.total_cmp(&b.1.first_seen))
+            .map(|(k, _)| *k);
+        if let Some(key) = oldest {
+            self.give_up(key);
+        }
+    }
+
+    fn give_up(&mut self, key: Key) {
+        if let Some(pending) = self.pending.remove(&key) {
+            self.buffered -= pending.buffered;
+            let (src, dst, id, protocol) = key;
+            self.given_up.push(Incomplete {
+                src,
+                dst,
+                id,
+                protocol,
+                received: pending.buffered,
+                total: pending.total,
+            });
+        }
+    }
+}
+
+impl std::fmt::Display for Incomplete {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Incomplete datagram id 0x{:x}, {} bytes", self.id, self.received)?;
+        match self.total {
+            Some(total) => write!(f, " of {} received", total),
+            None => write!(f, " received, last fragment missing"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::protocol::{Layer3, Layer4};
+
+    /// A UDP fragment from 10.0.0.1 to 10.0.0.2 at `offset` bytes into datagram 0x1234
+    fn ipv4(offset: usize, more: bool, payload: &[u8]) -> Vec<u8> {
+        let mut data = hex::decode("450000000000000040110000").unwrap();
+        data[2..4].copy_from_slice(&(20 + payload.len() as u16).to_be_bytes());
+        data[4..6].copy_from_slice(&0x1234u16.to_be_bytes());
+        let flags = (offset / 8) as u16 | if more { 0x2000 } else { 0 };
+        data[6..8].copy_from_slice(&flags.to_be_bytes());
+        data.extend([10, 0, 0, 1, 10, 0, 0, 2]);
+        data.extend(payload);
+        data
+    }
+
+    /// An IPv6 fragment of datagram 0x1234 whose fragmentable part starts
+    /// with destination options
+    fn ipv6(offset: usize, more: bool, payload: &[u8]) -> Vec<u8> {
+        let mut data = hex::decode("6000000000002c40").unwrap();
+        data[4..6].copy_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        data.extend(hex::decode("20010db8000000000000000000000001").unwrap());
+        data.extend(hex::decode("20010db8000000000000000000000002").unwrap());
+        let flags = offset as u16 | more as u16;
+        data.extend([60, 0]);
+        data.extend(flags.to_be_bytes());
+        data.extend(0x1234u32.to_be_bytes());
+        data.extend(payload);
+        data
+    }
+
+    fn add(fragments: &mut Fragments, data: &[u8], protocol: Layer3, time: f64) -> Outcome {
+        fragments.add(&IP::new(data, protocol).unwrap(), time)
+    }
+
+    #[test]
+    fn reassembles_out_of_order_ipv4() {
+        let payload: Vec<u8> = (0..120).collect();
+        let mut fragments = Fragments::default();
+        let last = add(&mut fragments, &ipv4(64, false, &payload[64..]), Layer3::IPv4, 0.0);
+        assert!(matches!(last.datagram, Datagram::Pending));
+        let first = add(&mut fragments, &ipv4(0, true, &payload[..64]), Layer3::IPv4, 0.1);
+        assert!(!first.overlap && !first.tiny && !first.oversized);
+        match first.datagram {
+            Datagram::Reassembled(data) => assert_eq!(data, payload),
+            _ => panic!("datagram not reassembled"),
+        }
+        assert!(fragments.flush().is_empty());
+    }
+
+    #[test]
+    fn reassembles_ipv6_past_destination_options() {
+        // Destination options, then a UDP header to port 53 and 48 bytes of data
+        let mut payload = hex::decode("1100000000000000d431003500380000").unwrap();
+        payload.extend([0x61; 48]);
+        let mut fragments = Fragments::default();
+        let data = ipv6(0, true, &payload[..56]);
+        let first = IP::new(&data, Layer3::IPv6).unwrap();
+        assert!(first.protocol == Layer4::from(60));
+        assert!(matches!(fragments.add(&first, 0.0).datagram, Datagram::Pending));
+        let data = ipv6(56, false, &payload[56..]);
+        let last = IP::new(&data, Layer3::IPv6).unwrap();
+        let datagram = match fragments.add(&last, 0.1).datagram {
+            Datagram::Reassembled(data) => data,
+            _ => panic!("datagram not reassembled"),
+        };
+        let (protocol, upper) = last.upper_layer(&datagram);
+        assert!(protocol == Layer4::Udp);
+        assert_eq!(upper, &payload[8..]);
+    }
+
+    #[test]
+    fn flags_overlap_tiny_and_oversized() {
+        let mut fragments = Fragments::default();
+        let payload = [0u8; 64];
+        add(&mut fragments, &ipv4(0, true, &payload), Layer3::IPv4, 0.0);
+        let overlap = add(&mut fragments, &ipv4(56, true, &payload), Layer3::IPv4, 0.0);
+        assert!(overlap.overlap);
+        let tiny = add(&mut fragments, &ipv4(120, true, &payload[..8]), Layer3::IPv4, 0.0);
+        assert!(tiny.tiny && !tiny.overlap);
+        let oversized = add(&mut fragments, &ipv4(65528, false, &payload[..16]), Layer3::IPv4, 0.0);
+        assert!(oversized.oversized);
+    }
+
+    #[test]
+    fn counts_the_header_toward_the_largest_datagram() {
+        let mut fragments = Fragments::default();
+        let payload = [0u8; 16];
+        let fits = add(&mut fragments, &ipv4(65496, false, &payload), Layer3::IPv4, 0.0);
+        assert!(!fits.oversized);
+        let over = add(&mut fragments, &ipv4(65504, false, &payload), Layer3::IPv4, 0.0);
+        assert!(over.oversized);
+    }
+
+    #[test]
+    fn drops_a_datagram_with_too_many_fragments() {
+        let mut fragments = Fragments::default();
+        for i in 0..MAX_FRAGMENTS {
+            let outcome = add(&mut fragments, &ipv4(i * 8, true, &[0; 8]), Layer3::IPv4, 0.0);
+            assert!(!outcome.too_many);
+        }
+        let dropped = add(&mut fragments, &ipv4(512, false, &[0; 8]), Layer3::IPv4, 0.0);
+        assert!(dropped.too_many && matches!(dropped.datagram, Datagram::Pending));
+        assert_eq!(fragments.buffered, 0);
+        assert!(fragments.flush().is_empty());
+    }
+
+    #[test]
+    fn gives_up_after_the_timeout() {
+        let mut fragments = Fragments::default();
+        add(&mut fragments, &ipv4(0, true, &[0; 64]), Layer3::IPv4, 0.0);
+        assert!(fragments.expire(TIMEOUT).is_empty());
+        let given_up = fragments.expire(TIMEOUT + 1.0);
+        assert_eq!(given_up.len(), 1);
+        assert_eq!((given_up[0].id, given_up[0].received, given_up[0].total), (0x1234, 64, None));
+        assert!(fragments.flush().is_empty());
+    }
+}
@@ -0,0 +1,96 @@
+use super::stream::{Direction, Segment};
+use crate::packet::http::Http;
+use std::collections::HashMap;
+
+/// Heads larger than this are decoded as far as they got
+const MAX_HEAD: usize = 16 * 1024;
+
+/// Partial heads held at once, all are dropped when there are more
+const MAX_PENDING: usize = 4096;
+
+/// Collects HTTP message heads split over several TCP segments
+#[derive(Default)]
+pub struct Heads {
+    pending: HashMap<(u64, Direction), Vec<u8>>,
+}
+
+impl Heads {
+    /// Feed the in-order bytes of a segment, returning a head once its blank line arrives
+    pub fn add(&mut self, segment: &Segment) -> Option<Http> {
+        let key = (segment.stream, segment.direction);
+        let head = match self.pending.get_mut(&key) {
+            Some(head) => head,
+            None if Http::detect(&segment.data) => {
+                if self.pending.len() >= MAX_PENDING {
+                    self.pending.clear();
+                }
+                self.pending.entry(key).or_default()
+            }
+            None => return None,
+        };
+        head.extend_from_slice(&segment.data);
+        let http = Http::new(head).ok();
+        match &http {
+            Some(http) if !http.complete && head.len() < MAX_HEAD => None,
+            _ => {
+                self.pending.remove(&key);
+                http
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(stream: u64, direction: Direction, data: &[u8]) -> Segment {
+        Segment {
+            stream,
+            direction,
+            data: data.to_vec(),
+            content: None,
+            opened: false,
+        }
+    }
+
+    #[test]
+    fn joins_a_head_split_over_segments() {
+        let mut heads = Heads::default();
+        assert!(heads
+            .add(&segment(1, Direction::ToServer, b"GET / HTTP/1.1\r\nHo"))
+            .is_none());
+        // The other direction and another stream are kept apart
+        assert!(heads
+            .add(&segment(1, Direction::ToClient, b"HTTP/1.1 200 OK\r\n"))
+            .is_none());
+        assert!(heads
+            .add(&segment(2, Direction::ToServer, b"st: other\r\n\r\n"))
+            .is_none());
+        let http = heads
+            .add(&segment(1, Direction::ToServer, b"st: example.com\r\n\r\n"))
+            .unwrap();
+        assert!(http.complete);
+        assert_eq!(http.header("Host"), Some("example.com"));
+        // The next request on the stream starts afresh
+        assert!(heads
+            .add(&segment(1, Direction::ToServer, b"\x16\x03\x01"))
+            .is_none());
+    }
+
+    #[test]
+    fn gives_up_on_a_head_that_never_ends() {
+        let mut heads = Heads::default();
+        assert!(heads
+            .add(&segment(1, Direction::ToServer, b"GET / HTTP/1.1\r\n"))
+            .is_none());
+        let header = [b"X-Filler: ".as_slice(), &[b'a'; 1024], b"\r\n"].concat();
+        let mut partial = None;
+        for _ in 0..MAX_HEAD / header.len() + 1 {
+            partial = partial.or(heads.add(&segment(1, Direction::ToServer, &header)));
+        }
+        let http = partial.unwrap();
+        assert!(!http.complete);
+        assert!(heads.pending.is_empty());
+    }
+}
@@ -0,0 +1,4 @@
+pub(crate) mod fragment;
+pub(crate) mod http;
+pub(crate) mod stream;
+pub(crate) mod tls;
@@ -0,0 +1,448 @@
+use crate::packet::classify::{self, Content};
+use crate::packet::ip::IP;
+use crate::packet::protocol::*;
+use crate::packet::transport::{TcpFlags, Transport};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::IpAddr;
+
+/// Connections silent for this many seconds are forgotten
+const IDLE_TIMEOUT: f64 = 300.0;
+
+/// Connections tracked at once, the longest idle quarter is dropped to make room
+const MAX_STREAMS: usize = 65536;
+
+/// Out-of-order bytes held per direction before the gap in front of them is
+/// given up on, as happens when the capture itself dropped a segment
+const MAX_OUT_OF_ORDER: usize = 1024 * 1024;
+
+/// Out-of-order bytes held across every stream, past this whichever stream
+/// takes a segment gives up on its gap
+const MAX_BUFFERED: usize = 64 * 1024 * 1024;
+
+/// Connection state as seen from the client, named as in RFC 793
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TcpState {
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+    Closed,
+}
+
+impl fmt::Display for TcpState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            TcpState::SynSent => "SYN_SENT",
+            TcpState::SynReceived => "SYN_RECEIVED",
+            TcpState::Established => "ESTABLISHED",
+            TcpState::FinWait1 => "FIN_WAIT_1",
+            TcpState::FinWait2 => "FIN_WAIT_2",
+            TcpState::CloseWait => "CLOSE_WAIT",
+            TcpState::Closing => "CLOSING",
+            TcpState::LastAck => "LAST_ACK",
+            TcpState::TimeWait => "TIME_WAIT",
+            TcpState::Closed => "CLOSED",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    ToServer,
+    ToClient,
+}
+
+/// One direction of a connection, putting its bytes back in sequence order
+#[derive(Default)]
+struct Half {
+    /// Sequence number of the next byte expected, once any segment was seen
+    next: Option<u32>,
+    /// Bytes put in order so far, `pending` is keyed on offsets in the whole
+    /// stream so the order holds past the 4 GiB the sequence numbers wrap at
+    offset: u64,
+    pending: BTreeMap<u64, Vec<u8>>,
+    buffered: usize,
+    /// Sequence number the FIN takes up
+    fin: Option<u32>,
+}
+
+impl Half {
+    fn syn(&mut self, seq: u32) {
+        // Retransmitted SYNs must not rewind a stream already under way
+        if self.next.is_none() {
+            self.next = Some(seq.wrapping_add(1));
+        }
+    }
+
+    /// Whether too much is waiting to hold out for the gap any longer
+    fn overflowing(&self, total: usize) -> bool {
+        self.buffered > MAX_OUT_OF_ORDER || total > MAX_BUFFERED
+    }
+
+    /// Take a segment, returning the bytes it puts in order; retransmitted
+    /// and overlapping bytes are only delivered the first time. `total` is
+    /// what every stream holds out of order together.
+    fn add(&mut self, seq: u32, payload: &[u8], total: &mut usize) -> Vec<u8> {
+        let next = *self.next.get_or_insert(seq);
+        let offset = seq.wrapping_sub(next) as i32;
+        if offset > 0 {
+            if !payload.is_empty() {
+                let kept = self.pending.entry(self.offset + offset as u64).or_default();
+                // A longer retransmission of a segment still waiting replaces it
+                if kept.len() < payload.len() {
+                    self.buffered += payload.len() - kept.len();
+                    *total += payload.len() - kept.len();
+                    *kept = payload.to_vec();
+                }
+            }
+            if !self.overflowing(*total) {
+                return Vec::new();
+            }
+        }
+        let mut data = Vec::new();
+        let skip = match offset < 0 {
+            true => offset.unsigned_abs() as usize,
+            false => 0,
+        };
+        if offset <= 0 && skip < payload.len() {
+            data.extend_from_slice(&payload[skip..]);
+        }
+        let mut position = self.offset + data.len() as u64;
+        while let Some((&key, _)) = self.pending.iter().next() {
+            // Jump the gap when too much is waiting behind it
+            if key > position && !self.overflowing(*total) {
+                break;
+            }
+            let segment = self.pending.remove(&key).unwrap();
+            self.buffered -= segment.len();
+            *total -= segment.len();
+            position = position.max(key);
+            let skip = (position - key) as usize;
+            if skip < segment.len() {
+                data.extend_from_slice(&segment[skip..]);
+                position += (segment.len() - skip) as u64;
+            }
+        }
+        let next = next.wrapping_add((position - self.offset) as u32);
+        self.offset = position;
+        self.next = Some(next);
+        if self.fin == Some(next) {
+            self.next = Some(next.wrapping_add(1));
+        }
+        data
+    }
+}
+
+/// A TCP connection, the client being whoever sent the first SYN
+pub struct Stream {
+    /// Index in order of first appearance, like Wireshark's `tcp.stream`
+    pub id: u64,
+    pub client: (IpAddr, u16),
+    pub server: (IpAddr, u16),
+    pub state: TcpState,
+    /// What the first bytes either way looked like, for streams seen from their SYN
+    pub content: Option<Content>,
+    from_start: bool,
+    last_seen: f64,
+    to_server: Half,
+    to_client: Half,
+}
+
+impl Stream {
+    fn update(&mut self, direction: Direction, flags: TcpFlags, ack: u32) {
+        if flags.has(TcpFlags::RST) {
+            self.state = TcpState::Closed;
+            return;
+        }
+        let receiver = match direction {
+            Direction::ToServer => &self.to_client,
+            Direction::ToClient => &self.to_server,
+        };
+        // Does this segment acknowledge the other side's FIN
+        let acks_fin = flags.has(TcpFlags::ACK)
+            && receiver.fin.is_some_and(|fin| ack == fin.wrapping_add(1));
+        let fin = flags.has(TcpFlags::FIN);
+        let from_client = direction == Direction::ToServer;
+        self.state = match (self.state, from_client) {
+            (TcpState::SynSent, false) if flags.has(TcpFlags::SYN) => TcpState::SynReceived,
+            (TcpState::SynReceived, true) if flags.has(TcpFlags::ACK) && !fin => {
+                TcpState::Established
+            }
+            (TcpState::SynReceived | TcpState::Established, true) if fin => TcpState::FinWait1,
+            (TcpState::SynReceived | TcpState::Established, false) if fin => TcpState::CloseWait,
+            (TcpState::FinWait1, false) if fin && acks_fin => TcpState::TimeWait,
+            (TcpState::FinWait1, false) if fin => TcpState::Closing,
+            (TcpState::FinWait1, false) if acks_fin => TcpState::FinWait2,
+            (TcpState::FinWait2, false) if fin => TcpState::TimeWait,
+            (TcpState::Closing, false) if acks_fin => TcpState::TimeWait,
+            (TcpState::CloseWait, true) if fin => TcpState::LastAck,
+            (TcpState::LastAck, false) if acks_fin => TcpState::Closed,
+            (state, _) => state,
+        };
+    }
+
+    /// No more data will flow either way
+    pub fn is_closed(&self) -> bool {
+        matches!(self.state, TcpState::TimeWait | TcpState::Closed)
+    }
+
+    fn buffered(&self) -> usize {
+        self.to_server.buffered + self.to_client.buffered
+    }
+}
+
+/// Bytes a segment put in order on its stream
+pub struct Segment {
+    pub stream: u64,
+    pub direction: Direction,
+    pub data: Vec<u8>,
+    pub content: Option<Content>,
+    /// Whether this segment is the first seen of its connection
+    pub opened: bool,
+}
+
+type Key = ((IpAddr, u16), (IpAddr, u16));
+
+/// Tracks every TCP connection seen and reassembles both byte streams
+#[derive(Default)]
+pub struct Streams {
+    streams: HashMap<Key, Stream>,
+    /// Where each stream is by its id
+    ids: HashMap<u64, Key>,
+    next_id: u64,
+    /// Out-of-order bytes held by every stream together
+    buffered: usize,
+    last_expired: f64,
+}
+
+impl Streams {
+    /// Feed a decoded TCP segment, `None` for anything that is not TCP
+    pub fn add(&mut self, ip: &IP, transport: &Transport, time: f64) -> Option<Segment> {
+        let tcp = transport.tcp.as_ref()?;
+        let src = (ip.src, transport.src_port);
+        let dst = (ip.dst, transport.dst_port);
+        let key = if src < dst { (src, dst) } else { (dst, src) };
+        let syn_only = tcp.flags.has(TcpFlags::SYN) && !tcp.flags.has(TcpFlags::ACK);
+
+        // A fresh SYN on a finished connection is a new connection reusing the ports
+        let reused = syn_only && self.streams.get(&key).is_some_and(|s| s.is_closed());
+        let opened = reused || !self.streams.contains_key(&key);
+        if opened {
+            // Looking through every stream once a second is plenty, unless they pile up sooner
+            if time - self.last_expired >= 1.0 || self.streams.len() >= MAX_STREAMS {
+                self.make_room(time);
+            }
+            let stream = self.open(src, dst, syn_only, time);
+            self.ids.insert(stream.id, key);
+            if let Some(old) = self.streams.insert(key, stream) {
+                self.ids.remove(&old.id);
+                self.buffered -= old.buffered();
+            }
+        }
+        let stream = self.streams.get_mut(&key).unwrap();
+        stream.last_seen = time;
+        let direction = match src == stream.client {
+            true => Direction::ToServer,
+            false => Direction::ToClient,
+        };
+        let half = match direction {
+            Direction::ToServer => &mut stream.to_server,
+            Direction::ToClient => &mut stream.to_client,
+        };
+        let mut seq = tcp.seq;
+        if tcp.flags.has(TcpFlags::SYN) {
+            half.syn(seq);
+            seq = seq.wrapping_add(1);
+        }
+        if tcp.flags.has(TcpFlags::FIN) {
+            half.fin = Some(seq.wrapping_add(transport.payload.len() as u32));
+        }
+        let data = half.add(seq, transport.payload, &mut self.buffered);
+        stream.update(direction, tcp.flags, tcp.ack);
+        if stream.content.is_none() && stream.from_start {
+            stream.content = classify::identify(&Layer4::Tcp, &data);
+        }
+        Some(Segment {
+            stream: stream.id,
+            direction,
+            data,
+            content: stream.content,
+            opened,
+        })
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Stream> {
+        self.streams.get(self.ids.get(&id)?)
+    }
+
+    fn open(&mut self, src: (IpAddr, u16), dst: (IpAddr, u16), syn: bool, time: f64) -> Stream {
+        // Picked up mid-connection, guess the lower port is the service
+        let (client, server) = match syn || src.1 > dst.1 {
+            true => (src, dst),
+            false => (dst, src),
+        };
+        let stream = Stream {
+            id: self.next_id,
+            client,
+            server,
+            state: match syn {
+                true => TcpState::SynSent,
+                false => TcpState::Established,
+            },
+            content: None,
+            from_start: syn,
+            last_seen: time,
+            to_server: Half::default(),
+            to_client: Half::default(),
+        };
+        self.next_id += 1;
+        stream
+    }
+
+    fn make_room(&mut self, now: f64) {
+        self.last_expired = now;
+        self.forget(|s| now - s.last_seen > IDLE_TIMEOUT);
+        if self.streams.len() >= MAX_STREAMS {
+            // Dropping a quarter at once keeps this from running for every new stream
+            let mut seen: Vec<f64> = self.streams.values().map(|s| s.last_seen).collect();
+            let cut = seen.len() - MAX_STREAMS * 3 / 4;
+            let (_, cutoff, _) = seen.select_nth_unstable_by(cut, f64::total_cmp);
+            let cutoff = *cutoff;
+            self.forget(|s| s.last_seen < cutoff);
+        }
+    }
+
+    /// Drop the streams `stale` picks, along with their ids and held bytes
+    fn forget(&mut self, stale: impl Fn(&Stream) -> bool) {
+        let ids = &mut self.ids;
+        let buffered = &mut self.buffered;
+        self.streams.retain(|_, s| {
+            if !stale(s) {
+                return true;
+            }
+            ids.remove(&s.id);
+            *buffered -= s.buffered();
+            false
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A TCP segment from 10.0.0.1:40000 to 10.0.0.2:80, or back when `reply`
+    fn packet(reply: bool, seq: u32, ack: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut data = hex::decode("4500000000014000400600000a0000010a000002").unwrap();
+        data[2..4].copy_from_slice(&(40 + payload.len() as u16).to_be_bytes());
+        let mut ports = [0x9c, 0x40, 0x00, 0x50];
+        if reply {
+            data[12..20].rotate_left(4);
+            ports.rotate_left(2);
+        }
+        data.extend(ports);
+        data.extend(seq.to_be_bytes());
+        data.extend(ack.to_be_bytes());
+        data.extend([0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        data.extend(payload);
+        data
+    }
+
+    fn feed(streams: &mut Streams, data: &[u8], time: f64) -> Segment {
+        let ip = IP::new(data, Layer3::IPv4).unwrap();
+        let transport = Transport::new(ip.payload, &ip.protocol).unwrap().unwrap();
+        streams.add(&ip, &transport, time).unwrap()
+    }
+
+    const SYN: u8 = TcpFlags::SYN;
+    const ACK: u8 = TcpFlags::ACK;
+    const FIN_ACK: u8 = TcpFlags::FIN | TcpFlags::ACK;
+
+    #[test]
+    fn puts_segments_back_in_order() {
+        let mut streams = Streams::default();
+        let syn = feed(&mut streams, &packet(false, 1000, 0, SYN, b""), 0.0);
+        assert!(syn.opened && syn.direction == Direction::ToServer);
+        feed(&mut streams, &packet(true, 5000, 1001, SYN | ACK, b""), 0.0);
+        feed(&mut streams, &packet(false, 1001, 5001, ACK, b""), 0.0);
+        assert_eq!(streams.get(0).unwrap().state, TcpState::Established);
+
+        // The second segment arrives first, then the first twice and a
+        // retransmission overlapping both
+        let early = feed(&mut streams, &packet(false, 1007, 5001, ACK, b"HTTP/1.1\r\n"), 0.1);
+        assert!(early.data.is_empty());
+        let first = feed(&mut streams, &packet(false, 1001, 5001, ACK, b"GET / "), 0.2);
+        assert_eq!(first.data, b"GET / HTTP/1.1\r\n");
+        let again = feed(&mut streams, &packet(false, 1001, 5001, ACK, b"GET / "), 0.3);
+        assert!(again.data.is_empty());
+        let more = feed(&mut streams, &packet(false, 1012, 5001, ACK, b"1.1\r\nHost"), 0.4);
+        assert_eq!(more.data, b"Host");
+        assert!(!more.opened && more.stream == 0);
+
+        let reply = feed(&mut streams, &packet(true, 5001, 1021, ACK, b"HTTP/1.1 200"), 0.5);
+        assert_eq!((reply.direction, &reply.data[..]), (Direction::ToClient, &b"HTTP/1.1 200"[..]));
+    }
+
+    #[test]
+    fn keeps_order_past_the_sequence_wrap() {
+        let mut streams = Streams::default();
+        let start = u32::MAX - 3;
+        feed(&mut streams, &packet(false, start - 1, 0, SYN, b""), 0.0);
+        let early = feed(&mut streams, &packet(false, 5, 0, ACK, b"wrap"), 0.1);
+        assert!(early.data.is_empty());
+        let first = feed(&mut streams, &packet(false, start, 0, ACK, b"over the "), 0.2);
+        assert_eq!(first.data, b"over the wrap");
+        let half = &streams.streams.values().next().unwrap().to_server;
+        assert_eq!((half.offset, half.next), (13, Some(9)));
+    }
+
+    #[test]
+    fn gives_up_a_gap_once_every_stream_together_holds_too_much() {
+        // The other streams already hold all but ten bytes
+        let mut total = MAX_BUFFERED - 10;
+        let mut half = Half::default();
+        assert!(half.add(0, b"", &mut total).is_empty());
+        assert!(half.add(100, b"0123456789", &mut total).is_empty());
+        assert_eq!(total, MAX_BUFFERED);
+        // One byte more and the stream taking it stops waiting on its gap
+        assert_eq!(half.add(50, b"y", &mut total), b"y");
+        assert_eq!(total, MAX_BUFFERED);
+        assert_eq!(half.add(51, &[b'z'; 49], &mut total).len(), 59);
+        assert_eq!(total, MAX_BUFFERED - 10);
+    }
+
+    #[test]
+    fn follows_the_close() {
+        let mut streams = Streams::default();
+        feed(&mut streams, &packet(false, 1000, 0, SYN, b""), 0.0);
+        feed(&mut streams, &packet(true, 5000, 1001, SYN | ACK, b""), 0.0);
+        feed(&mut streams, &packet(false, 1001, 5001, ACK, b""), 0.0);
+        feed(&mut streams, &packet(false, 1001, 5001, FIN_ACK, b""), 1.0);
+        assert_eq!(streams.get(0).unwrap().state, TcpState::FinWait1);
+        feed(&mut streams, &packet(true, 5001, 1002, FIN_ACK, b""), 1.0);
+        assert!(streams.get(0).unwrap().is_closed());
+
+        // The same ports again are a new connection
+        let syn = feed(&mut streams, &packet(false, 9000, 0, SYN, b""), 2.0);
+        assert!(syn.opened && syn.stream == 1);
+        assert!(streams.get(0).is_none() && streams.get(1).is_some());
+    }
+
+    #[test]
+    fn forgets_idle_streams() {
+        let mut streams = Streams::default();
+        feed(&mut streams, &packet(false, 1000, 0, SYN, b""), 0.0);
+        let mut other = packet(false, 1000, 0, SYN, b"");
+        other[20] = 0x9d;
+        feed(&mut streams, &other, IDLE_TIMEOUT + 1.0);
+        assert!(streams.get(0).is_none());
+        assert!(streams.get(1).is_some());
+    }
+}
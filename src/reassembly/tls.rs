@@ -0,0 +1,39 @@
+use super::stream::{Direction, Segment};
+use crate::packet::tls::Tls;
+use std::collections::HashMap;
+
+/// Handshake messages larger than this are given up on
+const MAX_HELLO: usize = 64 * 1024;
+
+/// Partial ClientHellos held at once, all are dropped when there are more
+const MAX_PENDING: usize = 4096;
+
+/// Collects ClientHellos split over several TCP segments, as large key shares make them
+#[derive(Default)]
+pub struct Hellos {
+    pending: HashMap<(u64, Direction), Vec<u8>>,
+}
+
+impl Hellos {
+    /// Feed the in-order bytes of a segment, returning the ClientHello once all of it arrived
+    pub fn add(&mut self, segment: &Segment) -> Option<Tls> {
+        let key = (segment.stream, segment.direction);
+        let hello = match self.pending.get_mut(&key) {
+            Some(hello) => hello,
+            None if Tls::new(&segment.data).is_ok_and(|t| !t.complete) => {
+                if self.pending.len() >= MAX_PENDING {
+                    self.pending.clear();
+                }
+                self.pending.entry(key).or_default()
+            }
+            None => return None,
+        };
+        hello.extend_from_slice(&segment.data);
+        let needed = Tls::handshake_len(hello).unwrap_or(0);
+        if hello.len() < needed && hello.len() < MAX_HELLO {
+            return None;
+        }
+        let hello = self.pending.remove(&key)?;
+        Tls::new(&hello).ok()
+    }
+}